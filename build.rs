@@ -12,7 +12,7 @@ use std::process::Command;
 use std::env;
 fn set_env_with_name(name: &str) {
     let ver = match Command::new("git")
-        .args(&["describe", "--tags", "--dirty=-modified"])
+        .args(["describe", "--tags", "--dirty=-modified"])
         .output()
     {
         Ok(cmd) => {
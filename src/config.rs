@@ -1,16 +1,53 @@
+extern crate runny;
+#[cfg(windows)]
+extern crate shlex;
+
 use std::cell::RefCell;
+use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use self::runny::running::Running;
+#[cfg(unix)]
+use self::runny::Runny;
+use self::runny::RunnyError;
+
+use unit::{UnitKind, UnitName};
 
 const DEFAULT_TIMEOUT_SECS: u64 = 5;
+const DEFAULT_PING_INTERVAL_SECS: u64 = 5;
+
+/// The environment variable that tells programs where the current run's directory is.
+pub const RUN_DIRECTORY_VAR: &str = "EXCLAVE_RUN_DIR";
+
+/// Variables naming the active jig and scenario, and the test being run.
+pub const JIG_VAR: &str = "EXCLAVE_JIG";
+pub const SCENARIO_VAR: &str = "EXCLAVE_SCENARIO";
+pub const TEST_VAR: &str = "EXCLAVE_TEST";
+
+/// The environment variable holding the directory the unit file was loaded from.
+pub const UNIT_DIRECTORY_VAR: &str = "EXCLAVE_UNIT_DIR";
+
+/// Variables set by tests may not start with this, so they can't replace ours.
+pub const RESERVED_VAR_PREFIX: &str = "EXCLAVE_";
+
+/// The variables set by a jig or scenario, along with its name.
+type EnvironmentLayer = RefCell<Option<(String, Vec<(String, String)>)>>;
 
 pub struct Config {
     timeout: Duration,
-    jig_working_directory: Rc<RefCell<Option<PathBuf>>>,
-    scenario_working_directory: Rc<RefCell<Option<PathBuf>>>,
+    jig_working_directory: RefCell<Option<PathBuf>>,
+    scenario_working_directory: RefCell<Option<PathBuf>>,
+    jig_environment: EnvironmentLayer,
+    scenario_environment: EnvironmentLayer,
+    run_variables: RefCell<Vec<(String, String)>>,
+    scenario_deadline: RefCell<Option<Instant>>,
     paths: Vec<PathBuf>,
     terminate_timeout: Duration,
+    ping_interval: Duration,
+    output_directory: Option<PathBuf>,
+    run_directory: RefCell<Option<PathBuf>>,
 }
 
 impl Config {
@@ -18,8 +55,15 @@ impl Config {
         Config {
             timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
             terminate_timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
-            jig_working_directory: Rc::new(RefCell::new(None)),
-            scenario_working_directory: Rc::new(RefCell::new(None)),
+            ping_interval: Duration::from_secs(DEFAULT_PING_INTERVAL_SECS),
+            jig_working_directory: RefCell::new(None),
+            scenario_working_directory: RefCell::new(None),
+            jig_environment: RefCell::new(None),
+            scenario_environment: RefCell::new(None),
+            run_variables: RefCell::new(vec![]),
+            scenario_deadline: RefCell::new(None),
+            output_directory: None,
+            run_directory: RefCell::new(None),
             paths: vec![
                 Path::new("/usr/local/sbin").to_owned(),
                 Path::new("/usr/local/bin").to_owned(),
@@ -38,6 +82,11 @@ impl Config {
         &self.terminate_timeout
     }
 
+    /// How often Interfaces, Loggers, and Triggers with Ping=yes are sent a PING.
+    pub fn ping_interval(&self) -> &Duration {
+        &self.ping_interval
+    }
+
     /// Return a working directory composed of the unit's directory,
     /// the jig working directory, and the scenario working directory.
     pub fn working_directory(&self, default: &Path, wd: &Option<PathBuf>) -> PathBuf {
@@ -82,4 +131,294 @@ impl Config {
     pub fn clear_scenario_working_directory(&self) {
         *self.scenario_working_directory.borrow_mut() = None;
     }
+
+    pub fn set_jig_environment(&self, jig: &UnitName, vars: Vec<(String, String)>) {
+        *self.jig_environment.borrow_mut() = Some((jig.id().to_owned(), vars));
+    }
+
+    pub fn clear_jig_environment(&self) {
+        *self.jig_environment.borrow_mut() = None;
+    }
+
+    pub fn set_scenario_environment(&self, scenario: &UnitName, vars: Vec<(String, String)>) {
+        *self.scenario_environment.borrow_mut() = Some((scenario.id().to_owned(), vars));
+    }
+
+    pub fn clear_scenario_environment(&self) {
+        *self.scenario_environment.borrow_mut() = None;
+    }
+
+    /// Remember a variable set by a test, to pass on to the tests after it.
+    pub fn set_run_variable(&self, key: &str, value: &str) {
+        let mut vars = self.run_variables.borrow_mut();
+        vars.retain(|(k, _)| k != key);
+        vars.push((key.to_owned(), value.to_owned()));
+    }
+
+    pub fn clear_run_variables(&self) {
+        self.run_variables.borrow_mut().clear();
+    }
+
+    /// When the current scenario runs out of time, if it has a Timeout.
+    pub fn set_scenario_deadline(&self, deadline: Option<Instant>) {
+        *self.scenario_deadline.borrow_mut() = deadline;
+    }
+
+    /// Limit a program's timeout to whatever time the current scenario has left.
+    pub fn limit_timeout(&self, timeout: Option<Duration>) -> Option<Duration> {
+        limit_timeout(timeout, *self.scenario_deadline.borrow())
+    }
+
+    /// The directory that each run's directory is created in.  Until one
+    /// is set, runs don't get a directory.
+    pub fn set_output_directory(&mut self, new_path: &Path) {
+        self.output_directory = Some(new_path.to_owned());
+    }
+
+    /// Create a fresh directory for a run of the named scenario, and make
+    /// it the current run directory.  Returns None if there is no output
+    /// directory to create it in.
+    pub fn create_run_directory(&self, scenario: &str) -> io::Result<Option<PathBuf>> {
+        let output_directory = match self.output_directory {
+            Some(ref d) => d,
+            None => {
+                self.clear_run_directory();
+                return Ok(None);
+            }
+        };
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        fs::create_dir_all(output_directory)?;
+
+        // Runs that start within the same second get a suffix.
+        let mut suffix = 0;
+        loop {
+            let name = match suffix {
+                0 => format!("{}-{}", scenario, secs),
+                n => format!("{}-{}-{}", scenario, secs, n),
+            };
+            let path = output_directory.join(name);
+            match fs::create_dir(&path) {
+                Ok(_) => {
+                    let path = path.canonicalize().unwrap_or(path);
+                    *self.run_directory.borrow_mut() = Some(path.clone());
+                    return Ok(Some(path));
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => suffix += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn run_directory(&self) -> Option<PathBuf> {
+        self.run_directory.borrow().clone()
+    }
+
+    /// Carry on using an existing run directory, such as when a run is resumed.
+    pub fn set_run_directory(&self, path: &Path) {
+        *self.run_directory.borrow_mut() = Some(path.to_owned());
+    }
+
+    pub fn clear_run_directory(&self) {
+        *self.run_directory.borrow_mut() = None;
+    }
+
+    /// Return the environment for a program started by the given unit,
+    /// composed of the jig's variables, the scenario's variables, any
+    /// variables set by earlier tests, and the unit's own, followed by the
+    /// variables Exclave always provides.
+    pub fn environment(
+        &self,
+        unit: &UnitName,
+        unit_directory: &Path,
+        unit_vars: &[(String, String)],
+    ) -> Vec<(String, String)> {
+        let mut vars = vec![];
+        let mut jig = None;
+        let mut scenario = None;
+        if let Some((ref name, ref layer)) = *self.jig_environment.borrow() {
+            vars.extend_from_slice(layer);
+            jig = Some(name.clone());
+        }
+        if let Some((ref name, ref layer)) = *self.scenario_environment.borrow() {
+            vars.extend_from_slice(layer);
+            scenario = Some(name.clone());
+        }
+        vars.extend_from_slice(&self.run_variables.borrow());
+        vars.extend_from_slice(unit_vars);
+
+        match *unit.kind() {
+            UnitKind::Jig => jig = Some(unit.id().to_owned()),
+            UnitKind::Scenario => scenario = Some(unit.id().to_owned()),
+            UnitKind::Test => vars.push((TEST_VAR.to_owned(), unit.id().to_owned())),
+            _ => (),
+        }
+        if let Some(jig) = jig {
+            vars.push((JIG_VAR.to_owned(), jig));
+        }
+        if let Some(scenario) = scenario {
+            vars.push((SCENARIO_VAR.to_owned(), scenario));
+        }
+        if let Some(ref run_directory) = *self.run_directory.borrow() {
+            vars.push((
+                RUN_DIRECTORY_VAR.to_owned(),
+                run_directory.to_string_lossy().into_owned(),
+            ));
+        }
+        vars.push((
+            UNIT_DIRECTORY_VAR.to_owned(),
+            unit_directory.to_string_lossy().into_owned(),
+        ));
+        vars
+    }
+}
+
+/// The shorter of `timeout` and the time left until `deadline`.
+pub fn limit_timeout(timeout: Option<Duration>, deadline: Option<Instant>) -> Option<Duration> {
+    let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+    match (timeout, remaining) {
+        (Some(timeout), Some(remaining)) => Some(timeout.min(remaining)),
+        (timeout, remaining) => timeout.or(remaining),
+    }
+}
+
+/// Build a command that runs with extra variables in its environment.
+pub fn command_with_environment(cmd: &str, vars: &[(String, String)]) -> UnitCommand {
+    UnitCommand {
+        cmd: cmd.to_owned(),
+        vars: vars.to_vec(),
+        working_directory: None,
+        timeout: None,
+        path: vec![],
+    }
+}
+
+/// A program to start, along with the variables to add to its environment.
+/// This takes the same settings as a Runny, which always passes on our own
+/// environment and has no way to add to it.
+pub struct UnitCommand {
+    cmd: String,
+    vars: Vec<(String, String)>,
+    working_directory: Option<PathBuf>,
+    timeout: Option<Duration>,
+    path: Vec<PathBuf>,
+}
+
+impl UnitCommand {
+    pub fn directory(&mut self, wd: &Option<PathBuf>) -> &mut UnitCommand {
+        self.working_directory = wd.clone();
+        self
+    }
+
+    pub fn path(&mut self, path: Vec<PathBuf>) -> &mut UnitCommand {
+        self.path = path;
+        self
+    }
+
+    pub fn timeout(&mut self, timeout: Duration) -> &mut UnitCommand {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Start the program on a pty.  Runny has no way of setting variables
+    /// on the child, so the program is run through env(1) with the
+    /// variables as arguments, which then runs the program itself.
+    #[cfg(unix)]
+    pub fn start(&self) -> Result<Running, RunnyError> {
+        let mut line = "env".to_owned();
+        for (key, value) in &self.vars {
+            line.push(' ');
+            line.push_str(&quote_argument(&format!("{}={}", key, value)));
+        }
+        line.push(' ');
+        line.push_str(&self.cmd);
+
+        let mut runny = Runny::new(&line);
+        runny
+            .directory(&self.working_directory)
+            .path(self.path.clone());
+        if let Some(timeout) = self.timeout {
+            runny.timeout(timeout);
+        }
+        runny.start()
+    }
+
+    /// Start the program with its variables set directly.  Runny uses plain
+    /// pipes on Windows, so this does the same, splitting the command line
+    /// the way Runny would.
+    #[cfg(windows)]
+    pub fn start(&self) -> Result<Running, RunnyError> {
+        use std::collections::HashMap;
+        use std::env;
+        use std::fs::File;
+        use std::os::windows::io::{FromRawHandle, IntoRawHandle};
+        use std::process::{Command, Stdio};
+
+        let mut args = shlex::split(&self.cmd.replace("\\", "\\\\")).unwrap_or_default();
+        if args.is_empty() {
+            return Err(RunnyError::NoCommandSpecified);
+        }
+        let mut cmd = Command::new(args.remove(0));
+        cmd.args(&args);
+        if !self.path.is_empty() {
+            if let Ok(path) = env::join_paths(&self.path) {
+                cmd.env("PATH", path);
+            }
+        }
+        for (key, value) in &self.vars {
+            cmd.env(key, value);
+        }
+        if let Some(ref wd) = self.working_directory {
+            cmd.current_dir(wd);
+        }
+        let mut child = cmd
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let stdin = unsafe { File::from_raw_handle(child.stdin.take().unwrap().into_raw_handle()) };
+        let stdout =
+            unsafe { File::from_raw_handle(child.stdout.take().unwrap().into_raw_handle()) };
+        let stderr =
+            unsafe { File::from_raw_handle(child.stderr.take().unwrap().into_raw_handle()) };
+        let mut handles = HashMap::new();
+        handles.insert("stderr".to_owned(), stderr);
+
+        Ok(Running::new(child, stdin, stdout, self.timeout, handles))
+    }
+}
+
+#[cfg(unix)]
+/// Quote a string so that Runny passes it on as a single, unchanged
+/// argument.  Runny doubles every backslash before splitting the command
+/// line, so backslashes are left outside of quotes to be unescaped again,
+/// and everything else goes in single quotes.
+fn quote_argument(arg: &str) -> String {
+    let mut quoted = String::new();
+    let mut in_quotes = false;
+    for c in arg.chars() {
+        let special = c == '\\' || c == '\'';
+        if special && in_quotes {
+            quoted.push('\'');
+            in_quotes = false;
+        } else if !special && !in_quotes {
+            quoted.push('\'');
+            in_quotes = true;
+        }
+        match c {
+            '\\' => quoted.push('\\'),
+            '\'' => quoted.push_str("\"'\""),
+            c => quoted.push(c),
+        }
+    }
+    if in_quotes {
+        quoted.push('\'');
+    }
+    if quoted.is_empty() {
+        quoted.push_str("''");
+    }
+    quoted
 }
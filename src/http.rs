@@ -0,0 +1,455 @@
+// A small built-in HTTP server.  It keeps a copy of the station's state by
+// watching the UnitBroadcaster, answers REST queries from that copy, and
+// injects commands as ManagerControlMessages.  Every status message and log
+// entry is also streamed to clients of /events as Server-Sent Events.
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::Value;
+
+use unit::UnitName;
+use unitbroadcaster::{UnitBroadcaster, UnitEvent};
+use unitmanager::{
+    FieldType, ManagerControlMessage, ManagerControlMessageContents, ManagerStatusMessage,
+    RunMetadata, RESUMED_MESSAGE,
+};
+
+/// The largest request body we're willing to read.  None of the endpoints
+/// take a body, so this only needs to leave room for well-meaning clients.
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// How long a client may take to send its request, or to accept data from
+/// us, before it is disconnected.
+const STREAM_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many events may be waiting for an /events client before it is dropped.
+const EVENT_QUEUE_DEPTH: usize = 256;
+
+/// What we know about the station, as seen from its status messages.
+#[derive(Default)]
+struct HttpState {
+    jig: Option<UnitName>,
+    scenarios: Vec<UnitName>,
+    scenario: Option<UnitName>,
+    tests: Vec<UnitName>,
+
+    /// Name and description of every unit that has been described to us.
+    names: HashMap<UnitName, String>,
+    descriptions: HashMap<UnitName, String>,
+
+    /// The state of the current (or most recent) run.
+    running: bool,
+    current_test: Option<UnitName>,
+    metadata: RunMetadata,
+    results: BTreeMap<String, Value>,
+    measurements: Vec<Value>,
+    finished: Option<Value>,
+
+    /// Totals for a repeating scenario, which last across its runs.
+    iteration: Option<Value>,
+}
+
+impl HttpState {
+    fn update(&mut self, msg: &ManagerStatusMessage) {
+        match msg {
+            ManagerStatusMessage::Jig(jig) => self.jig = jig.clone(),
+            ManagerStatusMessage::Scenarios(list) => self.scenarios = list.clone(),
+            ManagerStatusMessage::Scenario(scenario) => self.scenario = scenario.clone(),
+            ManagerStatusMessage::Tests(scenario, tests) => {
+                if self.scenario.as_ref() == Some(scenario) {
+                    self.tests = tests.clone();
+                }
+            }
+            ManagerStatusMessage::Describe(id, FieldType::Name, value) => {
+                self.names.insert(id.clone(), value.clone());
+            }
+            ManagerStatusMessage::Describe(id, FieldType::Description, value) => {
+                self.descriptions.insert(id.clone(), value.clone());
+            }
+            ManagerStatusMessage::Start(_, metadata) => {
+                self.running = true;
+                self.current_test = None;
+                self.metadata = metadata.clone();
+                self.results.clear();
+                self.measurements.clear();
+                // Keep the totals of a scenario that is still repeating.
+                if self.iteration.as_ref().is_some_and(|i| i["last"] == true) {
+                    self.iteration = None;
+                }
+                self.finished = None;
+            }
+            ManagerStatusMessage::Resumed(_, tests) => {
+                for test in tests {
+                    self.results.insert(
+                        test.id().clone(),
+                        json!({"result": "pass", "message": RESUMED_MESSAGE}),
+                    );
+                }
+            }
+            ManagerStatusMessage::Running(test) => {
+                self.current_test = Some(test.clone());
+                self.results
+                    .insert(test.id().clone(), json!({"result": "running"}));
+            }
+            ManagerStatusMessage::Pass(test, message) => {
+                self.results.insert(
+                    test.id().clone(),
+                    json!({"result": "pass", "message": message}),
+                );
+            }
+            ManagerStatusMessage::Fail(test, code, message) => {
+                self.results.insert(
+                    test.id().clone(),
+                    json!({"result": "fail", "code": code, "message": message}),
+                );
+            }
+            ManagerStatusMessage::Retry(test, attempt, code, message) => {
+                self.results.insert(
+                    test.id().clone(),
+                    json!({"result": "retry", "attempt": attempt, "code": code, "message": message}),
+                );
+            }
+            ManagerStatusMessage::Skipped(test, reason) => {
+                self.results.insert(
+                    test.id().clone(),
+                    json!({"result": "skip", "message": reason}),
+                );
+            }
+            ManagerStatusMessage::Finished(_, result, reason, run_directory) => {
+                self.running = false;
+                self.current_test = None;
+                self.finished = Some(json!({
+                    "result": result,
+                    "reason": reason,
+                    "run_directory": run_directory,
+                }));
+            }
+            ManagerStatusMessage::ScenarioStatus(_, state, _, _) => {
+                self.running = state != "idle" && state != "finished";
+            }
+            ManagerStatusMessage::TestStatus(test, state, _, _, reason) => {
+                if state == "running" {
+                    self.current_test = Some(test.clone());
+                }
+                if state != "pending" {
+                    self.results.insert(
+                        test.id().clone(),
+                        json!({"result": state, "message": reason}),
+                    );
+                }
+            }
+            ManagerStatusMessage::Measurement(_, _) => self.measurements.push(msg.to_json()),
+            ManagerStatusMessage::Iteration(_, _) => self.iteration = Some(msg.to_json()),
+            ManagerStatusMessage::Set(_, key, value) => {
+                self.metadata.insert(key.clone(), value.clone());
+            }
+            ManagerStatusMessage::Hello(_)
+            | ManagerStatusMessage::Log(_)
+            | ManagerStatusMessage::Ping(_)
+            | ManagerStatusMessage::Ask(_, _, _) => (),
+        }
+    }
+
+    fn describe(&self, id: &UnitName) -> Value {
+        json!({
+            "id": id.id(),
+            "name": self.names.get(id),
+            "description": self.descriptions.get(id),
+        })
+    }
+
+    fn jig_json(&self) -> Value {
+        match self.jig {
+            Some(ref jig) => self.describe(jig),
+            None => Value::Null,
+        }
+    }
+
+    fn scenarios_json(&self) -> Value {
+        json!({
+            "selected": self.scenario.as_ref().map(|s| s.id()),
+            "scenarios": self.scenarios.iter().map(|s| self.describe(s)).collect::<Vec<_>>(),
+        })
+    }
+
+    fn tests_json(&self) -> Value {
+        json!({
+            "scenario": self.scenario.as_ref().map(|s| s.id()),
+            "tests": self.tests.iter().map(|t| self.describe(t)).collect::<Vec<_>>(),
+        })
+    }
+
+    fn status_json(&self) -> Value {
+        json!({
+            "jig": self.jig.as_ref().map(|j| j.id()),
+            "scenario": self.scenario.as_ref().map(|s| s.id()),
+            "running": self.running,
+            "test": self.current_test.as_ref().map(|t| t.id()),
+            "metadata": self.metadata,
+            "results": self.results,
+            "measurements": self.measurements,
+            "finished": self.finished,
+            "iteration": self.iteration,
+        })
+    }
+}
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RequestError {
+    /// The request couldn't be read or understood.
+    Malformed,
+
+    /// The request came with a body larger than MAX_BODY_BYTES.
+    TooLarge,
+}
+
+pub struct HttpInterface {
+    state: Arc<Mutex<HttpState>>,
+    /// Queues for the /events clients, each drained by that client's own
+    /// thread, so that a slow client can't hold up the others.
+    event_clients: Arc<Mutex<Vec<SyncSender<String>>>>,
+    broadcaster: UnitBroadcaster,
+    id: UnitName,
+}
+
+impl HttpInterface {
+    /// Start serving on the given address, and return the address that was
+    /// actually bound.
+    pub fn start(address: &str, broadcaster: &UnitBroadcaster) -> Result<SocketAddr, String> {
+        let listener = TcpListener::bind(address)
+            .map_err(|e| format!("unable to listen on {}: {}", address, e))?;
+        let local_address = listener
+            .local_addr()
+            .map_err(|e| format!("unable to listen on {}: {}", address, e))?;
+
+        let http = Arc::new(HttpInterface {
+            state: Arc::new(Mutex::new(HttpState::default())),
+            event_clients: Arc::new(Mutex::new(vec![])),
+            broadcaster: broadcaster.clone(),
+            id: UnitName::internal("http"),
+        });
+
+        let receiver = broadcaster.subscribe();
+        let thr_http = http.clone();
+        thread::spawn(move || {
+            while let Ok(event) = receiver.recv() {
+                thr_http.process_event(event);
+            }
+        });
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(s) => s,
+                    Err(_) => continue,
+                };
+                // Don't let a slow client tie up a thread, or hold up /events.
+                if stream.set_read_timeout(Some(STREAM_TIMEOUT)).is_err()
+                    || stream.set_write_timeout(Some(STREAM_TIMEOUT)).is_err()
+                {
+                    continue;
+                }
+                let thr_http = http.clone();
+                thread::spawn(move || thr_http.handle_connection(stream));
+            }
+        });
+
+        // Ask for the current jig and scenarios, in case they're already known.
+        broadcaster.broadcast(&UnitEvent::ManagerRequest(ManagerControlMessage::new(
+            &UnitName::internal("http"),
+            ManagerControlMessageContents::InitialGreeting,
+        )));
+        Ok(local_address)
+    }
+
+    fn process_event(&self, event: UnitEvent) {
+        let msg = match event {
+            UnitEvent::ManagerStatus(msg) => {
+                self.state.lock().unwrap().update(&msg);
+                msg
+            }
+            UnitEvent::Log(entry) => ManagerStatusMessage::Log(entry),
+            _ => return,
+        };
+
+        let record = Self::sse_record(&msg);
+        self.event_clients
+            .lock()
+            .unwrap()
+            .retain(|client| client.try_send(record.clone()).is_ok());
+    }
+
+    /// Encode a message as a Server-Sent Event, named after its type.
+    pub fn sse_record(msg: &ManagerStatusMessage) -> String {
+        let json = msg.to_json();
+        format!(
+            "event: {}\ndata: {}\n\n",
+            json["type"].as_str().unwrap(),
+            json
+        )
+    }
+
+    pub fn read_request<R: BufRead>(reader: &mut R) -> Result<Request, RequestError> {
+        let mut request_line = String::new();
+        reader
+            .read_line(&mut request_line)
+            .map_err(|_| RequestError::Malformed)?;
+        let mut words = request_line.split_whitespace();
+        let method = words.next().ok_or(RequestError::Malformed)?.to_uppercase();
+        let target = words.next().ok_or(RequestError::Malformed)?;
+        let path = target.split('?').next().unwrap_or("").to_owned();
+
+        // Skip over the headers, and any body that came along with them.
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            if reader
+                .read_line(&mut header)
+                .map_err(|_| RequestError::Malformed)?
+                == 0
+            {
+                break;
+            }
+            let header = header.trim();
+            if header.is_empty() {
+                break;
+            }
+            let mut parts = header.splitn(2, ':');
+            let key = parts.next().unwrap_or("").trim();
+            if key.eq_ignore_ascii_case("content-length") {
+                content_length = parts.next().unwrap_or("").trim().parse().unwrap_or(0);
+            }
+        }
+        if content_length > MAX_BODY_BYTES {
+            return Err(RequestError::TooLarge);
+        }
+        let mut body = vec![0; content_length];
+        reader
+            .read_exact(&mut body)
+            .map_err(|_| RequestError::Malformed)?;
+
+        Ok(Request { method, path })
+    }
+
+    fn respond(mut stream: TcpStream, status: &str, body: &Value) {
+        let body = body.to_string();
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )
+        .ok();
+    }
+
+    fn send_request(&self, contents: ManagerControlMessageContents) {
+        self.broadcaster
+            .broadcast(&UnitEvent::ManagerRequest(ManagerControlMessage::new(
+                &self.id, contents,
+            )));
+    }
+
+    fn handle_connection(&self, mut stream: TcpStream) {
+        let request = match Self::read_request(&mut BufReader::new(&stream)) {
+            Ok(r) => r,
+            Err(RequestError::TooLarge) => {
+                return Self::respond(
+                    stream,
+                    "413 Payload Too Large",
+                    &json!({"error": "request body too large"}),
+                )
+            }
+            Err(RequestError::Malformed) => return,
+        };
+        let path: Vec<&str> = request.path.split('/').filter(|s| !s.is_empty()).collect();
+
+        let accepted = json!({"status": "accepted"});
+        match (request.method.as_str(), path.as_slice()) {
+            ("GET", ["jig"]) => {
+                let body = self.state.lock().unwrap().jig_json();
+                Self::respond(stream, "200 OK", &body)
+            }
+            ("GET", ["scenarios"]) => {
+                let body = self.state.lock().unwrap().scenarios_json();
+                Self::respond(stream, "200 OK", &body)
+            }
+            ("GET", ["tests"]) => {
+                let body = self.state.lock().unwrap().tests_json();
+                Self::respond(stream, "200 OK", &body)
+            }
+            ("GET", ["status"]) => {
+                let body = self.state.lock().unwrap().status_json();
+                Self::respond(stream, "200 OK", &body)
+            }
+            ("GET", ["events"]) => {
+                // Join the queue before the headers go out, so that no event
+                // sent after the client sees them can be missed.
+                let (queue, pending) = sync_channel::<String>(EVENT_QUEUE_DEPTH);
+                self.event_clients.lock().unwrap().push(queue);
+                if stream
+                    .write_all(
+                        b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n",
+                    )
+                    .is_err()
+                {
+                    return;
+                }
+                // Stream events from this thread until the client goes away,
+                // or falls so far behind that its queue is dropped.
+                for record in pending {
+                    if stream.write_all(record.as_bytes()).is_err() {
+                        break;
+                    }
+                }
+            }
+            ("POST", ["start"]) => {
+                self.send_request(ManagerControlMessageContents::StartScenario(None));
+                Self::respond(stream, "202 Accepted", &accepted)
+            }
+            ("POST", ["start", scenario]) | ("POST", ["scenario", scenario]) => {
+                let name = match UnitName::from_str(&scenario.to_lowercase(), "scenario") {
+                    Ok(n) => n,
+                    Err(e) => {
+                        return Self::respond(
+                            stream,
+                            "400 Bad Request",
+                            &json!({ "error": format!("invalid scenario name: {}", e) }),
+                        )
+                    }
+                };
+                if path[0] == "start" {
+                    self.send_request(ManagerControlMessageContents::StartScenario(Some(name)));
+                } else {
+                    self.send_request(ManagerControlMessageContents::Scenario(name));
+                }
+                Self::respond(stream, "202 Accepted", &accepted)
+            }
+            ("POST", ["resume"]) => {
+                self.send_request(ManagerControlMessageContents::ResumeScenario(None));
+                Self::respond(stream, "202 Accepted", &accepted)
+            }
+            ("POST", ["resume", serial]) => {
+                self.send_request(ManagerControlMessageContents::ResumeScenario(Some(
+                    serial.to_string(),
+                )));
+                Self::respond(stream, "202 Accepted", &accepted)
+            }
+            ("POST", ["abort"]) => {
+                self.send_request(ManagerControlMessageContents::AbortTests);
+                Self::respond(stream, "202 Accepted", &accepted)
+            }
+            _ => Self::respond(stream, "404 Not Found", &json!({"error": "not found"})),
+        }
+    }
+}
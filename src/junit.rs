@@ -0,0 +1,375 @@
+// A built-in reporter that writes one JUnit XML <testsuite> per scenario
+// run.  It watches the UnitBroadcaster for status messages and test output,
+// and writes the report once the scenario has finished.
+extern crate humantime;
+
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use self::humantime::format_rfc3339_seconds;
+
+use unit::UnitName;
+use unitbroadcaster::{LogEntry, LogType, UnitBroadcaster, UnitEvent};
+use unitmanager::{
+    ManagerControlMessage, ManagerControlMessageContents, ManagerStatusMessage, RunMetadata,
+    RESUMED_MESSAGE,
+};
+
+enum TestOutcome {
+    /// The test never ran, perhaps because the scenario was aborted.
+    NotRun,
+    Running,
+    Pass,
+    Fail(i32 /* return code */, String /* reason */),
+    Skip(String /* reason */),
+}
+
+struct TestCase {
+    outcome: TestOutcome,
+    started: Option<Instant>,
+    duration: Duration,
+    stdout: String,
+    stderr: String,
+}
+
+impl TestCase {
+    fn new() -> TestCase {
+        TestCase {
+            outcome: TestOutcome::NotRun,
+            started: None,
+            duration: Duration::from_secs(0),
+            stdout: String::new(),
+            stderr: String::new(),
+        }
+    }
+}
+
+/// Everything we know about the scenario run in progress.
+struct SuiteRun {
+    scenario: UnitName,
+    metadata: RunMetadata,
+    timestamp: SystemTime,
+    started: Instant,
+    order: Vec<UnitName>,
+    tests: HashMap<UnitName, TestCase>,
+}
+
+impl SuiteRun {
+    fn test(&mut self, id: &UnitName) -> &mut TestCase {
+        if !self.tests.contains_key(id) {
+            self.order.push(id.clone());
+        }
+        self.tests.entry(id.clone()).or_insert_with(TestCase::new)
+    }
+
+    fn to_xml(&self, result: u32, reason: &str) -> String {
+        let mut failures = 0;
+        let mut skipped = 0;
+        for test in self.tests.values() {
+            match test.outcome {
+                TestOutcome::Fail(_, _) => failures += 1,
+                TestOutcome::Skip(_) | TestOutcome::NotRun => skipped += 1,
+                _ => (),
+            }
+        }
+
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(&format!(
+            "<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"{}\" timestamp=\"{}\" time=\"{:.3}\">\n",
+            xml_escape(self.scenario.id()),
+            self.tests.len(),
+            failures,
+            skipped,
+            format_rfc3339_seconds(self.timestamp),
+            as_secs_f64(self.started.elapsed())
+        ));
+
+        xml.push_str("  <properties>\n");
+        xml.push_str(&format!(
+            "    <property name=\"result\" value=\"{}\"/>\n    <property name=\"reason\" value=\"{}\"/>\n",
+            result,
+            xml_escape(reason)
+        ));
+        for (key, value) in &self.metadata {
+            xml.push_str(&format!(
+                "    <property name=\"{}\" value=\"{}\"/>\n",
+                xml_escape(key),
+                xml_escape(value)
+            ));
+        }
+        xml.push_str("  </properties>\n");
+
+        for id in &self.order {
+            let test = &self.tests[id];
+            xml.push_str(&format!(
+                "  <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\">\n",
+                xml_escape(id.id()),
+                xml_escape(self.scenario.id()),
+                as_secs_f64(test.duration)
+            ));
+            match test.outcome {
+                TestOutcome::Fail(code, ref reason) => xml.push_str(&format!(
+                    "    <failure message=\"{}\" type=\"exit {}\">test exited with code {}</failure>\n",
+                    xml_escape(reason),
+                    code,
+                    code
+                )),
+                TestOutcome::Skip(ref reason) => xml.push_str(&format!(
+                    "    <skipped message=\"{}\"/>\n",
+                    xml_escape(reason)
+                )),
+                TestOutcome::NotRun => xml.push_str("    <skipped message=\"not run\"/>\n"),
+                TestOutcome::Running | TestOutcome::Pass => (),
+            }
+            if !test.stdout.is_empty() {
+                xml.push_str(&format!(
+                    "    <system-out>{}</system-out>\n",
+                    xml_escape(&test.stdout)
+                ));
+            }
+            if !test.stderr.is_empty() {
+                xml.push_str(&format!(
+                    "    <system-err>{}</system-err>\n",
+                    xml_escape(&test.stderr)
+                ));
+            }
+            xml.push_str("  </testcase>\n");
+        }
+        xml.push_str("</testsuite>\n");
+        xml
+    }
+}
+
+fn as_secs_f64(d: Duration) -> f64 {
+    d.as_secs() as f64 + f64::from(d.subsec_nanos()) / 1_000_000_000.0
+}
+
+/// Escape text for use in XML attributes and character data.  Control
+/// characters other than tabs and newlines aren't allowed in XML at all.
+pub fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\t' | '\n' | '\r' => out.push(c),
+            c if c.is_control() => (),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+pub struct JUnitReporter {
+    directory: PathBuf,
+    broadcaster: UnitBroadcaster,
+    id: UnitName,
+
+    /// The order of tests in each scenario, as last announced.
+    sequences: HashMap<UnitName, Vec<UnitName>>,
+
+    run: Option<SuiteRun>,
+}
+
+impl JUnitReporter {
+    pub fn start(directory: &Path, broadcaster: &UnitBroadcaster) -> Result<(), String> {
+        fs::create_dir_all(directory)
+            .map_err(|e| format!("unable to create {}: {}", directory.display(), e))?;
+
+        let mut reporter = JUnitReporter {
+            directory: directory.to_owned(),
+            broadcaster: broadcaster.clone(),
+            id: UnitName::internal("junit"),
+            sequences: HashMap::new(),
+            run: None,
+        };
+
+        let receiver = broadcaster.subscribe();
+        thread::spawn(move || {
+            while let Ok(event) = receiver.recv() {
+                reporter.process_event(event);
+            }
+        });
+        Ok(())
+    }
+
+    fn process_event(&mut self, event: UnitEvent) {
+        match event {
+            UnitEvent::ManagerStatus(msg) => self.process_status(msg),
+            UnitEvent::Log(entry) => self.process_log(&entry),
+            _ => (),
+        }
+    }
+
+    fn process_log(&mut self, entry: &LogEntry) {
+        let run = match self.run {
+            Some(ref mut run) => run,
+            None => return,
+        };
+        if !run.tests.contains_key(entry.id()) {
+            return;
+        }
+        let test = run.test(entry.id());
+        let output = match *entry.kind() {
+            LogType::Info => &mut test.stdout,
+            LogType::Error => &mut test.stderr,
+        };
+        output.push_str(entry.message());
+        output.push('\n');
+    }
+
+    fn process_status(&mut self, msg: ManagerStatusMessage) {
+        match msg {
+            ManagerStatusMessage::Tests(scenario, tests) => {
+                // Tests that haven't been seen yet run in the announced order.
+                if let Some(ref mut run) = self.run {
+                    if run.scenario == scenario {
+                        for test in &tests {
+                            run.test(test);
+                        }
+                    }
+                }
+                self.sequences.insert(scenario, tests);
+            }
+            ManagerStatusMessage::Start(scenario, metadata) => {
+                let mut run = SuiteRun {
+                    scenario: scenario.clone(),
+                    metadata,
+                    timestamp: SystemTime::now(),
+                    started: Instant::now(),
+                    order: vec![],
+                    tests: HashMap::new(),
+                };
+                if let Some(sequence) = self.sequences.get(&scenario) {
+                    for test in sequence {
+                        run.test(test);
+                    }
+                }
+                self.run = Some(run);
+
+                // Make sure we have the current test order for this scenario.
+                self.broadcaster
+                    .broadcast(&UnitEvent::ManagerRequest(ManagerControlMessage::new(
+                        &self.id,
+                        ManagerControlMessageContents::Tests(Some(scenario)),
+                    )));
+            }
+            ManagerStatusMessage::Resumed(_, tests) => {
+                for test in &tests {
+                    if let Some(ref mut run) = self.run {
+                        run.test(test)
+                            .stdout
+                            .push_str(&format!("{}\n", RESUMED_MESSAGE));
+                    }
+                    self.finish_test(test, TestOutcome::Pass);
+                }
+            }
+            ManagerStatusMessage::Running(test) => {
+                if let Some(ref mut run) = self.run {
+                    let test = run.test(&test);
+                    test.outcome = TestOutcome::Running;
+                    test.started = Some(Instant::now());
+                }
+            }
+            ManagerStatusMessage::Retry(test, attempt, code, reason) => {
+                // Only the last attempt counts, but keep a record of the others.
+                if let Some(ref mut run) = self.run {
+                    run.test(&test).stderr.push_str(&format!(
+                        "attempt {} failed with return code {}: {}\n",
+                        attempt, code, reason
+                    ));
+                }
+            }
+            ManagerStatusMessage::Set(_, key, value) => {
+                if let Some(ref mut run) = self.run {
+                    run.metadata.insert(key, value);
+                }
+            }
+            ManagerStatusMessage::Measurement(test, m) => {
+                if let Some(ref mut run) = self.run {
+                    let test = run.test(&test);
+                    let output = if m.passed {
+                        &mut test.stdout
+                    } else {
+                        &mut test.stderr
+                    };
+                    output.push_str(&format!(
+                        "measurement {} = {}{}: {}\n",
+                        m.name,
+                        m.value,
+                        m.units.map(|u| format!(" {}", u)).unwrap_or_default(),
+                        if m.passed { "pass" } else { "fail" }
+                    ));
+                }
+            }
+            ManagerStatusMessage::Pass(test, _) => self.finish_test(&test, TestOutcome::Pass),
+            ManagerStatusMessage::Fail(test, code, reason) => {
+                self.finish_test(&test, TestOutcome::Fail(code, reason))
+            }
+            ManagerStatusMessage::Skipped(test, reason) => {
+                self.finish_test(&test, TestOutcome::Skip(reason))
+            }
+            ManagerStatusMessage::Finished(scenario, result, reason, _) => {
+                let run = match self.run.take() {
+                    Some(run) => run,
+                    None => return,
+                };
+                if let Err(e) = self.write_report(&run, result, &reason) {
+                    self.broadcaster
+                        .broadcast(&UnitEvent::Log(LogEntry::new_error(
+                            self.id.clone(),
+                            format!("unable to write JUnit report for {}: {}", scenario, e),
+                        )));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn finish_test(&mut self, id: &UnitName, outcome: TestOutcome) {
+        if let Some(ref mut run) = self.run {
+            let test = run.test(id);
+            if let Some(started) = test.started {
+                test.duration = started.elapsed();
+            }
+            test.outcome = outcome;
+        }
+    }
+
+    fn write_report(&self, run: &SuiteRun, result: u32, reason: &str) -> io::Result<()> {
+        let secs = run
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        // Runs of a repeating scenario may finish within the same second.
+        let mut suffix = 0;
+        let (path, mut file) = loop {
+            let name = match suffix {
+                0 => format!("{}-{}.xml", run.scenario.id(), secs),
+                n => format!("{}-{}-{}.xml", run.scenario.id(), secs, n),
+            };
+            let path = self.directory.join(name);
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => break (path, file),
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => suffix += 1,
+                Err(e) => return Err(e),
+            }
+        };
+        file.write_all(run.to_xml(result, reason).as_bytes())?;
+        self.broadcaster
+            .broadcast(&UnitEvent::Log(LogEntry::new_info(
+                self.id.clone(),
+                format!("wrote JUnit report {}", path.display()),
+            )));
+        Ok(())
+    }
+}
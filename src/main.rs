@@ -4,13 +4,19 @@ extern crate ctrlc;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde;
+#[macro_use]
 extern crate serde_json;
 
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 mod config;
+mod http;
+mod junit;
+mod plan;
 mod quiesce;
+mod resultsdb;
 mod terminal;
 mod unit;
 mod unitbroadcaster;
@@ -25,7 +31,7 @@ use unitlibrary::UnitLibrary;
 use unitloader::UnitLoader;
 use unitwatcher::UnitWatcher;
 
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, SubCommand};
 
 fn main() {
     let config = Arc::new(Mutex::new(config::Config::new()));
@@ -49,6 +55,7 @@ fn main() {
         .long_version(env!("GIT_VERSION"))
         .author("Sean Cross <sean@xobs.io>")
         .about("Orchestrates the Common Factory Test Interface server")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("CONFIG_DIR")
                 .short("c")
@@ -86,9 +93,91 @@ fn main() {
                 .long("keyboard-trigger")
                 .help("Run default scenario on enter key press"),
         )
+        .arg(
+            Arg::with_name("HTTP_ADDRESS")
+                .long("http")
+                .value_name("ADDRESS")
+                .help("Serve the HTTP API on the specified address, e.g. 127.0.0.1:8080")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("JUNIT_DIR")
+                .long("junit")
+                .value_name("DIRECTORY")
+                .help("Write a JUnit XML report for each scenario run to the specified directory")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("OUTPUT_DIR")
+                .long("output-dir")
+                .value_name("DIRECTORY")
+                .help("Create a directory for each scenario run's artifacts in the specified directory")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("RESULTS_DB")
+                .long("results-db")
+                .value_name("FILE")
+                .help("Record every run, test result and log line in the specified SQLite database")
+                .required(false)
+                .takes_value(true),
+        )
+        .subcommand(
+            SubCommand::with_name("plan")
+                .about("Print the order a scenario's tests would run in, and why, without running them")
+                .arg(
+                    Arg::with_name("CONFIG_DIR")
+                        .short("c")
+                        .long("config-dir")
+                        .value_name("CONFIG_DIR")
+                        .number_of_values(1)
+                        .required(true)
+                        .multiple(true)
+                        .takes_value(true)
+                        .help("Directory where configuration unit files are stored"),
+                )
+                .arg(
+                    Arg::with_name("JIG")
+                        .long("jig")
+                        .value_name("JIG")
+                        .help("Plan as if the specified jig were attached")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("SCENARIO")
+                        .long("scenario")
+                        .value_name("SCENARIO")
+                        .help("Scenario to plan")
+                        .required(true)
+                        .takes_value(true),
+                ),
+        )
         .get_matches();
 
+    if let Some(plan_matches) = matches.subcommand_matches("plan") {
+        let config_dirs: Vec<_> = plan_matches.values_of("CONFIG_DIR").unwrap().collect();
+        if let Err(e) = plan::run(
+            &config_dirs,
+            plan_matches.value_of("JIG"),
+            plan_matches.value_of("SCENARIO").unwrap(),
+        ) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let config_dirs: Vec<_> = matches.values_of("CONFIG_DIR").unwrap().collect();
+    if let Some(directory) = matches.value_of("OUTPUT_DIR") {
+        config
+            .lock()
+            .unwrap()
+            .set_output_directory(Path::new(directory));
+    }
     let output_type = if matches.is_present("PLAIN") {
         Some(terminal::TerminalOutputType::Plain)
     } else if matches.is_present("QUIET") {
@@ -103,6 +192,24 @@ fn main() {
         matches.is_present("KEYBOARD_TRIGGER"),
     );
 
+    if let Some(address) = matches.value_of("HTTP_ADDRESS") {
+        if let Err(e) = http::HttpInterface::start(address, &unit_broadcaster) {
+            panic!("Unable to start HTTP server: {}", e);
+        }
+    }
+
+    if let Some(directory) = matches.value_of("JUNIT_DIR") {
+        if let Err(e) = junit::JUnitReporter::start(Path::new(directory), &unit_broadcaster) {
+            panic!("Unable to start JUnit reporter: {}", e);
+        }
+    }
+
+    if let Some(path) = matches.value_of("RESULTS_DB") {
+        if let Err(e) = resultsdb::ResultsDb::start(Path::new(path), &unit_broadcaster) {
+            panic!("Unable to open results database: {}", e);
+        }
+    }
+
     for config_dir in config_dirs {
         unit_watcher
             .add_path(config_dir)
@@ -117,9 +224,8 @@ fn main() {
         None => None,
         Some(dv) => {
             use std::fs::File;
-            use std::path::Path;
             let path = Path::new(dv);
-            Some(File::create(&path).expect("Couldn't create logfile"))
+            Some(File::create(path).expect("Couldn't create logfile"))
         }
     };
     // Main message loop.  Monitor messages and pass them to each component.
@@ -0,0 +1,91 @@
+// Plan mode.  The units in the config directories are loaded through the
+// UnitLibrary, and the order a scenario's tests would run in is printed
+// along with why each test is there.  Nothing is run, including the jig's
+// own TestFile and TestProgram checks.
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use config::Config;
+use unit::UnitName;
+use unitbroadcaster::{UnitBroadcaster, UnitEvent, UnitStatus, UnitStatusEvent};
+use unitlibrary::UnitLibrary;
+use units::scenario::Inclusion;
+
+pub fn run(config_dirs: &[&str], jig: Option<&str>, scenario: &str) -> Result<(), String> {
+    let jig_name = match jig {
+        Some(jig) => Some(
+            UnitName::from_str(&jig.to_lowercase(), "jig")
+                .map_err(|e| format!("invalid jig name: {}", e))?,
+        ),
+        None => None,
+    };
+    let scenario_name = UnitName::from_str(&scenario.to_lowercase(), "scenario")
+        .map_err(|e| format!("invalid scenario name: {}", e))?;
+
+    let config = Arc::new(Mutex::new(Config::new()));
+    let broadcaster = UnitBroadcaster::new();
+    let receiver = broadcaster.subscribe();
+    let library = UnitLibrary::new(&broadcaster, &config);
+
+    for config_dir in config_dirs {
+        let entries = Path::new(config_dir)
+            .read_dir()
+            .map_err(|e| format!("unable to read config directory {}: {}", config_dir, e))?;
+        for entry in entries {
+            let path = entry
+                .map_err(|e| format!("unable to read config directory {}: {}", config_dir, e))?
+                .path();
+            if let Some(evt) = UnitStatusEvent::new_added(&path) {
+                library.process_message(&UnitEvent::Status(UnitStatusEvent::new_load_started(
+                    evt.name(),
+                    &path,
+                )));
+            }
+        }
+    }
+    let plan = library.plan(jig_name.as_ref(), &scenario_name);
+
+    // A unit that couldn't be loaded may be why a test is missing.
+    while let Ok(event) = receiver.try_recv() {
+        if let UnitEvent::Status(ref evt) = event {
+            match *evt.status() {
+                // The scenario's own problems are reported below.
+                UnitStatus::LoadFailed(_) if *evt.name() == scenario_name => (),
+                UnitStatus::LoadFailed(ref reason) => {
+                    eprintln!(
+                        "warning: unable to load {}: {}",
+                        evt.name(),
+                        reason.trim_end()
+                    )
+                }
+                _ => (),
+            }
+        }
+    }
+    let plan = plan?;
+
+    match jig_name {
+        Some(jig_name) => println!("{} on {}:", scenario_name, jig_name),
+        None => println!("{}:", scenario_name),
+    }
+    let width = plan.iter().map(|t| t.name.id().len()).max().unwrap_or(0);
+    let mut step = 0;
+    for test in &plan {
+        // Assumed tests aren't run, so they don't get a step number.
+        let number = if test.reasons.contains(&Inclusion::Assumed) {
+            "-".to_owned()
+        } else {
+            step += 1;
+            step.to_string()
+        };
+        let reasons: Vec<String> = test.reasons.iter().map(|r| r.to_string()).collect();
+        println!(
+            "{:>4}  {:<width$}  {}",
+            number,
+            test.name.id(),
+            reasons.join(", "),
+            width = width
+        );
+    }
+    Ok(())
+}
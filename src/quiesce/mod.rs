@@ -28,6 +28,11 @@ impl Quiesce {
         {
             return;
         }
+        // Status messages are only copies of what was sent to interfaces, and
+        // don't change anything that a rescan would pick up.
+        if let UnitEvent::ManagerStatus(_) = *evt {
+            return;
+        }
         self.restart_timer(UnitEvent::RescanRequest);
     }
 
@@ -31,7 +31,7 @@ impl Ord for ScheduledEvent {
 
 impl PartialOrd for ScheduledEvent {
     fn partial_cmp(&self, other: &ScheduledEvent) -> Option<Ordering> {
-        other.when.partial_cmp(&self.when)
+        Some(self.cmp(other))
     }
 }
 
@@ -63,7 +63,7 @@ impl ScheduleWorker {
             match action {
                 Action::Schedule(event) => self.schedule.push(event),
                 Action::Ignore(ignore_id) => {
-                    for &ScheduledEvent { ref id, .. } in &self.schedule {
+                    for ScheduledEvent { id, .. } in &self.schedule {
                         if *id == ignore_id {
                             self.ignore.insert(ignore_id);
                             break;
@@ -0,0 +1,311 @@
+// A built-in results store.  Every run, test result and log line seen on
+// the UnitBroadcaster is written to a local SQLite file, so a station keeps
+// a queryable history of its own even when nothing upstream is listening.
+extern crate rusqlite;
+extern crate serde_json;
+
+use std::path::Path;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use self::rusqlite::Connection;
+
+use unit::UnitName;
+use unitbroadcaster::{LogEntry, UnitBroadcaster, UnitEvent};
+use unitmanager::{
+    ManagerControlMessage, ManagerControlMessageContents, ManagerStatusMessage, RunMetadata,
+    DUT_KEY, RESUMED_MESSAGE,
+};
+
+// The `dut` column of `runs` holds the DUT_KEY ("serial") entry of the run's
+// metadata, so that every run of a given board can be found without parsing
+// the metadata.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS runs (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        jig TEXT,
+        scenario TEXT NOT NULL,
+        dut TEXT,
+        metadata TEXT NOT NULL,
+        started_at REAL NOT NULL,
+        finished_at REAL,
+        result INTEGER,
+        reason TEXT,
+        run_directory TEXT
+    );
+    CREATE INDEX IF NOT EXISTS runs_dut ON runs (dut);
+    CREATE TABLE IF NOT EXISTS test_results (
+        run_id INTEGER NOT NULL REFERENCES runs (id),
+        test TEXT NOT NULL,
+        result TEXT NOT NULL,
+        code INTEGER,
+        message TEXT,
+        attempts INTEGER NOT NULL DEFAULT 1,
+        started_at REAL,
+        finished_at REAL,
+        PRIMARY KEY (run_id, test)
+    );
+    CREATE TABLE IF NOT EXISTS log_lines (
+        run_id INTEGER NOT NULL REFERENCES runs (id),
+        unit TEXT NOT NULL,
+        unit_type TEXT NOT NULL,
+        message_type TEXT NOT NULL,
+        unix_time INTEGER NOT NULL,
+        unix_time_nsecs INTEGER NOT NULL,
+        message TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS log_lines_run ON log_lines (run_id);
+    CREATE TABLE IF NOT EXISTS measurements (
+        run_id INTEGER NOT NULL REFERENCES runs (id),
+        test TEXT NOT NULL,
+        name TEXT NOT NULL,
+        value REAL NOT NULL,
+        units TEXT,
+        low REAL,
+        high REAL,
+        passed INTEGER NOT NULL,
+        recorded_at REAL NOT NULL
+    );
+";
+
+fn now() -> f64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as f64 + f64::from(d.subsec_nanos()) / 1_000_000_000.0,
+        Err(_) => 0.0,
+    }
+}
+
+pub struct ResultsDb {
+    connection: Connection,
+    broadcaster: UnitBroadcaster,
+    id: UnitName,
+
+    /// The most recently announced jig.
+    jig: Option<UnitName>,
+
+    /// The row id of the run in progress, if any.
+    run_id: Option<i64>,
+
+    /// The run's metadata, including any variables set by tests.
+    metadata: RunMetadata,
+}
+
+impl ResultsDb {
+    /// Prepare a database for recording results, creating any missing tables.
+    pub fn new(
+        connection: Connection,
+        broadcaster: &UnitBroadcaster,
+    ) -> rusqlite::Result<ResultsDb> {
+        // Every log line is its own write, so don't wait for each one to
+        // reach the disk.  A power cut may lose the last few lines, but
+        // can't corrupt the database.
+        connection.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+        connection.pragma_update(None, "synchronous", "NORMAL")?;
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(ResultsDb {
+            connection,
+            broadcaster: broadcaster.clone(),
+            id: UnitName::internal("resultsdb"),
+            jig: None,
+            run_id: None,
+            metadata: RunMetadata::new(),
+        })
+    }
+
+    pub fn start(path: &Path, broadcaster: &UnitBroadcaster) -> Result<(), String> {
+        let mut db = Connection::open(path)
+            .and_then(|c| Self::new(c, broadcaster))
+            .map_err(|e| format!("unable to open {}: {}", path.display(), e))?;
+
+        let receiver = broadcaster.subscribe();
+        thread::spawn(move || {
+            while let Ok(event) = receiver.recv() {
+                if let Err(e) = db.process_event(event) {
+                    db.broadcaster
+                        .broadcast(&UnitEvent::Log(LogEntry::new_error(
+                            db.id.clone(),
+                            format!("unable to record results: {}", e),
+                        )));
+                }
+            }
+        });
+
+        // Ask for the current jig, in case it has already been announced.
+        broadcaster.broadcast(&UnitEvent::ManagerRequest(ManagerControlMessage::new(
+            &UnitName::internal("resultsdb"),
+            ManagerControlMessageContents::Jig,
+        )));
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    pub fn process_event(&mut self, event: UnitEvent) -> rusqlite::Result<()> {
+        match event {
+            UnitEvent::ManagerStatus(msg) => self.process_status(msg),
+            UnitEvent::Log(entry) => self.process_log(&entry),
+            _ => Ok(()),
+        }
+    }
+
+    fn process_log(&self, entry: &LogEntry) -> rusqlite::Result<()> {
+        let run_id = match self.run_id {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        // Our own errors are about failing to write, so recording them
+        // would only fail again.
+        if entry.id() == &self.id {
+            return Ok(());
+        }
+        self.connection.execute(
+            "INSERT INTO log_lines (run_id, unit, unit_type, message_type, unix_time, unix_time_nsecs, message)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            (
+                run_id,
+                entry.id().id(),
+                entry.id().kind().to_string(),
+                entry.kind().as_str(),
+                entry.secs() as i64,
+                entry.nsecs(),
+                entry.message(),
+            ),
+        )?;
+        Ok(())
+    }
+
+    fn process_status(&mut self, msg: ManagerStatusMessage) -> rusqlite::Result<()> {
+        match msg {
+            ManagerStatusMessage::Jig(jig) => self.jig = jig,
+            ManagerStatusMessage::Start(scenario, metadata) => {
+                self.connection.execute(
+                    "INSERT INTO runs (jig, scenario, dut, metadata, started_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    (
+                        self.jig.as_ref().map(|j| j.id().clone()),
+                        scenario.id(),
+                        metadata.get(DUT_KEY),
+                        serde_json::to_string(&metadata).unwrap_or_default(),
+                        now(),
+                    ),
+                )?;
+                self.run_id = Some(self.connection.last_insert_rowid());
+                self.metadata = metadata;
+            }
+            ManagerStatusMessage::Set(_, key, value) => {
+                if let Some(run_id) = self.run_id {
+                    self.metadata.insert(key, value);
+                    self.connection.execute(
+                        "UPDATE runs SET metadata = ?1, dut = ?2 WHERE id = ?3",
+                        (
+                            serde_json::to_string(&self.metadata).unwrap_or_default(),
+                            self.metadata.get(DUT_KEY),
+                            run_id,
+                        ),
+                    )?;
+                }
+            }
+            ManagerStatusMessage::Resumed(_, tests) => {
+                for test in &tests {
+                    self.finish_test(test, "pass", Some(0), RESUMED_MESSAGE)?;
+                }
+            }
+            ManagerStatusMessage::Running(test) => {
+                if let Some(run_id) = self.run_id {
+                    // A retried test keeps the time its first attempt started.
+                    self.connection.execute(
+                        "INSERT INTO test_results (run_id, test, result, started_at)
+                         VALUES (?1, ?2, 'running', ?3)
+                         ON CONFLICT (run_id, test) DO UPDATE SET result = 'running'",
+                        (run_id, test.id(), now()),
+                    )?;
+                }
+            }
+            ManagerStatusMessage::Retry(test, _, code, message) => {
+                if let Some(run_id) = self.run_id {
+                    self.connection.execute(
+                        "UPDATE test_results SET attempts = attempts + 1, code = ?1, message = ?2
+                         WHERE run_id = ?3 AND test = ?4",
+                        (code, message, run_id, test.id()),
+                    )?;
+                }
+            }
+            ManagerStatusMessage::Measurement(test, m) => {
+                if let Some(run_id) = self.run_id {
+                    self.connection.execute(
+                        "INSERT INTO measurements (run_id, test, name, value, units, low, high, passed, recorded_at)
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                        (
+                            run_id,
+                            test.id(),
+                            m.name,
+                            m.value,
+                            m.units,
+                            m.low,
+                            m.high,
+                            m.passed,
+                            now(),
+                        ),
+                    )?;
+                }
+            }
+            ManagerStatusMessage::Pass(test, message) => {
+                self.finish_test(&test, "pass", Some(0), &message)?
+            }
+            ManagerStatusMessage::Fail(test, code, message) => {
+                self.finish_test(&test, "fail", Some(code), &message)?
+            }
+            ManagerStatusMessage::Skipped(test, reason) => {
+                self.finish_test(&test, "skip", None, &reason)?
+            }
+            ManagerStatusMessage::Finished(_, result, reason, run_directory) => {
+                if let Some(run_id) = self.run_id.take() {
+                    self.connection.execute(
+                        "UPDATE runs SET finished_at = ?1, result = ?2, reason = ?3, run_directory = ?4
+                         WHERE id = ?5",
+                        (
+                            now(),
+                            result,
+                            reason,
+                            run_directory.map(|p| p.to_string_lossy().into_owned()),
+                            run_id,
+                        ),
+                    )?;
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn finish_test(
+        &self,
+        test: &UnitName,
+        result: &str,
+        code: Option<i32>,
+        message: &str,
+    ) -> rusqlite::Result<()> {
+        let run_id = match self.run_id {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        let updated = self.connection.execute(
+            "UPDATE test_results SET result = ?1, code = ?2, message = ?3, finished_at = ?4
+             WHERE run_id = ?5 AND test = ?6",
+            (result, code, message, now(), run_id, test.id()),
+        )?;
+        // Skipped tests never started, so there's no row to update yet.
+        if updated == 0 {
+            self.connection.execute(
+                "INSERT INTO test_results (run_id, test, result, code, message, finished_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                (run_id, test.id(), result, code, message, now()),
+            )?;
+        }
+        Ok(())
+    }
+}
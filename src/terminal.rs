@@ -78,19 +78,20 @@ impl TerminalInterface {
             let id = UnitName::internal("terminal");
             let thread_broadcaster = broadcaster.clone();
 
-            // Broadcast a start scenario message if an enter key is pressed in the terminal where exclave is running
-            // Could possibly be extended to do things like, run test #1 when the '1' key is entered or print stats when
-            // the '?' key is entered
+            // Broadcast a start scenario message if an enter key is pressed in the terminal where exclave is running,
+            // or resume the last run if 'r' is entered first.  Could possibly be extended to do things like, run
+            // test #1 when the '1' key is entered or print stats when the '?' key is entered
             thread::spawn(move || loop {
                 let mut line = String::new();
                 std::io::stdin()
                     .read_line(&mut line)
                     .expect("Failed to read line");
+                let contents = match line.trim() {
+                    "r" => ManagerControlMessageContents::ResumeScenario(None),
+                    _ => ManagerControlMessageContents::StartScenario(None),
+                };
                 thread_broadcaster.broadcast(&UnitEvent::ManagerRequest(
-                    ManagerControlMessage::new(
-                        &id,
-                        ManagerControlMessageContents::StartScenario(None),
-                    ),
+                    ManagerControlMessage::new(&id, contents),
                 ));
             });
         }
@@ -131,6 +132,7 @@ impl TerminalInterface {
             UnitEvent::RescanRequest => (),
             UnitEvent::Shutdown => (),
             UnitEvent::ManagerRequest(_) => (),
+            UnitEvent::ManagerStatus(_) => (),
         }
 
         match self.output_type {
@@ -150,6 +152,7 @@ impl TerminalInterface {
             UnitEvent::Shutdown => println!("Shutting down"),
             UnitEvent::Log(log) => println!("{}", log),
             UnitEvent::ManagerRequest(_) => (),
+            UnitEvent::ManagerStatus(_) => (),
         };
     }
 
@@ -1,25 +1,46 @@
-use std::path::PathBuf;
-use std::sync::mpsc::{Receiver, RecvError, Sender};
+extern crate rusqlite;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvError, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use config::Config;
+use config::{command_with_environment, Config};
+use http::{HttpInterface, RequestError};
+use junit::{xml_escape, JUnitReporter};
+use resultsdb::ResultsDb;
 
-use unit::{UnitKind, UnitName};
-use unitbroadcaster::{UnitBroadcaster, UnitEvent, UnitStatus};
+use unit::{RestartPolicy, UnitKind, UnitName};
+use unitbroadcaster::{LogEntry, UnitBroadcaster, UnitEvent, UnitStatus};
 use unitlibrary::UnitLibrary;
-use unitmanager::{ManagerControlMessage, ManagerControlMessageContents};
+use unitmanager::{
+    ManagerControlMessage, ManagerControlMessageContents, ManagerStatusMessage, Measurement,
+    RunMetadata,
+};
 
+use units::interface::InterfaceDescription;
 use units::jig::JigDescription;
-use units::scenario::ScenarioDescription;
+#[cfg(unix)]
+use units::listener::{ListenAddress, Listener};
+use units::logger::{Logger, LoggerDescription};
+use units::scenario::{Inclusion, ScenarioDescription};
 use units::test::TestDescription;
+use units::trigger::Trigger;
 
 struct Exclave {
     broadcaster: UnitBroadcaster,
     receiver: Receiver<UnitEvent>,
     control: Sender<ManagerControlMessage>,
+    config: Arc<Mutex<Config>>,
     library: UnitLibrary,
+    scenarios: RefCell<HashMap<UnitName, ScenarioDescription>>,
 }
 
 const GENERIC_JIG: &str = r##"
@@ -43,7 +64,7 @@ fn oneliner_write_sleep_write_exit(
     stop: &str,
     ret: Option<u32>,
 ) -> String {
-    let retcode = if let Some(r) = ret { r } else { 0 };
+    let retcode = ret.unwrap_or_default();
 
     if let Some(d) = delay {
         format!(
@@ -72,7 +93,7 @@ fn oneliner_write_sleep_write_exit(
     stop: &str,
     ret: Option<u32>,
 ) -> String {
-    let retcode = if let Some(r) = ret { r } else { 0 };
+    let retcode = ret.unwrap_or_default();
 
     if let Some(d) = delay {
         format!(
@@ -123,6 +144,8 @@ impl Exclave {
             library,
             receiver,
             control,
+            config,
+            scenarios: RefCell::new(HashMap::new()),
         }
     }
 
@@ -146,12 +169,14 @@ impl Exclave {
                 self.library.get_manager().borrow().load_jig(&desc).unwrap();
             }
             UnitKind::Scenario => {
-                let desc = ScenarioDescription::from_string(
+                let mut desc = ScenarioDescription::from_string(
                     unit_text,
-                    name,
+                    name.clone(),
                     &PathBuf::from("test/config"),
                 )
                 .unwrap();
+                desc.resolve_includes(&self.scenarios.borrow());
+                self.scenarios.borrow_mut().insert(name, desc.clone());
                 self.library
                     .get_manager()
                     .borrow()
@@ -182,6 +207,15 @@ impl Exclave {
             .expect("interface couldn't send exit message to controller");
     }
 
+    pub fn resume_scenario(&self, name: &UnitName, serial: Option<&str>) {
+        self.control
+            .send(ManagerControlMessage::new(
+                name,
+                ManagerControlMessageContents::ResumeScenario(serial.map(|s| s.to_owned())),
+            ))
+            .expect("interface couldn't send resume message to controller");
+    }
+
     pub fn run_once(&self) -> Result<UnitEvent, RecvError> {
         let msg = self.receiver.recv()?;
         self.library.process_message(&msg);
@@ -287,6 +321,7 @@ ExecStop={}
                 }
             }
             // If a "STOP" event is received before the command is run, that's a problem.
+            #[allow(clippy::collapsible_match)]
             UnitEvent::Status(ref s) => {
                 if s.name.kind() == &UnitKind::Scenario {
                     if let UnitStatus::DeactivatedSuccessfully(ref msg) = s.status {
@@ -357,6 +392,7 @@ ExecStopFailure={}
                 }
             }
             // If a "STOP" event is received before the command is run, that's a problem.
+            #[allow(clippy::collapsible_match)]
             UnitEvent::Status(ref s) => {
                 if s.name.kind() == &UnitKind::Scenario {
                     if let UnitStatus::DeactivatedSuccessfully(ref msg) = s.status {
@@ -427,6 +463,7 @@ ExecStopFailure={}
                 }
             }
             // If a "STOP" event is received before the command is run, that's a problem.
+            #[allow(clippy::collapsible_match)]
             UnitEvent::Status(ref s) => {
                 if s.name.kind() == &UnitKind::Scenario {
                     if let UnitStatus::DeactivatedSuccessfully(ref msg) = s.status {
@@ -443,73 +480,2486 @@ ExecStopFailure={}
 }
 
 #[test]
-/// Test that "Requires=" works.
-/// Create a test "test-dependent" that
-fn test_requires() {
+/// A test's ExecStopSuccess runs before its result is reported, and a
+/// failing stop command fails the test.
+fn test_execstopsuccess_failure() {
     let exclave = Exclave::new(None);
+    let scenario = UnitName::from_str("teststop", "scenario").unwrap();
+    let test_name = UnitName::from_str("stoptest", "test").unwrap();
 
-    let scenario_name = UnitName::from_str("scenario", "scenario").unwrap();
-    let master_name = UnitName::from_str("master", "test").unwrap();
-    let dependent_name = UnitName::from_str("dependent", "test").unwrap();
+    exclave.add_unit(
+        &test_name,
+        &format!(
+            r##"[Test]
+Name=Stop test
+Description=Run something after the test passes
+ExecStart={}
+ExecStopSuccess={}
+"##,
+            oneliner_write_sleep_write_exit("begin", None, "end", None),
+            oneliner_write_sleep_write_exit("stop-starting", None, "stop-ending", Some(3))
+        ),
+    );
+    exclave.add_unit(
+        &scenario,
+        r##"[Scenario]
+Name=Test Stop Scenario
+Description=Run a test with a stop command
+Tests=stoptest
+"##,
+    );
+    exclave.rescan();
+
+    exclave.start_scenario(&scenario);
+
+    let mut stop_ran = false;
+    loop {
+        let msg = exclave.run_once().unwrap();
+        println!("Message: {:?}", msg);
+        if let UnitEvent::ManagerRequest(ManagerControlMessage {
+            ref sender,
+            ref contents,
+        }) = msg
+        {
+            if *sender != test_name {
+                continue;
+            }
+            match *contents {
+                ManagerControlMessageContents::Log(ref line) if line == "stop-ending" => {
+                    stop_ran = true
+                }
+                ManagerControlMessageContents::TestFinished(code, _) => {
+                    assert!(stop_ran, "test finished before its stop command ran");
+                    assert_eq!(code, 3);
+                    return;
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+#[test]
+#[cfg(unix)]
+/// A test that fails once and then passes is reported as a retry, followed by a pass.
+fn test_retry() {
+    let exclave = Exclave::new(None);
+    let scenario = UnitName::from_str("retry", "scenario").unwrap();
+    let test_name = UnitName::from_str("flaky", "test").unwrap();
+    let marker = std::env::temp_dir().join(format!("exclave-retry-{}", std::process::id()));
+    std::fs::remove_file(&marker).ok();
 
     exclave.add_unit(
-        &dependent_name,
-        &make_sleep_test("begin-dependent", None, "end-dependent", None),
+        &test_name,
+        &format!(
+            r##"[Test]
+Name=Flaky test
+Description=Fail the first time, and pass the second
+ExecStart=/bin/sh -c "if [ -e {0} ]; then exit 0; fi; touch {0}; exit 3"
+Retries=2
+RetryOn=3
+"##,
+            marker.display()
+        ),
+    );
+    exclave.add_unit(
+        &scenario,
+        r##"[Scenario]
+Name=Retry Scenario
+Description=Run a flaky test
+Tests=flaky
+"##,
     );
+    exclave.rescan();
 
-    let mut master_test = make_sleep_test("begin-master", None, "end-master", None);
-    master_test.push_str("\nRequires=dependent");
-    exclave.add_unit(&master_name, &master_test);
+    exclave.start_scenario(&scenario);
+
+    let mut retried = false;
+    loop {
+        let msg = exclave.run_once().unwrap();
+        println!("Message: {:?}", msg);
+        match msg {
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Retry(ref test, attempt, code, _)) => {
+                assert_eq!(*test, test_name);
+                assert_eq!((attempt, code), (1, 3));
+                retried = true;
+            }
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Fail(_, _, _)) => {
+                panic!("flaky test was not retried")
+            }
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Pass(ref test, _)) => {
+                assert_eq!(*test, test_name);
+                assert!(retried, "test passed without being retried");
+                break;
+            }
+            _ => (),
+        }
+    }
+    std::fs::remove_file(&marker).ok();
+}
+
+#[test]
+#[cfg(unix)]
+/// Aborting a run should stop a test from being retried, even if it's
+/// waiting for its RetryDelay to pass.
+fn abort_retry() {
+    let exclave = Exclave::new(None);
+    let scenario = UnitName::from_str("abortretry", "scenario").unwrap();
+    let test_name = UnitName::from_str("broken", "test").unwrap();
+    let marker = std::env::temp_dir().join(format!("exclave-abort-retry-{}", std::process::id()));
+    std::fs::remove_file(&marker).ok();
 
     exclave.add_unit(
-        &scenario_name,
+        &test_name,
+        &format!(
+            r##"[Test]
+Name=Broken test
+Description=Always fail
+ExecStart=/bin/sh -c "echo run >> {}; exit 3"
+Retries=3
+RetryDelay=1s
+"##,
+            marker.display()
+        ),
+    );
+    exclave.add_unit(
+        &scenario,
         r##"[Scenario]
-Name=Exec Stop Test
-Description=Run something on stop
-Tests=master
+Name=Abort Retry Scenario
+Description=Abort a test that is being retried
+Tests=broken
 "##,
     );
     exclave.rescan();
 
-    exclave.start_scenario(&scenario_name);
-    // Ensure dependent_seen goes `true` before master_seen does.
-    let mut _master_seen = false;
-    let mut dependent_seen = false;
+    exclave.start_scenario(&scenario);
+
+    loop {
+        match exclave.run_once().unwrap() {
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Retry(..)) => {
+                exclave
+                    .control
+                    .send(ManagerControlMessage::new(
+                        &scenario,
+                        ManagerControlMessageContents::AbortTests,
+                    ))
+                    .unwrap();
+            }
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Pass(..)) => {
+                panic!("broken test passed")
+            }
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Finished(_, result, _, _)) => {
+                assert_ne!(result, 200);
+                break;
+            }
+            _ => (),
+        }
+    }
+
+    // Let the RetryDelay go by, and make sure the test isn't started again.
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        if let Ok(msg) = exclave.receiver.recv_timeout(remaining) {
+            exclave.library.process_message(&msg);
+            if let UnitEvent::ManagerStatus(ManagerStatusMessage::Running(_)) = msg {
+                panic!("test was retried after the run was aborted");
+            }
+        }
+    }
+    assert_eq!(std::fs::read_to_string(&marker).unwrap(), "run\n");
+    std::fs::remove_file(&marker).ok();
+}
+
+#[test]
+#[cfg(unix)]
+/// Variables from the scenario and the test both reach the test, with the test's winning.
+fn test_environment() {
+    let exclave = Exclave::new(None);
+    let scenario = UnitName::from_str("envscenario", "scenario").unwrap();
+    let test_name = UnitName::from_str("env", "test").unwrap();
+
+    exclave.add_unit(
+        &test_name,
+        r##"[Test]
+Name=Environment test
+Description=Check the variables set by the scenario and the test
+ExecStart=/bin/sh -c "test $GREETING.$SHARED.$EXCLAVE_TEST.$EXCLAVE_SCENARIO = hello.test.env.envscenario"
+Environment="SHARED=test"
+"##,
+    );
+    exclave.add_unit(
+        &scenario,
+        r##"[Scenario]
+Name=Environment Scenario
+Description=Pass variables on to a test
+Tests=env
+Environment=GREETING=hello
+Environment=SHARED=scenario
+"##,
+    );
+    exclave.rescan();
+
+    exclave.start_scenario(&scenario);
+
     loop {
         let msg = exclave.run_once().unwrap();
         println!("Message: {:?}", msg);
         match msg {
-            UnitEvent::ManagerRequest(ref mrq) => {
-                let ManagerControlMessage {
-                    sender: ref sender_name,
-                    contents: ref msg,
-                } = mrq;
-                if let ManagerControlMessageContents::Log(ref string) = msg {
-                    if *sender_name == dependent_name && string == "end-dependent" {
-                        assert!(!_master_seen);
-                        assert!(!dependent_seen);
-                        dependent_seen = true;
-                    }
-                    if *sender_name == master_name && string == "begin-master" {
-                        assert!(!_master_seen);
-                        assert!(dependent_seen);
-                        _master_seen = true;
-                        return;
-                    }
-                }
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Fail(_, code, _)) => {
+                panic!("test did not see its environment (result {})", code)
             }
-            // If a "STOP" event is received before the command is run, that's a problem.
-            UnitEvent::Status(ref s) => {
-                if s.name.kind() == &UnitKind::Scenario {
-                    if let UnitStatus::DeactivatedSuccessfully(ref msg) = s.status {
-                        panic!(
-                            "unit {} deactivated before strings were found (success: {})",
-                            s.name, msg
-                        );
-                    }
-                }
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Pass(ref test, _)) => {
+                assert_eq!(*test, test_name);
+                break;
+            }
+            _ => (),
+        }
+    }
+}
+
+#[test]
+#[cfg(unix)]
+/// Variables should reach the program exactly as given, without passing
+/// through our own environment.
+fn environment_quoting() {
+    let tricky = r#"it's a "test" of \back\\slashes, $HOME and `ls`"#;
+    let vars = vec![
+        ("EXCLAVE_TRICKY".to_owned(), tricky.to_owned()),
+        ("EXCLAVE_EMPTY".to_owned(), "".to_owned()),
+    ];
+    let mut running = command_with_environment(
+        "/bin/sh -c 'printf \"%s|%s\" \"$EXCLAVE_TRICKY\" \"${EXCLAVE_EMPTY-unset}\"'",
+        &vars,
+    )
+    .start()
+    .unwrap();
+    let mut output = String::new();
+    running.read_to_string(&mut output).unwrap();
+    assert_eq!(output, format!("{}|", tricky));
+    assert!(std::env::var_os("EXCLAVE_TRICKY").is_none());
+}
+
+#[test]
+#[cfg(unix)]
+/// Measurements are checked against the test's limits, and one that's out of limits fails the test.
+fn test_measurement_limits() {
+    let exclave = Exclave::new(None);
+    let scenario = UnitName::from_str("measure", "scenario").unwrap();
+    let test_name = UnitName::from_str("power", "test").unwrap();
+
+    exclave.add_unit(
+        &test_name,
+        r##"[Test]
+Name=Power test
+Description=Report a good voltage and a bad current
+ExecStart=/bin/sh -c "echo MEASURE vbat 3.3 V; echo MEASURE ibat 0.9"
+Measure=vbat Low=3.0 High=3.6 Units=V
+Measure=ibat High=0.5
+"##,
+    );
+    exclave.add_unit(
+        &scenario,
+        r##"[Scenario]
+Name=Measurement Scenario
+Description=Run a test that measures things
+Tests=power
+"##,
+    );
+    exclave.rescan();
+
+    exclave.start_scenario(&scenario);
+
+    let mut measurements = vec![];
+    loop {
+        let msg = exclave.run_once().unwrap();
+        println!("Message: {:?}", msg);
+        match msg {
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Measurement(ref test, ref m)) => {
+                assert_eq!(*test, test_name);
+                measurements.push((m.name.clone(), m.passed));
+            }
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Pass(_, _)) => {
+                panic!("test passed with a measurement out of limits")
+            }
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Fail(ref test, _, ref message)) => {
+                assert_eq!(*test, test_name);
+                assert_eq!(message, "measurement out of limits: ibat");
+                break;
+            }
+            _ => (),
+        }
+    }
+    assert_eq!(
+        measurements,
+        vec![("vbat".to_owned(), true), ("ibat".to_owned(), false)]
+    );
+}
+
+#[test]
+#[cfg(unix)]
+/// A measurement below its Low limit, or reported in units other than the
+/// ones its limits are given in, fails the test.
+fn test_measurement_units() {
+    let exclave = Exclave::new(Some(Duration::from_secs(30)));
+    let scenario = UnitName::from_str("units", "scenario").unwrap();
+    let test_name = UnitName::from_str("units", "test").unwrap();
+
+    exclave.add_unit(
+        &test_name,
+        r##"[Test]
+Name=Units test
+Description=Report measurements in the wrong units, or too low
+ExecStart=/bin/sh -c "echo MEASURE vbat 3300 mV; echo MEASURE temp -5 C; echo MEASURE vref 1.2 V"
+Measure=vbat Low=3.0 High=3.6 Units=V
+Measure=temp Low=0 High=70
+Measure=vref Low=1.1 High=1.3 Units=V
+"##,
+    );
+    exclave.add_unit(
+        &scenario,
+        r##"[Scenario]
+Name=Units Scenario
+Description=Run a test that measures things in the wrong units
+Tests=units
+"##,
+    );
+    exclave.rescan();
+
+    exclave.start_scenario(&scenario);
+
+    let mut measurements = vec![];
+    loop {
+        let msg = exclave.run_once().unwrap();
+        println!("Message: {:?}", msg);
+        match msg {
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Measurement(_, ref m)) => {
+                measurements.push((m.name.clone(), m.units.clone(), m.passed));
+            }
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Pass(_, _)) => {
+                panic!("test passed with measurements out of limits")
+            }
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Fail(ref test, _, ref message)) => {
+                assert_eq!(*test, test_name);
+                assert_eq!(message, "measurement out of limits: vbat, temp");
+                break;
+            }
+            UnitEvent::Shutdown => panic!("test never finished"),
+            _ => (),
+        }
+    }
+    assert_eq!(
+        measurements,
+        vec![
+            ("vbat".to_owned(), Some("mV".to_owned()), false),
+            ("temp".to_owned(), Some("C".to_owned()), false),
+            ("vref".to_owned(), Some("V".to_owned()), true),
+        ]
+    );
+}
+
+#[test]
+#[cfg(unix)]
+/// With an output directory, each run gets a directory named after the
+/// scenario, holding what each test printed and any files the tests left.
+fn run_directory_layout() {
+    let exclave = Exclave::new(Some(Duration::from_secs(30)));
+    let output = std::env::temp_dir().join(format!("exclave-output-{}", std::process::id()));
+    exclave.config.lock().unwrap().set_output_directory(&output);
+    let scenario = UnitName::from_str("bundle", "scenario").unwrap();
+
+    exclave.add_unit(
+        &UnitName::from_str("chatty", "test").unwrap(),
+        r##"[Test]
+Name=Chatty
+Description=Print to stdout and stderr
+ExecStart=/bin/sh -c "echo first; echo second; echo complaint >&2"
+"##,
+    );
+    exclave.add_unit(
+        &UnitName::from_str("dump", "test").unwrap(),
+        r##"[Test]
+Name=Dump
+Description=Leave a file in the run directory
+ExecStart=/bin/sh -c "echo flash contents > $EXCLAVE_RUN_DIR/flash.bin"
+"##,
+    );
+    exclave.add_unit(
+        &scenario,
+        r##"[Scenario]
+Name=Bundle Scenario
+Description=Collect everything in the run directory
+Tests=chatty dump
+"##,
+    );
+    exclave.rescan();
+
+    exclave.start_scenario(&scenario);
+
+    let run_directory = loop {
+        let msg = exclave.run_once().unwrap();
+        println!("Message: {:?}", msg);
+        match msg {
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Finished(_, result, _, dir)) => {
+                assert_eq!(result, 200);
+                break dir.expect("run had no directory");
+            }
+            UnitEvent::Shutdown => panic!("scenario never finished"),
+            _ => (),
+        }
+    };
+
+    assert_eq!(
+        run_directory.parent().unwrap(),
+        output.canonicalize().unwrap()
+    );
+    let name = run_directory.file_name().unwrap().to_string_lossy();
+    assert!(name.starts_with("bundle-"), "unexpected name {}", name);
+
+    let mut files: Vec<String> = std::fs::read_dir(&run_directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        vec![
+            "chatty.stderr",
+            "chatty.stdout",
+            "dump.stderr",
+            "dump.stdout",
+            "flash.bin"
+        ]
+    );
+    let read = |file: &str| std::fs::read_to_string(run_directory.join(file)).unwrap();
+    assert_eq!(read("chatty.stdout"), "first\nsecond\n");
+    assert_eq!(read("chatty.stderr"), "complaint\n");
+    assert_eq!(read("dump.stdout"), "");
+    assert_eq!(read("flash.bin"), "flash contents\n");
+    std::fs::remove_dir_all(&output).ok();
+}
+
+#[test]
+/// Without an output directory, runs don't create a directory at all.
+fn run_directory_disabled() {
+    let exclave = Exclave::new(Some(Duration::from_secs(30)));
+    let scenario = UnitName::from_str("simple", "scenario").unwrap();
+    exclave.add_unit(
+        &UnitName::from_str("quiet", "test").unwrap(),
+        r##"[Test]
+Name=Quiet
+Description=Do nothing
+ExecStart=true
+"##,
+    );
+    exclave.add_unit(
+        &scenario,
+        r##"[Scenario]
+Name=Simple Scenario
+Description=Run one test
+Tests=quiet
+"##,
+    );
+    exclave.rescan();
+    assert_eq!(exclave.config.lock().unwrap().run_directory(), None);
+
+    exclave.start_scenario(&scenario);
+
+    loop {
+        let msg = exclave.run_once().unwrap();
+        println!("Message: {:?}", msg);
+        match msg {
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Finished(_, result, _, dir)) => {
+                assert_eq!(result, 200);
+                assert_eq!(dir, None);
+                break;
+            }
+            UnitEvent::Log(ref entry) if entry.message().starts_with("run directory") => {
+                panic!("a run directory was created: {}", entry.message())
             }
+            UnitEvent::Shutdown => panic!("scenario never finished"),
             _ => (),
         }
     }
 }
+
+#[test]
+#[cfg(unix)]
+/// A daemon's measurements are all read before its result is decided, so
+/// one reported just before it exits still fails it.
+fn daemon_measurement_limits() {
+    let exclave = Exclave::new(Some(Duration::from_secs(30)));
+    let scenario = UnitName::from_str("daemon", "scenario").unwrap();
+    let daemon = UnitName::from_str("monitor", "test").unwrap();
+    let waiter = UnitName::from_str("wait", "test").unwrap();
+
+    exclave.add_unit(
+        &daemon,
+        r##"[Test]
+Name=Power monitor
+Description=Watch the supply in the background
+ExecStart=/bin/sh -c "echo monitor ready; sleep 0.2; echo MEASURE vbat 2.5 V"
+Type=daemon
+DaemonReadyText=monitor ready
+Measure=vbat Low=3.0 High=3.6 Units=V
+"##,
+    );
+    exclave.add_unit(
+        &waiter,
+        r##"[Test]
+Name=Wait
+Description=Outlast the monitor
+ExecStart=sleep 1
+"##,
+    );
+    exclave.add_unit(
+        &scenario,
+        r##"[Scenario]
+Name=Daemon Scenario
+Description=Run a monitor alongside another test
+Tests=monitor wait
+"##,
+    );
+    exclave.rescan();
+
+    exclave.start_scenario(&scenario);
+
+    loop {
+        let msg = exclave.run_once().unwrap();
+        println!("Message: {:?}", msg);
+        match msg {
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Pass(ref test, _))
+                if *test == daemon =>
+            {
+                panic!("daemon passed with a measurement out of limits")
+            }
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Fail(ref test, _, ref message))
+                if *test == daemon =>
+            {
+                assert_eq!(message, "measurement out of limits: vbat");
+                break;
+            }
+            UnitEvent::Shutdown => panic!("daemon never finished"),
+            _ => (),
+        }
+    }
+}
+
+#[test]
+#[cfg(unix)]
+/// A variable set by one test is in the environment of the tests after it.
+fn test_set_variable() {
+    let exclave = Exclave::new(None);
+    let scenario = UnitName::from_str("variables", "scenario").unwrap();
+    let writer = UnitName::from_str("read-serial", "test").unwrap();
+    let reader = UnitName::from_str("check-serial", "test").unwrap();
+
+    exclave.add_unit(
+        &writer,
+        r##"[Test]
+Name=Read serial
+Description=Read the serial number
+ExecStart=echo SET BOARD_SERIAL A1234
+"##,
+    );
+    exclave.add_unit(
+        &reader,
+        r##"[Test]
+Name=Check serial
+Description=Make sure the serial number was passed on
+ExecStart=/bin/sh -c "test $BOARD_SERIAL = A1234"
+Requires=read-serial
+"##,
+    );
+    exclave.add_unit(
+        &scenario,
+        r##"[Scenario]
+Name=Variable Scenario
+Description=Pass a variable from one test to another
+Tests=read-serial check-serial
+"##,
+    );
+    exclave.rescan();
+
+    exclave.start_scenario(&scenario);
+
+    let mut set = false;
+    loop {
+        let msg = exclave.run_once().unwrap();
+        println!("Message: {:?}", msg);
+        match msg {
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Set(ref test, ref key, ref value)) => {
+                assert_eq!(*test, writer);
+                assert_eq!((key.as_str(), value.as_str()), ("BOARD_SERIAL", "A1234"));
+                set = true;
+            }
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Fail(ref test, _, _)) => {
+                panic!("test {} failed", test)
+            }
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Pass(ref test, _))
+                if *test == reader =>
+            {
+                assert!(set, "variable was never set");
+                break;
+            }
+            _ => (),
+        }
+    }
+}
+
+#[test]
+/// Test that "Requires=" works.
+/// Create a test "test-dependent" that
+fn test_requires() {
+    let exclave = Exclave::new(None);
+
+    let scenario_name = UnitName::from_str("scenario", "scenario").unwrap();
+    let master_name = UnitName::from_str("master", "test").unwrap();
+    let dependent_name = UnitName::from_str("dependent", "test").unwrap();
+
+    exclave.add_unit(
+        &dependent_name,
+        &make_sleep_test("begin-dependent", None, "end-dependent", None),
+    );
+
+    let mut master_test = make_sleep_test("begin-master", None, "end-master", None);
+    master_test.push_str("\nRequires=dependent");
+    exclave.add_unit(&master_name, &master_test);
+
+    exclave.add_unit(
+        &scenario_name,
+        r##"[Scenario]
+Name=Exec Stop Test
+Description=Run something on stop
+Tests=master
+"##,
+    );
+    exclave.rescan();
+
+    exclave.start_scenario(&scenario_name);
+    // Ensure dependent_seen goes `true` before master_seen does.
+    let mut _master_seen = false;
+    let mut dependent_seen = false;
+    loop {
+        let msg = exclave.run_once().unwrap();
+        println!("Message: {:?}", msg);
+        match msg {
+            UnitEvent::ManagerRequest(ref mrq) => {
+                let ManagerControlMessage {
+                    sender: ref sender_name,
+                    contents: ref msg,
+                } = mrq;
+                if let ManagerControlMessageContents::Log(ref string) = msg {
+                    if *sender_name == dependent_name && string == "end-dependent" {
+                        assert!(!_master_seen);
+                        assert!(!dependent_seen);
+                        dependent_seen = true;
+                    }
+                    if *sender_name == master_name && string == "begin-master" {
+                        assert!(!_master_seen);
+                        assert!(dependent_seen);
+                        _master_seen = true;
+                        return;
+                    }
+                }
+            }
+            // If a "STOP" event is received before the command is run, that's a problem.
+            #[allow(clippy::collapsible_match)]
+            UnitEvent::Status(ref s) => {
+                if s.name.kind() == &UnitKind::Scenario {
+                    if let UnitStatus::DeactivatedSuccessfully(ref msg) = s.status {
+                        panic!(
+                            "unit {} deactivated before strings were found (success: {})",
+                            s.name, msg
+                        );
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+#[test]
+/// Ensure status messages are encoded as flat JSON objects for interfaces.
+fn json_status_messages() {
+    let test_name = UnitName::from_str("flash", "test").unwrap();
+    let scenario_name = UnitName::from_str("final", "scenario").unwrap();
+
+    let fail = ManagerStatusMessage::Fail(test_name.clone(), 3, "no ack".to_owned());
+    assert_eq!(
+        fail.to_json().to_string(),
+        r#"{"code":3,"message":"no ack","test":"flash","type":"fail"}"#
+    );
+
+    let tests = ManagerStatusMessage::Tests(scenario_name.clone(), vec![test_name]);
+    let tests = tests.to_json();
+    assert_eq!(tests["type"], "tests");
+    assert_eq!(tests["scenario"], "final");
+    assert_eq!(tests["tests"][0], "flash");
+
+    let jig = ManagerStatusMessage::Jig(None).to_json();
+    assert!(jig["jig"].is_null());
+
+    let ping = ManagerStatusMessage::Ping(7);
+    assert_eq!(ping.to_json().to_string(), r#"{"id":7,"type":"ping"}"#);
+
+    let mut metadata = RunMetadata::new();
+    metadata.insert("serial".to_owned(), "A1234567".to_owned());
+    let start = ManagerStatusMessage::Start(scenario_name, metadata).to_json();
+    assert_eq!(start["metadata"]["serial"], "A1234567");
+}
+
+#[test]
+/// Run a single test without its requirements, and make sure only that test runs.
+fn run_single_test() {
+    let exclave = Exclave::new(None);
+
+    let master_name = UnitName::from_str("master", "test").unwrap();
+    let dependent_name = UnitName::from_str("dependent", "test").unwrap();
+
+    exclave.add_unit(
+        &dependent_name,
+        &make_sleep_test("begin-dependent", None, "end-dependent", None),
+    );
+
+    let mut master_test = make_sleep_test("begin-master", None, "end-master", None);
+    master_test.push_str("\nRequires=dependent");
+    exclave.add_unit(&master_name, &master_test);
+    exclave.rescan();
+
+    exclave
+        .control
+        .send(ManagerControlMessage::new(
+            &UnitName::internal("test"),
+            ManagerControlMessageContents::RunTest(master_name.clone(), false),
+        ))
+        .unwrap();
+
+    let mut master_seen = false;
+    loop {
+        let msg = exclave.run_once().unwrap();
+        println!("Message: {:?}", msg);
+        if let UnitEvent::ManagerRequest(ref mrq) = msg {
+            let ManagerControlMessage {
+                sender: ref sender_name,
+                contents: ref msg,
+            } = mrq;
+            match msg {
+                ManagerControlMessageContents::Log(ref string) => {
+                    assert!(*sender_name != dependent_name);
+                    if *sender_name == master_name && string == "end-master" {
+                        master_seen = true;
+                    }
+                }
+                ManagerControlMessageContents::ScenarioFinished(code, _) => {
+                    assert_eq!(*code, 200);
+                    assert!(master_seen);
+                    return;
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+#[test]
+/// The scenario made up to run a single test mustn't be mistaken for a real
+/// scenario that has the same name as the test.
+fn run_single_test_name() {
+    let exclave = Exclave::new(Some(Duration::from_secs(30)));
+
+    let test_name = UnitName::from_str("solo", "test").unwrap();
+    let scenario_name = UnitName::from_str("solo", "scenario").unwrap();
+    exclave.add_unit(
+        &test_name,
+        &make_sleep_test("begin-solo", None, "end-solo", None),
+    );
+    exclave.add_unit(
+        &scenario_name,
+        "[Scenario]\nName=Solo\nDescription=A real scenario\nTests=solo\n",
+    );
+    exclave.rescan();
+
+    exclave
+        .control
+        .send(ManagerControlMessage::new(
+            &UnitName::internal("test"),
+            ManagerControlMessageContents::RunTest(test_name.clone(), false),
+        ))
+        .unwrap();
+
+    let mut started = None;
+    loop {
+        let msg = exclave.run_once().unwrap();
+        println!("Message: {:?}", msg);
+        match msg {
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Start(ref id, _)) => {
+                assert_ne!(*id, scenario_name);
+                assert_eq!(*id.kind(), UnitKind::Internal);
+                started = Some(id.clone());
+            }
+            UnitEvent::ManagerRequest(ManagerControlMessage {
+                contents: ManagerControlMessageContents::ScenarioFinished(code, _),
+                ..
+            }) => {
+                assert_eq!(code, 200);
+                break;
+            }
+            UnitEvent::Shutdown => panic!("test never finished"),
+            _ => (),
+        }
+    }
+    let started = started.expect("no START was sent");
+    assert!(!exclave
+        .library
+        .get_manager()
+        .borrow()
+        .get_scenarios()
+        .borrow()
+        .contains_key(&started));
+}
+
+#[test]
+#[cfg(unix)]
+/// A test that asks a question nobody answers should get its PromptDefault.
+fn prompt_default_answer() {
+    let exclave = Exclave::new(None);
+    let scenario_name = UnitName::from_str("prompt", "scenario").unwrap();
+    let test_name = UnitName::from_str("asker", "test").unwrap();
+
+    exclave.add_unit(
+        &test_name,
+        r##"[Test]
+Name=Ask a question
+Description=Ask the operator for a color, and print the answer
+ExecStart=/bin/sh -c "echo 'ASK What color is the LED?'; read color; echo got-$color"
+PromptTimeout=1
+PromptDefault=green
+"##,
+    );
+    exclave.add_unit(
+        &scenario_name,
+        r##"[Scenario]
+Name=Prompt Scenario
+Description=Ask a question
+Tests=asker
+"##,
+    );
+    exclave.rescan();
+
+    exclave.start_scenario(&scenario_name);
+    let mut asked = false;
+    loop {
+        let msg = exclave.run_once().unwrap();
+        println!("Message: {:?}", msg);
+        match msg {
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Ask(_, ref name, ref question)) => {
+                assert_eq!(*name, test_name);
+                assert_eq!(question, "What color is the LED?");
+                asked = true;
+            }
+            UnitEvent::ManagerRequest(ManagerControlMessage {
+                ref sender,
+                contents: ManagerControlMessageContents::Log(ref string),
+            }) if *sender == test_name && string == "got-green" => {
+                assert!(asked);
+                return;
+            }
+            _ => (),
+        }
+    }
+}
+
+#[test]
+/// After a scenario finishes, STATUS should report it and each of its tests.
+fn status_snapshot() {
+    let exclave = Exclave::new(None);
+    let three_name = UnitName::from_str("three", "scenario").unwrap();
+
+    for n in 1..=3 {
+        exclave.add_unit(
+            &UnitName::from_str(&format!("test{}", n), "test").unwrap(),
+            &make_sleep_test("start", None, "end", if n == 2 { Some(1) } else { None }),
+        );
+    }
+    exclave.add_unit(&three_name, THREE_TEST_SCENARIO);
+    exclave.rescan();
+
+    exclave.start_scenario(&three_name);
+    exclave.wait_for_deactivate(&three_name).unwrap();
+
+    exclave
+        .control
+        .send(ManagerControlMessage::new(
+            &UnitName::internal("test"),
+            ManagerControlMessageContents::Status,
+        ))
+        .unwrap();
+
+    let mut test_states = vec![];
+    loop {
+        match exclave.run_once().unwrap() {
+            UnitEvent::ManagerStatus(ManagerStatusMessage::ScenarioStatus(
+                ref name,
+                ref state,
+                elapsed,
+                timeout,
+            )) => {
+                assert_eq!(*name, three_name);
+                assert_eq!(state, "finished");
+                assert!(elapsed.is_some());
+                assert_eq!(timeout, Some(Duration::from_secs(200)));
+            }
+            UnitEvent::ManagerStatus(ManagerStatusMessage::TestStatus(
+                ref name,
+                ref state,
+                _,
+                _,
+                _,
+            )) => {
+                test_states.push((name.id().clone(), state.clone()));
+                if test_states.len() == 3 {
+                    break;
+                }
+            }
+            _ => (),
+        }
+    }
+    assert_eq!(
+        test_states,
+        vec![
+            ("test1".to_owned(), "pass".to_owned()),
+            ("test2".to_owned(), "fail".to_owned()),
+            ("test3".to_owned(), "pass".to_owned()),
+        ]
+    );
+}
+
+#[test]
+/// Tests in a MaxParallel scenario should overlap, except for exclusive ones.
+fn parallel_tests() {
+    let exclave = Exclave::new(None);
+    let scenario = UnitName::from_str("parallel", "scenario").unwrap();
+    let first = UnitName::from_str("first", "test").unwrap();
+    let second = UnitName::from_str("second", "test").unwrap();
+    let alone = UnitName::from_str("alone", "test").unwrap();
+
+    for (name, exclusive) in &[(&first, "no"), (&second, "no"), (&alone, "yes")] {
+        exclave.add_unit(
+            name,
+            &format!(
+                r##"[Test]
+Name={}
+Description=Sleep for a moment
+ExecStart=sleep 1
+Exclusive={}
+"##,
+                name.id(),
+                exclusive
+            ),
+        );
+    }
+    exclave.add_unit(
+        &scenario,
+        r##"[Scenario]
+Name=Parallel Scenario
+Description=Run two tests at once, then one on its own
+Tests=first second alone
+MaxParallel=3
+"##,
+    );
+    exclave.rescan();
+
+    exclave.start_scenario(&scenario);
+
+    let mut running = vec![];
+    let mut passed = 0;
+    loop {
+        let msg = exclave.run_once().unwrap();
+        println!("Message: {:?}", msg);
+        match msg {
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Running(ref test)) => {
+                if *test == alone {
+                    assert_eq!(passed, 2, "exclusive test started alongside others");
+                } else {
+                    assert_eq!(passed, 0, "tests did not run in parallel");
+                }
+                running.push(test.clone());
+            }
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Pass(_, _)) => passed += 1,
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Fail(ref test, _, _)) => {
+                panic!("test {} failed", test)
+            }
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Finished(_, result, _, _)) => {
+                assert_eq!(result, 200);
+                break;
+            }
+            _ => (),
+        }
+    }
+    assert_eq!(running, vec![first, second, alone]);
+}
+
+#[test]
+/// A test that runs past its scenario's Timeout should be stopped.
+fn scenario_timeout() {
+    let exclave = Exclave::new(None);
+    let scenario = UnitName::from_str("hurried", "scenario").unwrap();
+    let test = UnitName::from_str("slow", "test").unwrap();
+
+    exclave.add_unit(
+        &test,
+        r##"[Test]
+Name=Slow test
+Description=Take far longer than the scenario allows
+ExecStart=sleep 30
+"##,
+    );
+    exclave.add_unit(
+        &scenario,
+        r##"[Scenario]
+Name=Hurried Scenario
+Description=Give up after a second
+Tests=slow
+Timeout=1
+"##,
+    );
+    exclave.rescan();
+
+    let start = Instant::now();
+    exclave.start_scenario(&scenario);
+
+    let mut failed = false;
+    loop {
+        let msg = exclave.run_once().unwrap();
+        println!("Message: {:?}", msg);
+        match msg {
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Fail(ref name, _, _)) => {
+                assert_eq!(*name, test);
+                failed = true;
+            }
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Finished(_, result, reason, _)) => {
+                assert!(failed, "test was never stopped");
+                assert!(result > 500);
+                assert_eq!(reason, "scenario timeout");
+                break;
+            }
+            _ => (),
+        }
+    }
+    assert!(start.elapsed() < Duration::from_secs(10));
+}
+
+#[test]
+/// A run that finishes in time should cancel its Timeout watchdog.
+fn scenario_timeout_cancelled() {
+    let exclave = Exclave::new(Some(Duration::from_secs(3)));
+    let scenario = UnitName::from_str("punctual", "scenario").unwrap();
+
+    exclave.add_unit(
+        &UnitName::from_str("quick", "test").unwrap(),
+        "[Test]\nName=Quick test\nDescription=Finish straight away\nExecStart=true\n",
+    );
+    exclave.add_unit(
+        &scenario,
+        "[Scenario]\nName=Punctual Scenario\nDescription=Finish well within the timeout\nTests=quick\nTimeout=1\n",
+    );
+    exclave.rescan();
+    exclave.start_scenario(&scenario);
+
+    let mut finished = false;
+    loop {
+        let msg = exclave.run_once().unwrap();
+        println!("Message: {:?}", msg);
+        match msg {
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Finished(_, result, _, _)) => {
+                assert_eq!(result, 200);
+                finished = true;
+            }
+            UnitEvent::ManagerRequest(ManagerControlMessage {
+                contents: ManagerControlMessageContents::ScenarioTimedOut,
+                ..
+            }) => panic!("watchdog went off after the run finished"),
+            UnitEvent::Shutdown => break,
+            _ => (),
+        }
+    }
+    assert!(finished);
+}
+
+#[test]
+/// A scenario with Repeat= should run again until it has run that many times.
+fn repeat_scenario() {
+    let exclave = Exclave::new(None);
+    let scenario = UnitName::from_str("burn-in", "scenario").unwrap();
+
+    exclave.add_unit(
+        &UnitName::from_str("quick", "test").unwrap(),
+        r##"[Test]
+Name=Quick test
+Description=Pass straight away
+ExecStart=true
+"##,
+    );
+    exclave.add_unit(
+        &scenario,
+        r##"[Scenario]
+Name=Burn-in Scenario
+Description=Run the same test three times
+Tests=quick
+Repeat=3
+RepeatDelay=100ms
+"##,
+    );
+    exclave.rescan();
+
+    exclave.start_scenario(&scenario);
+
+    let mut starts = 0;
+    let mut finishes = 0;
+    loop {
+        let msg = exclave.run_once().unwrap();
+        println!("Message: {:?}", msg);
+        match msg {
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Start(_, _)) => starts += 1,
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Finished(_, result, _, _)) => {
+                assert_eq!(result, 200);
+                finishes += 1;
+            }
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Iteration(ref name, ref i)) => {
+                assert_eq!(*name, scenario);
+                assert_eq!(i.number, finishes);
+                assert!(i.passed);
+                assert_eq!((i.total_passed, i.total_failed), (finishes, 0));
+                if i.last {
+                    break;
+                }
+            }
+            _ => (),
+        }
+    }
+    assert_eq!((starts, finishes), (3, 3));
+}
+
+#[test]
+/// A scenario should run the tests and ExecStart commands of the scenarios it includes.
+fn include_scenarios() {
+    let exclave = Exclave::new(None);
+    let whole = UnitName::from_str("whole", "scenario").unwrap();
+    let flash = UnitName::from_str("flash", "test").unwrap();
+    let check = UnitName::from_str("check", "test").unwrap();
+
+    exclave.add_unit(
+        &flash,
+        r##"[Test]
+Name=Flash
+Description=Program the board
+ExecStart=true
+"##,
+    );
+    exclave.add_unit(
+        &check,
+        r##"[Test]
+Name=Check
+Description=Check the programmed board
+ExecStart=true
+Requires=flash
+"##,
+    );
+    exclave.add_unit(
+        &UnitName::from_str("programming", "scenario").unwrap(),
+        r##"[Scenario]
+Name=Programming
+Description=Program the board
+Tests=flash
+ExecStart=echo programming-start
+"##,
+    );
+    exclave.add_unit(
+        &UnitName::from_str("functional", "scenario").unwrap(),
+        r##"[Scenario]
+Name=Functional
+Description=Check a board that was programmed earlier
+Tests=check
+Assume=flash
+"##,
+    );
+    exclave.add_unit(
+        &whole,
+        r##"[Scenario]
+Name=Whole
+Description=Program and check the board
+Includes=programming functional
+ExecStart=echo whole-start
+"##,
+    );
+    exclave.rescan();
+
+    exclave.start_scenario(&whole);
+
+    let mut log = vec![];
+    loop {
+        let msg = exclave.run_once().unwrap();
+        println!("Message: {:?}", msg);
+        match msg {
+            UnitEvent::ManagerRequest(ManagerControlMessage {
+                ref sender,
+                contents: ManagerControlMessageContents::Log(ref line),
+            }) if *sender == whole && line.ends_with("-start") => log.push(line.clone()),
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Running(ref test)) => {
+                log.push(test.id().clone())
+            }
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Finished(_, result, _, _)) => {
+                assert_eq!(result, 200);
+                break;
+            }
+            _ => (),
+        }
+    }
+    assert_eq!(
+        log,
+        vec!["programming-start", "whole-start", "flash", "check"]
+    );
+}
+
+#[test]
+#[cfg(unix)]
+/// Resuming a failed run should only run the tests that didn't pass the first time.
+fn resume_scenario() {
+    let exclave = Exclave::new(None);
+    let scenario = UnitName::from_str("resume", "scenario").unwrap();
+    let program = UnitName::from_str("program", "test").unwrap();
+    let cable = UnitName::from_str("cable", "test").unwrap();
+    let verify = UnitName::from_str("verify", "test").unwrap();
+    let marker = std::env::temp_dir().join(format!("exclave-resume-{}", std::process::id()));
+    std::fs::remove_file(&marker).ok();
+
+    exclave.add_unit(
+        &program,
+        r##"[Test]
+Name=Program
+Description=Always passes
+ExecStart=true
+"##,
+    );
+    exclave.add_unit(
+        &cable,
+        &format!(
+            r##"[Test]
+Name=Cable
+Description=Passes once the cable is plugged in
+ExecStart=/bin/sh -c "test -e {}"
+Requires=program
+"##,
+            marker.display()
+        ),
+    );
+    exclave.add_unit(
+        &verify,
+        r##"[Test]
+Name=Verify
+Description=Needs the cable
+ExecStart=true
+Requires=cable
+"##,
+    );
+    exclave.add_unit(
+        &scenario,
+        r##"[Scenario]
+Name=Resume Scenario
+Description=Fail partway through, then resume
+Tests=verify
+"##,
+    );
+    exclave.rescan();
+
+    exclave.start_scenario(&scenario);
+    let mut ran = vec![];
+    let mut skipped = vec![];
+    loop {
+        let msg = exclave.run_once().unwrap();
+        println!("Message: {:?}", msg);
+        match msg {
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Running(ref test)) => {
+                ran.push(test.clone())
+            }
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Skipped(ref test, _)) => {
+                skipped.push(test.clone())
+            }
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Finished(_, result, _, _)) => {
+                assert_eq!(result, 501);
+                break;
+            }
+            _ => (),
+        }
+    }
+    assert_eq!(ran, vec![program.clone(), cable.clone()]);
+    assert_eq!(skipped, vec![verify.clone()]);
+
+    // Plug the cable in, and pick up where we left off.
+    std::fs::File::create(&marker).unwrap();
+    exclave.resume_scenario(&scenario, None);
+    let mut ran = vec![];
+    let mut resumed = false;
+    loop {
+        let msg = exclave.run_once().unwrap();
+        println!("Message: {:?}", msg);
+        match msg {
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Resumed(ref name, ref kept)) => {
+                assert_eq!(*name, scenario);
+                assert_eq!(*kept, vec![program.clone()]);
+                resumed = true;
+            }
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Running(ref test)) => {
+                ran.push(test.clone())
+            }
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Finished(_, result, _, _)) => {
+                assert_eq!(result, 200);
+                break;
+            }
+            _ => (),
+        }
+    }
+    assert!(resumed, "run was not marked as resumed");
+    assert_eq!(ran, vec![cable, verify]);
+    std::fs::remove_file(&marker).ok();
+}
+
+#[test]
+/// A run must not be resumed on a different device from the one it was
+/// started on.
+fn resume_different_dut() {
+    let exclave = Exclave::new(None);
+    let scenario = UnitName::from_str("resumedut", "scenario").unwrap();
+    let test_name = UnitName::from_str("broken", "test").unwrap();
+    exclave.add_unit(
+        &test_name,
+        "[Test]\nName=Broken\nDescription=Always fail\nExecStart=false\n",
+    );
+    exclave.add_unit(
+        &scenario,
+        "[Scenario]\nName=Resume DUT\nDescription=Resume on another board\nTests=broken\n",
+    );
+    exclave.rescan();
+
+    let mut metadata = RunMetadata::new();
+    metadata.insert("serial".to_owned(), "A1234".to_owned());
+    exclave
+        .control
+        .send(ManagerControlMessage::new(
+            &scenario,
+            ManagerControlMessageContents::StartScenarioWithMetadata(
+                Some(scenario.clone()),
+                metadata,
+            ),
+        ))
+        .unwrap();
+    loop {
+        if let UnitEvent::ManagerStatus(ManagerStatusMessage::Finished(..)) =
+            exclave.run_once().unwrap()
+        {
+            break;
+        }
+    }
+
+    // Another board is refused, as is a board that isn't named, and nothing
+    // is started.
+    let refusals = [
+        (
+            Some("B5678"),
+            "unable to resume scenario: device B5678 is not the device from the last run (A1234)",
+        ),
+        (
+            None,
+            "unable to resume scenario: the last run was for device A1234, so its serial must be given",
+        ),
+    ];
+    for (serial, reason) in refusals.iter() {
+        exclave.resume_scenario(&scenario, *serial);
+        loop {
+            match exclave.run_once().unwrap() {
+                UnitEvent::Log(ref entry) if entry.message().starts_with("unable to resume") => {
+                    assert_eq!(entry.message(), *reason);
+                    break;
+                }
+                UnitEvent::ManagerStatus(ManagerStatusMessage::Start(..)) => {
+                    panic!("run was resumed on a different device")
+                }
+                _ => (),
+            }
+        }
+    }
+
+    // The same board may carry on.
+    exclave.resume_scenario(&scenario, Some("A1234"));
+    loop {
+        match exclave.run_once().unwrap() {
+            UnitEvent::Log(ref entry) if entry.message().starts_with("unable to resume") => {
+                panic!("{}", entry.message())
+            }
+            UnitEvent::ManagerStatus(ManagerStatusMessage::Resumed(ref name, _)) => {
+                assert_eq!(*name, scenario);
+                break;
+            }
+            _ => (),
+        }
+    }
+}
+
+#[test]
+/// A plan should list the tests in the order they would run, and why each one is there.
+fn plan_scenario() {
+    let exclave = Exclave::new(None);
+    let test_name = |name| UnitName::from_str(name, "test").unwrap();
+    exclave.add_unit(
+        &test_name("psu"),
+        "[Test]\nName=PSU\nDescription=Power up\nExecStart=true\nProvides=power\n",
+    );
+    exclave.add_unit(
+        &test_name("flash"),
+        "[Test]\nName=Flash\nDescription=Program\nExecStart=true\nRequires=power\n",
+    );
+    exclave.add_unit(
+        &test_name("leds"),
+        "[Test]\nName=LEDs\nDescription=Blink\nExecStart=true\nSuggests=flash\n",
+    );
+    exclave.add_unit(
+        &test_name("serial"),
+        "[Test]\nName=Serial\nDescription=Read serial\nExecStart=true\n",
+    );
+    exclave.add_unit(
+        &test_name("usb"),
+        "[Test]\nName=USB\nDescription=Enumerate\nExecStart=true\nRequires=serial flash\n",
+    );
+    let scenario = ScenarioDescription::from_string(
+        "[Scenario]\nName=All\nDescription=Everything\nTests=usb leds\nAssume=serial\n",
+        UnitName::from_str("all", "scenario").unwrap(),
+        &PathBuf::from("test/config"),
+    )
+    .unwrap();
+
+    let plan = scenario
+        .plan(&exclave.library.get_manager().borrow())
+        .unwrap();
+    let plan: Vec<(UnitName, Vec<Inclusion>)> =
+        plan.into_iter().map(|t| (t.name, t.reasons)).collect();
+    assert_eq!(
+        plan,
+        vec![
+            (
+                test_name("psu"),
+                vec![
+                    Inclusion::RequiredBy(test_name("flash")),
+                    Inclusion::Provides(test_name("power")),
+                ]
+            ),
+            (
+                test_name("flash"),
+                vec![
+                    Inclusion::RequiredBy(test_name("usb")),
+                    Inclusion::SuggestedBy(test_name("leds")),
+                ]
+            ),
+            (test_name("usb"), vec![Inclusion::Explicit]),
+            (test_name("leds"), vec![Inclusion::Explicit]),
+            (
+                test_name("serial"),
+                vec![Inclusion::Assumed, Inclusion::RequiredBy(test_name("usb"))]
+            ),
+        ]
+    );
+}
+
+#[cfg(unix)]
+/// A per-test path for a Unix socket, removed if left over from an earlier run.
+fn socket_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("exclave-{}-{}.sock", name, std::process::id()));
+    std::fs::remove_file(&path).ok();
+    path
+}
+
+#[cfg(unix)]
+/// Start a listener whose clients are announced on the returned channel,
+/// and whose input is read and discarded.
+fn start_listener(path: &Path) -> (Listener, Receiver<u32>) {
+    let (connected_tx, connected) = channel();
+    let connected_tx = Mutex::new(connected_tx);
+    let listener = Listener::start(
+        &ListenAddress::Unix(path.to_path_buf()),
+        move |id, mut c| {
+            connected_tx.lock().unwrap().send(id).ok();
+            std::io::copy(&mut c, &mut std::io::sink()).ok();
+        },
+    )
+    .unwrap();
+    (listener, connected)
+}
+
+#[test]
+#[cfg(unix)]
+/// Stale sockets should be replaced, but anything else at the path must be left alone.
+fn listener_bind() {
+    let path = socket_path("bind");
+
+    std::fs::write(&path, "precious").unwrap();
+    let address = ListenAddress::Unix(path.clone());
+    assert!(Listener::start(&address, |_, _| ()).is_err());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "precious");
+    std::fs::remove_file(&path).unwrap();
+
+    // Leave a socket behind, as a crashed run would.
+    drop(UnixListener::bind(&path).unwrap());
+    let listener = Listener::start(&address, |_, _| ()).unwrap();
+    UnixStream::connect(&path).unwrap();
+    listener.stop();
+    assert!(!path.exists());
+}
+
+#[test]
+#[cfg(unix)]
+/// Everything written to a listener should reach every connected client.
+fn listener_broadcast() {
+    let path = socket_path("broadcast");
+    let (listener, connected) = start_listener(&path);
+
+    let clients: Vec<UnixStream> = (0..2)
+        .map(|_| UnixStream::connect(&path).unwrap())
+        .collect();
+    for _ in &clients {
+        connected.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    listener.write_all(b"HELLO world\n");
+    for client in clients {
+        let mut line = String::new();
+        BufReader::new(client).read_line(&mut line).unwrap();
+        assert_eq!(line, "HELLO world\n");
+    }
+    listener.stop();
+}
+
+#[test]
+#[cfg(unix)]
+/// Writing to one client shouldn't reach any of the others.
+fn listener_write_to() {
+    let path = socket_path("write-to");
+    let (listener, connected) = start_listener(&path);
+
+    let mut clients = vec![];
+    for _ in 0..2 {
+        let client = UnixStream::connect(&path).unwrap();
+        let id = connected.recv_timeout(Duration::from_secs(5)).unwrap();
+        clients.push((id, client));
+    }
+
+    listener.write_to(clients[1].0, b"JIG private\n");
+    listener.write_all(b"HELLO world\n");
+
+    let mut lines = vec![];
+    for (_, client) in clients {
+        let mut reader = BufReader::new(client);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        lines.push(line);
+    }
+    assert_eq!(lines, vec!["HELLO world\n", "JIG private\n"]);
+    listener.stop();
+}
+
+#[test]
+#[cfg(unix)]
+/// A client that connects to an interface, or asks it something, gets the
+/// answer on its own, without it being repeated to the other clients.
+fn listener_interface_replies() {
+    let exclave = Exclave::new(Some(Duration::from_secs(30)));
+    let dir = std::env::temp_dir().join(format!("exclave-dashboard-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = socket_path("dashboard");
+    let unit = dir.join("dashboard.interface");
+    std::fs::write(
+        &unit,
+        format!(
+            "[Interface]\nName=Dashboard\nDescription=Shared dashboard\nListenStream={}\n",
+            path.display()
+        ),
+    )
+    .unwrap();
+    {
+        let manager = exclave.library.get_manager().borrow();
+        let interface = manager
+            .load_interface(&InterfaceDescription::from_path(&unit).unwrap())
+            .unwrap();
+        manager.activate(&interface);
+    }
+    loop {
+        if let UnitEvent::Status(ref s) = exclave.run_once().unwrap() {
+            if s.status == UnitStatus::Active {
+                break;
+            }
+        }
+    }
+
+    // Handle requests until the given client's request has been answered.
+    let serve = |request: &ManagerControlMessageContents| loop {
+        let msg = exclave.run_once().unwrap();
+        println!("Message: {:?}", msg);
+        match msg {
+            UnitEvent::ManagerRequest(ManagerControlMessage {
+                contents: ManagerControlMessageContents::FromClient(_, ref inner),
+                ..
+            }) if **inner == *request => break,
+            UnitEvent::Shutdown => panic!("request never arrived"),
+            _ => (),
+        }
+    };
+    let read_line = |reader: &mut BufReader<UnixStream>| {
+        let mut line = String::new();
+        reader.read_line(&mut line).map(|_| line)
+    };
+    // Read whatever has arrived, stopping once the client has gone quiet.
+    let drain = |reader: &mut BufReader<UnixStream>| {
+        reader
+            .get_ref()
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let mut lines = vec![];
+        while let Ok(line) = read_line(reader) {
+            lines.push(line);
+        }
+        lines
+    };
+
+    let mut first = BufReader::new(UnixStream::connect(&path).unwrap());
+    serve(&ManagerControlMessageContents::InitialGreeting);
+    assert!(drain(&mut first)[0].starts_with("HELLO "));
+
+    let mut second = BufReader::new(UnixStream::connect(&path).unwrap());
+    serve(&ManagerControlMessageContents::InitialGreeting);
+    assert!(drain(&mut second)[0].starts_with("HELLO "));
+    second.get_mut().write_all(b"JIG\n").unwrap();
+    serve(&ManagerControlMessageContents::Jig);
+    assert_eq!(drain(&mut second), vec!["JIG\n"]);
+
+    // The first client heard none of that.
+    assert_eq!(drain(&mut first), Vec::<String>::new());
+
+    exclave.library.get_manager().borrow().deactivate(
+        &UnitName::from_str("dashboard", "interface").unwrap(),
+        "test over",
+    );
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+#[cfg(unix)]
+/// Clients that disconnect or stop reading must not hold up the others.
+fn listener_client_disconnect() {
+    let path = socket_path("disconnect");
+    let (listener, connected) = start_listener(&path);
+
+    let closed = UnixStream::connect(&path).unwrap();
+    let stalled = UnixStream::connect(&path).unwrap();
+    let reader = UnixStream::connect(&path).unwrap();
+    for _ in 0..3 {
+        connected.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+    drop(closed);
+
+    let (line_tx, lines) = channel();
+    thread::spawn(move || {
+        for line in BufReader::new(reader).lines() {
+            if line_tx.send(line.unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+
+    // Write far more than the stalled client's queue and socket buffer can
+    // hold, waiting for the reading client to see each line.
+    let line = format!("{}\n", "x".repeat(16 * 1024));
+    let started = Instant::now();
+    for _ in 0..600 {
+        listener.write_all(line.as_bytes());
+        assert_eq!(
+            lines.recv_timeout(Duration::from_secs(5)).unwrap().len(),
+            line.len() - 1
+        );
+    }
+    assert!(started.elapsed() < Duration::from_secs(30));
+
+    // The stalled client should have been dropped part of the way through.
+    let mut stalled = stalled;
+    stalled
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut received = vec![];
+    stalled.read_to_end(&mut received).unwrap();
+    assert!(received.len() < line.len() * 600);
+    listener.stop();
+}
+
+#[test]
+/// The five XML special characters are escaped, and control characters
+/// that XML can't carry are dropped.
+fn junit_xml_escape() {
+    assert_eq!(
+        xml_escape(r#"<a href="x">Tom & Jerry's</a>"#),
+        "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&apos;s&lt;/a&gt;"
+    );
+    assert_eq!(
+        xml_escape("bell\u{7}\u{0}escape\u{1b}[0m\ttab\r\nline"),
+        "bellescape[0m\ttab\r\nline"
+    );
+    assert_eq!(xml_escape("µV ✓"), "µV ✓");
+}
+
+#[test]
+/// A report counts failed tests as failures, and tests that were skipped or
+/// never ran as skipped.
+fn junit_report_counts() {
+    let broadcaster = UnitBroadcaster::new();
+    let receiver = broadcaster.subscribe();
+    let dir = std::env::temp_dir().join(format!("exclave-junit-{}", std::process::id()));
+    JUnitReporter::start(&dir, &broadcaster).unwrap();
+
+    let scenario = UnitName::from_str("final", "scenario").unwrap();
+    let tests: Vec<UnitName> = ["pass", "fail", "skip", "never"]
+        .iter()
+        .map(|t| UnitName::from_str(t, "test").unwrap())
+        .collect();
+    let mut metadata = RunMetadata::new();
+    metadata.insert("serial".to_owned(), "A<1>".to_owned());
+    for msg in [
+        ManagerStatusMessage::Tests(scenario.clone(), tests.clone()),
+        ManagerStatusMessage::Start(scenario.clone(), metadata),
+        ManagerStatusMessage::Running(tests[0].clone()),
+        ManagerStatusMessage::Pass(tests[0].clone(), "ok".to_owned()),
+        ManagerStatusMessage::Running(tests[1].clone()),
+        ManagerStatusMessage::Fail(tests[1].clone(), 2, "voltage \"low\"".to_owned()),
+        ManagerStatusMessage::Skipped(tests[2].clone(), "requirement failed".to_owned()),
+        ManagerStatusMessage::Finished(
+            scenario.clone(),
+            500,
+            "at least one test failed".to_owned(),
+            None,
+        ),
+    ] {
+        broadcaster.broadcast(&UnitEvent::ManagerStatus(msg));
+    }
+
+    let report = loop {
+        match receiver.recv_timeout(Duration::from_secs(10)).unwrap() {
+            UnitEvent::Log(ref entry) if entry.message().starts_with("wrote JUnit report ") => {
+                break PathBuf::from(&entry.message()["wrote JUnit report ".len()..]);
+            }
+            UnitEvent::Log(ref entry) => panic!("unexpected log: {}", entry.message()),
+            _ => (),
+        }
+    };
+    let xml = std::fs::read_to_string(&report).unwrap();
+    println!("{}", xml);
+    assert!(xml.contains(
+        "<testsuite name=\"final\" tests=\"4\" failures=\"1\" errors=\"0\" skipped=\"2\" "
+    ));
+    assert!(xml.contains("<property name=\"result\" value=\"500\"/>"));
+    assert!(xml.contains("<property name=\"serial\" value=\"A&lt;1&gt;\"/>"));
+    assert!(xml.contains(
+        "<failure message=\"voltage &quot;low&quot;\" type=\"exit 2\">test exited with code 2</failure>"
+    ));
+    assert!(xml.contains("<skipped message=\"requirement failed\"/>"));
+    assert!(xml.contains("<skipped message=\"not run\"/>"));
+
+    // Test cases appear in the order the scenario runs them.
+    let positions: Vec<usize> = tests
+        .iter()
+        .map(|t| xml.find(&format!("<testcase name=\"{}\"", t.id())).unwrap())
+        .collect();
+    assert!(positions.windows(2).all(|w| w[0] < w[1]));
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+/// A run should be readable back out of the results database, without its
+/// own error messages being recorded.
+fn results_db_round_trip() {
+    let broadcaster = UnitBroadcaster::new();
+    let mut db = ResultsDb::new(
+        rusqlite::Connection::open_in_memory().unwrap(),
+        &broadcaster,
+    )
+    .unwrap();
+    let scenario = UnitName::from_str("final", "scenario").unwrap();
+    let test_name = UnitName::from_str("flash", "test").unwrap();
+    let mut metadata = RunMetadata::new();
+    metadata.insert("serial".to_owned(), "A1234".to_owned());
+
+    let events = vec![
+        UnitEvent::ManagerStatus(ManagerStatusMessage::Start(scenario.clone(), metadata)),
+        UnitEvent::ManagerStatus(ManagerStatusMessage::Running(test_name.clone())),
+        UnitEvent::Log(LogEntry::new_info(
+            test_name.clone(),
+            "writing image".to_owned(),
+        )),
+        UnitEvent::Log(LogEntry::new_error(
+            UnitName::internal("resultsdb"),
+            "unable to record results: database is locked".to_owned(),
+        )),
+        UnitEvent::ManagerStatus(ManagerStatusMessage::Measurement(
+            test_name.clone(),
+            Measurement {
+                name: "vbus".to_owned(),
+                value: 5.1,
+                units: Some("V".to_owned()),
+                low: Some(4.75),
+                high: Some(5.25),
+                passed: true,
+            },
+        )),
+        UnitEvent::ManagerStatus(ManagerStatusMessage::Pass(
+            test_name.clone(),
+            "image verified".to_owned(),
+        )),
+        UnitEvent::ManagerStatus(ManagerStatusMessage::Finished(
+            scenario,
+            200,
+            "all tests passed".to_owned(),
+            None,
+        )),
+    ];
+    for event in events {
+        db.process_event(event).unwrap();
+    }
+
+    let connection = db.connection();
+    let run: (String, String, i32, String) = connection
+        .query_row("SELECT scenario, dut, result, reason FROM runs", [], |r| {
+            Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?))
+        })
+        .unwrap();
+    assert_eq!(
+        run,
+        (
+            "final".to_owned(),
+            "A1234".to_owned(),
+            200,
+            "all tests passed".to_owned()
+        )
+    );
+
+    let result: (String, String, i32, String) = connection
+        .query_row(
+            "SELECT test, result, code, message FROM test_results",
+            [],
+            |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)),
+        )
+        .unwrap();
+    assert_eq!(
+        result,
+        (
+            "flash".to_owned(),
+            "pass".to_owned(),
+            0,
+            "image verified".to_owned()
+        )
+    );
+
+    let logs: Vec<(String, String)> = connection
+        .prepare("SELECT unit, message FROM log_lines")
+        .unwrap()
+        .query_map([], |r| Ok((r.get(0)?, r.get(1)?)))
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    assert_eq!(logs, vec![("flash".to_owned(), "writing image".to_owned())]);
+
+    let measurement: (String, f64, bool) = connection
+        .query_row("SELECT name, value, passed FROM measurements", [], |r| {
+            Ok((r.get(0)?, r.get(1)?, r.get(2)?))
+        })
+        .unwrap();
+    assert_eq!(measurement, ("vbus".to_owned(), 5.1, true));
+}
+
+#[test]
+/// Restart settings should be parsed, and unrelated keys left alone.
+fn restart_policy_parsing() {
+    let mut policy = RestartPolicy::default();
+    assert!(!policy.should_restart(true));
+
+    for (key, value) in &[
+        ("Restart", "on-failure"),
+        ("RestartSec", "250ms"),
+        ("StartLimitBurst", "3"),
+        ("StartLimitIntervalSec", "30"),
+    ] {
+        assert!(policy.parse_directive("Logger", key, Some(value)).unwrap());
+    }
+    assert!(policy.should_restart(true));
+    assert!(!policy.should_restart(false));
+    assert_eq!(*policy.delay(), Duration::from_millis(250));
+    assert_eq!(policy.start_limit_burst(), 3);
+    assert_eq!(*policy.start_limit_interval(), Duration::from_secs(30));
+
+    policy
+        .parse_directive("Logger", "Restart", Some("Always"))
+        .unwrap();
+    assert!(policy.should_restart(false));
+
+    assert!(!policy
+        .parse_directive("Logger", "ExecStart", Some("true"))
+        .unwrap());
+    assert!(policy
+        .parse_directive("Logger", "Restart", Some("sometimes"))
+        .is_err());
+    assert!(policy
+        .parse_directive("Logger", "StartLimitBurst", Some("many"))
+        .is_err());
+    assert!(policy
+        .parse_directive("Logger", "RestartSec", None)
+        .is_err());
+
+    // Liveness checks are off unless asked for.
+    assert!(!policy.ping());
+    assert!(policy
+        .parse_directive("Logger", "Ping", Some("yes"))
+        .unwrap());
+    assert!(policy
+        .parse_directive("Logger", "PingTimeoutSec", Some("2"))
+        .unwrap());
+    assert!(policy.ping());
+    assert_eq!(*policy.ping_timeout(), Duration::from_secs(2));
+    assert!(policy
+        .parse_directive("Logger", "Ping", Some("maybe"))
+        .is_err());
+}
+
+#[test]
+/// A logger that keeps exiting should be restarted, until it has been
+/// restarted StartLimitBurst times within StartLimitIntervalSec.
+fn logger_start_limit() {
+    let exclave = Exclave::new(Some(Duration::from_secs(30)));
+    let dir = std::env::temp_dir().join(format!("exclave-restart-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("quitter.logger");
+    std::fs::write(
+        &path,
+        r##"[Logger]
+Name=Quitter
+Description=Exit straight away
+ExecStart=true
+Restart=always
+RestartSec=0
+StartLimitBurst=2
+StartLimitIntervalSec=60
+"##,
+    )
+    .unwrap();
+    let logger = {
+        let manager = exclave.library.get_manager().borrow();
+        let logger = manager
+            .load_logger(&LoggerDescription::from_path(&path).unwrap())
+            .unwrap();
+        manager.activate(&logger);
+        logger
+    };
+
+    let mut activations = 0;
+    loop {
+        let msg = exclave.run_once().unwrap();
+        println!("Message: {:?}", msg);
+        match msg {
+            UnitEvent::Status(ref s) if s.name == logger => match s.status {
+                UnitStatus::Active => activations += 1,
+                UnitStatus::ActivationFailed(ref reason)
+                    if reason.starts_with("start limit hit") =>
+                {
+                    break
+                }
+                _ => (),
+            },
+            UnitEvent::Shutdown => panic!("start limit was never hit"),
+            _ => (),
+        }
+    }
+    // The first run, and then two restarts.
+    assert_eq!(activations, 3);
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+/// Feed lines through a trigger reader, and collect the messages it sends
+/// until the input runs out.
+fn trigger_messages(
+    read: fn(UnitName, Sender<ManagerControlMessage>, std::io::Cursor<&'static str>),
+    input: &'static str,
+) -> Vec<ManagerControlMessageContents> {
+    let id = UnitName::from_str("button", "trigger").unwrap();
+    let (sender, receiver) = channel();
+    read(id.clone(), sender, std::io::Cursor::new(input));
+    receiver
+        .try_iter()
+        .map(|msg| {
+            assert_eq!(msg.sender, id);
+            msg.contents
+        })
+        .collect()
+}
+
+#[test]
+/// Each line a text trigger prints becomes one message for the manager,
+/// followed by ChildExited once it closes stdout.
+fn trigger_text_read() {
+    assert_eq!(
+        trigger_messages(
+            Trigger::text_read,
+            "STOP\nLOG button\\tpressed twice\nhello footswitch 1.2\nstart Final\n\n"
+        ),
+        vec![
+            ManagerControlMessageContents::AbortTests,
+            ManagerControlMessageContents::Log("button\tpressed twice".to_owned()),
+            ManagerControlMessageContents::Log(
+                "trigger identifies itself as footswitch 1.2".to_owned()
+            ),
+            ManagerControlMessageContents::StartScenario(Some(
+                UnitName::from_str("final", "scenario").unwrap()
+            )),
+            ManagerControlMessageContents::StartScenario(None),
+            ManagerControlMessageContents::ChildExited,
+        ]
+    );
+}
+
+#[test]
+/// Each object a JSON trigger prints becomes one message for the manager.
+/// Lines that aren't valid requests are reported, and don't stop the reader.
+fn trigger_json_read() {
+    let messages = trigger_messages(
+        Trigger::json_read,
+        r##"{"type":"hello","version":"lid-switch 1.2"}
+{"type":"start"}
+{"type":"start","scenario":"Linux-Tests","metadata":{"serial":"A1234567","fixture":3}}
+
+{"type":"stop"}
+{"type":"log","message":"Lid closed"}
+{"type":"pong","id":7}
+{"type":"start","scenario":"final.bogus"}
+{"type":"log"
+{"type":"jump"}
+{"type":"pong","id":"seven"}
+"##,
+    );
+
+    let mut metadata = RunMetadata::new();
+    metadata.insert("serial".to_owned(), "A1234567".to_owned());
+    metadata.insert("fixture".to_owned(), "3".to_owned());
+    assert_eq!(
+        messages[..7],
+        [
+            ManagerControlMessageContents::Log(
+                "trigger identifies itself as lid-switch 1.2".to_owned()
+            ),
+            ManagerControlMessageContents::StartScenarioWithMetadata(None, RunMetadata::new()),
+            ManagerControlMessageContents::StartScenarioWithMetadata(
+                Some(UnitName::from_str("linux-tests", "scenario").unwrap()),
+                metadata
+            ),
+            ManagerControlMessageContents::AbortTests,
+            ManagerControlMessageContents::Log("Lid closed".to_owned()),
+            ManagerControlMessageContents::Pong("7".to_owned()),
+            ManagerControlMessageContents::Error(
+                "Invalid scenario name: unrecognized unit type \".bogus\"".to_owned()
+            ),
+        ]
+    );
+    assert_eq!(messages.len(), 11);
+    for msg in &messages[7..10] {
+        match msg {
+            ManagerControlMessageContents::Error(ref e) => {
+                assert!(e.starts_with("Invalid JSON request: "), "{}", e)
+            }
+            other => panic!("malformed request gave {:?}", other),
+        }
+    }
+    assert_eq!(messages[10], ManagerControlMessageContents::ChildExited);
+}
+
+#[test]
+/// Events= picks which result events a logger is sent, either by name or
+/// with "all", and refuses events it doesn't know about.
+fn logger_events_parsing() {
+    let exclave = Exclave::new(None);
+    let config = Config::new();
+    let dir = std::env::temp_dir().join(format!("exclave-events-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("results.logger");
+    let test = UnitName::from_str("ls", "test").unwrap();
+    let pass = ManagerStatusMessage::Pass(test.clone(), "ok".to_owned());
+    let running = ManagerStatusMessage::Running(test.clone());
+    let set = ManagerStatusMessage::Set(test, "serial".to_owned(), "A1".to_owned());
+
+    let load = |events: &str| {
+        std::fs::write(
+            &path,
+            format!(
+                "[Logger]\nName=Results\nDescription=Record results\nExecStart=cat\n{}",
+                events
+            ),
+        )
+        .unwrap();
+        LoggerDescription::from_path(&path)
+    };
+
+    let manager = exclave.library.get_manager().borrow();
+    let logger = Logger::new(&load("").unwrap(), &manager, &config);
+    assert!(!logger.wants(&pass));
+
+    let logger = Logger::new(&load("Events=Pass, fail set").unwrap(), &manager, &config);
+    assert!(logger.wants(&pass));
+    assert!(logger.wants(&set));
+    assert!(!logger.wants(&running));
+
+    let logger = Logger::new(&load("Events=all").unwrap(), &manager, &config);
+    assert!(logger.wants(&pass));
+    assert!(logger.wants(&running));
+
+    // Log messages aren't result events, and are always sent.
+    assert!(!logger.wants(&ManagerStatusMessage::Log(LogEntry::new_info(
+        UnitName::internal("test"),
+        "hello".to_owned()
+    ))));
+
+    assert!(load("Events=pass finished").is_err());
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+/// Result events are sent to TSV loggers with one column per field, and to
+/// JSON loggers as the interface record plus a timestamp.
+fn logger_event_records() {
+    let now = Duration::new(1485942258, 5000);
+    let test = UnitName::from_str("power", "test").unwrap();
+    let measurement = ManagerStatusMessage::Measurement(
+        test.clone(),
+        Measurement {
+            name: "vbat".to_owned(),
+            value: 3.3,
+            units: Some("V".to_owned()),
+            low: Some(3.0),
+            high: None,
+            passed: true,
+        },
+    );
+    assert_eq!(
+        Logger::tsv_event_record(&measurement, now).unwrap(),
+        "measurement\tpower\ttest\t1485942258\t5000\tvbat\t3.3\tV\t3\t\tpass"
+    );
+
+    let fail = ManagerStatusMessage::Fail(test.clone(), 2, "no such\tfile".to_owned());
+    assert_eq!(
+        Logger::tsv_event_record(&fail, now).unwrap(),
+        "fail\tpower\ttest\t1485942258\t5000\t2\tno such\\tfile"
+    );
+
+    let finished = ManagerStatusMessage::Finished(
+        UnitName::from_str("bundle", "scenario").unwrap(),
+        200,
+        "all tests passed".to_owned(),
+        None,
+    );
+    assert_eq!(
+        Logger::tsv_event_record(&finished, now).unwrap(),
+        "finish\tbundle\tscenario\t1485942258\t5000\t200\tall tests passed\t"
+    );
+
+    let resumed = ManagerStatusMessage::Resumed(
+        UnitName::from_str("bundle", "scenario").unwrap(),
+        vec![test.clone(), UnitName::from_str("flash", "test").unwrap()],
+    );
+    assert_eq!(
+        Logger::tsv_event_record(&resumed, now).unwrap(),
+        "resume\tbundle\tscenario\t1485942258\t5000\tpower flash"
+    );
+
+    assert_eq!(
+        Logger::json_event_record(&fail, now),
+        json!({
+            "type": "fail",
+            "test": "power",
+            "code": 2,
+            "message": "no such\tfile",
+            "unix_time": 1485942258,
+            "unix_time_nsecs": 5000,
+        })
+    );
+
+    let log = ManagerStatusMessage::Log(LogEntry::new_info(test, "hello".to_owned()));
+    assert_eq!(Logger::tsv_event_record(&log, now), None);
+}
+
+#[test]
+#[cfg(unix)]
+/// Only loggers with Ping=yes are sent PINGs, and only those that then fail
+/// to answer within PingTimeoutSec are stopped.
+fn logger_ping_timeout() {
+    let exclave = Exclave::new(Some(Duration::from_secs(30)));
+    let dir = std::env::temp_dir().join(format!("exclave-ping-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let units = [
+        ("silent", "sleep 10", "Ping=yes\nPingTimeoutSec=200ms\n"),
+        (
+            "answering",
+            "sh -c 'while read kind id; do echo PONG $id; done'",
+            "Ping=yes\nPingTimeoutSec=200ms\n",
+        ),
+        ("unsupervised", "sleep 10", ""),
+    ];
+    let mut loggers = vec![];
+    for (name, exec, extra) in &units {
+        let path = dir.join(format!("{}.logger", name));
+        std::fs::write(
+            &path,
+            format!(
+                "[Logger]\nName={}\nDescription=Liveness\nExecStart={}\n{}",
+                name, exec, extra
+            ),
+        )
+        .unwrap();
+        let manager = exclave.library.get_manager().borrow();
+        let logger = manager
+            .load_logger(&LoggerDescription::from_path(&path).unwrap())
+            .unwrap();
+        manager.activate(&logger);
+        loggers.push(logger);
+    }
+
+    let checker = UnitName::internal("ping-test");
+    let check_liveness = || {
+        exclave
+            .control
+            .send(ManagerControlMessage::new(
+                &checker,
+                ManagerControlMessageContents::CheckLiveness,
+            ))
+            .unwrap();
+    };
+
+    let mut active = 0;
+    let mut checks = 0;
+    let mut pongs = 0;
+    let mut failures = vec![];
+    loop {
+        let msg = exclave.run_once().unwrap();
+        println!("Message: {:?}", msg);
+        match msg {
+            UnitEvent::Status(ref s) => match s.status {
+                UnitStatus::Active if loggers.contains(&s.name) => {
+                    active += 1;
+                    if active == loggers.len() {
+                        check_liveness();
+                    }
+                }
+                UnitStatus::ActivationFailed(ref reason) => {
+                    failures.push((s.name.clone(), reason.clone()))
+                }
+                _ => (),
+            },
+            UnitEvent::ManagerRequest(ref mrq) if mrq.sender == checker => {
+                checks += 1;
+                match checks {
+                    // Give the PINGs time to expire, then check again.
+                    1 => {
+                        let control = exclave.control.clone();
+                        let checker = checker.clone();
+                        thread::spawn(move || {
+                            thread::sleep(Duration::from_millis(600));
+                            control
+                                .send(ManagerControlMessage::new(
+                                    &checker,
+                                    ManagerControlMessageContents::CheckLiveness,
+                                ))
+                                .unwrap();
+                        });
+                    }
+                    // Anything reaped by the second check has now been broadcast,
+                    // so a third check marks the end of the results.
+                    2 => check_liveness(),
+                    _ => break,
+                }
+            }
+            UnitEvent::ManagerRequest(ref mrq) => {
+                if let ManagerControlMessageContents::Pong(_) = mrq.contents {
+                    if mrq.sender == loggers[1] {
+                        pongs += 1;
+                    }
+                }
+            }
+            UnitEvent::Shutdown => panic!("liveness checks never finished"),
+            _ => (),
+        }
+    }
+
+    assert!(pongs >= 1);
+    assert_eq!(failures.len(), 1, "unexpected failures: {:?}", failures);
+    assert_eq!(failures[0].0, loggers[0]);
+    assert!(failures[0].1.starts_with("no PONG received"));
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+/// Requests should be parsed down to their method and path, with any body
+/// skipped over, and oversized bodies refused.
+fn http_request_parsing() {
+    let request = HttpInterface::read_request(&mut std::io::Cursor::new(
+        "post /start/final?now=1 HTTP/1.1\r\nHost: station\r\ncontent-length: 5\r\n\r\nhello",
+    ))
+    .ok()
+    .unwrap();
+    assert_eq!(request.method, "POST");
+    assert_eq!(request.path, "/start/final");
+
+    let oversized = format!(
+        "POST /abort HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+        1024 * 1024 * 1024
+    );
+    assert_eq!(
+        HttpInterface::read_request(&mut std::io::Cursor::new(oversized)).err(),
+        Some(RequestError::TooLarge)
+    );
+    assert_eq!(
+        HttpInterface::read_request(&mut std::io::Cursor::new("\r\n")).err(),
+        Some(RequestError::Malformed)
+    );
+    assert_eq!(
+        HttpInterface::read_request(&mut std::io::Cursor::new(
+            "POST /abort HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort"
+        ))
+        .err(),
+        Some(RequestError::Malformed)
+    );
+}
+
+#[test]
+/// Every event should be framed as a named Server-Sent Event.
+fn http_sse_framing() {
+    let fail = ManagerStatusMessage::Fail(
+        UnitName::from_str("flash", "test").unwrap(),
+        3,
+        "no ack".to_owned(),
+    );
+    assert_eq!(
+        HttpInterface::sse_record(&fail),
+        "event: fail\ndata: {\"code\":3,\"message\":\"no ack\",\"test\":\"flash\",\"type\":\"fail\"}\n\n"
+    );
+}
+
+/// Send a raw request to the HTTP server, and return the status line and body.
+fn http_request(address: &SocketAddr, request: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let status = response.lines().next().unwrap_or("").to_owned();
+    let body = response.split("\r\n\r\n").nth(1).unwrap_or("").to_owned();
+    (status, body)
+}
+
+#[test]
+/// Requests should be routed to the right endpoint, and events streamed.
+fn http_routing() {
+    let broadcaster = UnitBroadcaster::new();
+    let receiver = broadcaster.subscribe();
+    let address = HttpInterface::start("127.0.0.1:0", &broadcaster).unwrap();
+
+    assert_eq!(
+        http_request(&address, "GET /jig HTTP/1.1\r\n\r\n"),
+        ("HTTP/1.1 200 OK".to_owned(), "null".to_owned())
+    );
+    assert_eq!(
+        http_request(&address, "GET /nowhere HTTP/1.1\r\n\r\n").0,
+        "HTTP/1.1 404 Not Found"
+    );
+    assert_eq!(
+        http_request(
+            &address,
+            "POST /abort HTTP/1.1\r\nContent-Length: 1000000\r\n\r\n"
+        )
+        .0,
+        "HTTP/1.1 413 Payload Too Large"
+    );
+    assert_eq!(
+        http_request(&address, "POST /abort HTTP/1.1\r\n\r\n").0,
+        "HTTP/1.1 202 Accepted"
+    );
+    loop {
+        if let UnitEvent::ManagerRequest(ManagerControlMessage {
+            contents: ManagerControlMessageContents::AbortTests,
+            ..
+        }) = receiver.recv_timeout(Duration::from_secs(5)).unwrap()
+        {
+            break;
+        }
+    }
+
+    // Once the headers have arrived, events should follow.
+    let mut events = BufReader::new(TcpStream::connect(address).unwrap());
+    events
+        .get_mut()
+        .write_all(b"GET /events HTTP/1.1\r\n\r\n")
+        .unwrap();
+    let mut line = String::new();
+    while line != "\r\n" {
+        line.clear();
+        events.read_line(&mut line).unwrap();
+    }
+    let test_name = UnitName::from_str("flash", "test").unwrap();
+    broadcaster.broadcast(&UnitEvent::ManagerStatus(ManagerStatusMessage::Running(
+        test_name,
+    )));
+    let mut record = String::new();
+    for _ in 0..3 {
+        events.read_line(&mut record).unwrap();
+    }
+    assert_eq!(
+        record,
+        "event: running\ndata: {\"test\":\"flash\",\"type\":\"running\"}\n\n"
+    );
+}
+
+#[test]
+/// An /events client that stops reading mustn't hold up the others.
+fn http_events_stalled_client() {
+    let broadcaster = UnitBroadcaster::new();
+    let address = HttpInterface::start("127.0.0.1:0", &broadcaster).unwrap();
+
+    let connect = || {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(b"GET /events HTTP/1.1\r\n\r\n").unwrap();
+        let mut events = BufReader::new(stream);
+        let mut line = String::new();
+        while line != "\r\n" {
+            line.clear();
+            events.read_line(&mut line).unwrap();
+        }
+        events
+    };
+    let _stalled = connect();
+    let mut events = connect();
+    events
+        .get_ref()
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    // Send far more than the stalled client's socket can buffer.
+    let test_name = UnitName::from_str("flash", "test").unwrap();
+    let message = "x".repeat(64 * 1024);
+    for _ in 0..200 {
+        broadcaster.broadcast(&UnitEvent::Log(LogEntry::new_info(
+            test_name.clone(),
+            message.clone(),
+        )));
+        let mut record = String::new();
+        for _ in 0..3 {
+            events.read_line(&mut record).unwrap();
+        }
+        assert!(record.starts_with("event: log\n"));
+    }
+}
@@ -5,11 +5,13 @@ extern crate runny;
 extern crate systemd_parser;
 
 use std::fmt;
-use std::io;
-use std::path::Path;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use self::dependy::DepError;
-use self::humantime::DurationError;
+use self::humantime::{parse_duration, DurationError};
 use self::runny::running::RunningError;
 use self::runny::RunnyError;
 use self::systemd_parser::errors::ParserError;
@@ -119,7 +121,7 @@ impl UnitName {
     }
 
     pub fn from_list(s: &str, default_type: &str) -> Result<Vec<Self>, UnitNameError> {
-        let in_list_list: Vec<&str> = s.split(|c| c == ',').collect();
+        let in_list_list: Vec<&str> = s.split(',').collect();
         let mut out_list = vec![];
         for in_list in in_list_list {
             for item in in_list.split_whitespace() {
@@ -150,6 +152,8 @@ pub enum UnitIncompatibleReason {
     TestFileNotPresent(String),
     IncompatibleJig,
     DependencyError(DepError<UnitName>),
+    IncludeNotFound(UnitName),
+    IncludeLoop(UnitName),
 }
 
 impl fmt::Display for UnitIncompatibleReason {
@@ -165,6 +169,12 @@ impl fmt::Display for UnitIncompatibleReason {
                 write!(f, "Test file {} not present", file_name)
             }
             UnitIncompatibleReason::IncompatibleJig => write!(f, "Jig not compatible"),
+            UnitIncompatibleReason::IncludeNotFound(ref name) => {
+                write!(f, "Included scenario {} not found", name)
+            }
+            UnitIncompatibleReason::IncludeLoop(ref name) => {
+                write!(f, "Scenario {} ends up including itself", name)
+            }
             UnitIncompatibleReason::DependencyError(ref dep_error) => match dep_error {
                 DepError::RequirementsNotFound(ref req) => {
                     write!(f, "Requirement '{}' not found", req)
@@ -250,6 +260,12 @@ pub enum UnitActivateError {
 
     /// We tried to Exec, but an error occurred.
     ExecFailed(RunnyError),
+
+    /// We couldn't listen on the requested socket.
+    ListenFailed(String /* address */, io::Error),
+
+    /// An EnvironmentFile couldn't be read.
+    EnvironmentFailed(io::Error),
 }
 
 impl From<RunnyError> for UnitActivateError {
@@ -275,6 +291,12 @@ impl fmt::Display for UnitActivateError {
             },
             UnitActivateError::UnitNotFound => write!(f, "Couldn't find unit by id"),
             UnitActivateError::UnitNotSelected => write!(f, "Tried to activate a deselected unit"),
+            UnitActivateError::ListenFailed(ref address, ref e) => {
+                write!(f, "Unable to listen on {}: {}", address, e)
+            }
+            UnitActivateError::EnvironmentFailed(ref e) => {
+                write!(f, "Unable to read environment: {}", e)
+            }
         }
     }
 }
@@ -396,3 +418,303 @@ impl fmt::Display for UnitDescriptionError {
         }
     }
 }
+
+/// Parse a yes/no value for the given key of a unit section.
+pub fn parse_bool(section: &str, key: &str, value: &str) -> Result<bool, UnitDescriptionError> {
+    match value.to_lowercase().as_str() {
+        "yes" | "true" | "on" | "1" => Ok(true),
+        "no" | "false" | "off" | "0" => Ok(false),
+        _ => Err(UnitDescriptionError::InvalidValue(
+            section.to_owned(),
+            key.to_owned(),
+            value.to_owned(),
+            vec!["yes".to_owned(), "no".to_owned()],
+        )),
+    }
+}
+
+/// When a long-running unit should be restarted after it exits.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RestartMode {
+    /// Never restart the unit.
+    No,
+
+    /// Restart the unit only if it exited with an error or stopped responding.
+    OnFailure,
+
+    /// Restart the unit whenever it exits.
+    Always,
+}
+
+/// The systemd-style Restart=, RestartSec=, StartLimitBurst= and
+/// StartLimitIntervalSec= settings shared by Interfaces, Loggers, and Triggers,
+/// along with the Ping= and PingTimeoutSec= liveness settings.
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    mode: RestartMode,
+    delay: Duration,
+    start_limit_burst: u32,
+    start_limit_interval: Duration,
+
+    /// Whether the unit has promised to answer PINGs.
+    ping: bool,
+    ping_timeout: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy {
+            mode: RestartMode::No,
+            delay: Duration::from_millis(100),
+            start_limit_burst: 5,
+            start_limit_interval: Duration::from_secs(10),
+            ping: false,
+            ping_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Apply a restart-related directive from the given section.
+    /// Returns false if the key has nothing to do with restarting.
+    pub fn parse_directive(
+        &mut self,
+        section: &str,
+        key: &str,
+        value: Option<&str>,
+    ) -> Result<bool, UnitDescriptionError> {
+        let value = match key {
+            "Restart"
+            | "RestartSec"
+            | "StartLimitBurst"
+            | "StartLimitIntervalSec"
+            | "Ping"
+            | "PingTimeoutSec" => match value {
+                Some(s) => s,
+                None => {
+                    return Err(UnitDescriptionError::MissingValue(
+                        section.to_owned(),
+                        key.to_owned(),
+                    ))
+                }
+            },
+            _ => return Ok(false),
+        };
+
+        match key {
+            "Restart" => {
+                self.mode = match value.to_lowercase().as_str() {
+                    "no" => RestartMode::No,
+                    "on-failure" => RestartMode::OnFailure,
+                    "always" => RestartMode::Always,
+                    other => {
+                        return Err(UnitDescriptionError::InvalidValue(
+                            section.to_owned(),
+                            key.to_owned(),
+                            other.to_owned(),
+                            vec![
+                                "no".to_owned(),
+                                "on-failure".to_owned(),
+                                "always".to_owned(),
+                            ],
+                        ))
+                    }
+                }
+            }
+            "RestartSec" => self.delay = Self::parse_time(value)?,
+            "StartLimitBurst" => self.start_limit_burst = value.parse::<u32>()?,
+            "StartLimitIntervalSec" => self.start_limit_interval = Self::parse_time(value)?,
+            "Ping" => self.ping = parse_bool(section, key, value)?,
+            _ => self.ping_timeout = Self::parse_time(value)?,
+        }
+        Ok(true)
+    }
+
+    fn parse_time(time_str: &str) -> Result<Duration, DurationError> {
+        if let Ok(val) = time_str.parse::<u64>() {
+            Ok(Duration::from_secs(val))
+        } else {
+            parse_duration(time_str)
+        }
+    }
+
+    /// Whether a unit that exited should be brought back up.
+    pub fn should_restart(&self, failed: bool) -> bool {
+        match self.mode {
+            RestartMode::No => false,
+            RestartMode::OnFailure => failed,
+            RestartMode::Always => true,
+        }
+    }
+
+    /// How long to wait before restarting the unit.
+    pub fn delay(&self) -> &Duration {
+        &self.delay
+    }
+
+    /// The maximum number of restarts allowed within `start_limit_interval()`.
+    pub fn start_limit_burst(&self) -> u32 {
+        self.start_limit_burst
+    }
+
+    pub fn start_limit_interval(&self) -> &Duration {
+        &self.start_limit_interval
+    }
+
+    /// Whether the unit should be sent PINGs and restarted if it stops answering.
+    pub fn ping(&self) -> bool {
+        self.ping
+    }
+
+    /// How long the unit has to answer a PING.
+    pub fn ping_timeout(&self) -> &Duration {
+        &self.ping_timeout
+    }
+}
+
+/// The systemd-style Environment= and EnvironmentFile= settings shared by
+/// every unit that runs a program.
+#[derive(Debug, Clone, Default)]
+pub struct UnitEnvironment {
+    vars: Vec<(String, String)>,
+
+    /// Files to read variables from, and whether it's okay for them to be missing.
+    files: Vec<(PathBuf, bool)>,
+}
+
+impl UnitEnvironment {
+    /// Apply an environment-related directive from the given section.
+    /// Returns false if the key has nothing to do with the environment.
+    pub fn parse_directive(
+        &mut self,
+        section: &str,
+        key: &str,
+        value: Option<&str>,
+    ) -> Result<bool, UnitDescriptionError> {
+        match key {
+            "Environment" => match value {
+                // An empty assignment resets the list, as with systemd.
+                None | Some("") => self.vars.clear(),
+                Some(s) => {
+                    for assignment in Self::split_quoted(s) {
+                        match Self::parse_assignment(&assignment) {
+                            Some(var) => self.vars.push(var),
+                            None => {
+                                return Err(UnitDescriptionError::InvalidValue(
+                                    section.to_owned(),
+                                    key.to_owned(),
+                                    assignment,
+                                    vec!["NAME=value".to_owned()],
+                                ))
+                            }
+                        }
+                    }
+                }
+            },
+            "EnvironmentFile" => match value {
+                None | Some("") => self.files.clear(),
+                Some(s) => match s.strip_prefix('-') {
+                    Some(optional) => self.files.push((PathBuf::from(optional), true)),
+                    None => self.files.push((PathBuf::from(s), false)),
+                },
+            },
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Split a line into words on whitespace, keeping quoted strings together.
+    fn split_quoted(s: &str) -> Vec<String> {
+        let mut words = vec![];
+        let mut word = String::new();
+        let mut in_word = false;
+        let mut quote = None;
+        for c in s.chars() {
+            match quote {
+                Some(q) if c == q => quote = None,
+                Some(_) => word.push(c),
+                None if c == '"' || c == '\'' => {
+                    quote = Some(c);
+                    in_word = true;
+                }
+                None if c.is_whitespace() => {
+                    if in_word {
+                        words.push(word.clone());
+                        word.clear();
+                        in_word = false;
+                    }
+                }
+                None => {
+                    word.push(c);
+                    in_word = true;
+                }
+            }
+        }
+        if in_word {
+            words.push(word);
+        }
+        words
+    }
+
+    fn parse_assignment(s: &str) -> Option<(String, String)> {
+        let mut parts = s.splitn(2, '=');
+        let name = parts.next()?.trim();
+        let value = parts.next()?;
+        if name.is_empty() {
+            return None;
+        }
+        Some((name.to_owned(), value.to_owned()))
+    }
+
+    /// Read a file of NAME=value lines.  Blank lines and lines starting
+    /// with "#" or ";" are ignored, and values may be quoted.
+    fn read_file(path: &Path) -> io::Result<Vec<(String, String)>> {
+        let mut contents = String::new();
+        File::open(path)?.read_to_string(&mut contents)?;
+        let mut vars = vec![];
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            match Self::parse_assignment(line) {
+                Some((name, value)) => {
+                    let value = value.trim();
+                    let unquoted = Self::split_quoted(value);
+                    let value = match unquoted.len() {
+                        1 => unquoted[0].clone(),
+                        _ => value.to_owned(),
+                    };
+                    vars.push((name, value));
+                }
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{}: not a NAME=value line: {}", path.display(), line),
+                    ))
+                }
+            }
+        }
+        Ok(vars)
+    }
+
+    /// The unit's variables, with anything from its EnvironmentFiles after the
+    /// Environment= settings.  Relative paths are relative to the unit's directory.
+    pub fn resolve(&self, unit_directory: &Path) -> io::Result<Vec<(String, String)>> {
+        let mut vars = self.vars.clone();
+        for (path, optional) in &self.files {
+            let path = unit_directory.join(path);
+            match Self::read_file(&path) {
+                Ok(mut file_vars) => vars.append(&mut file_vars),
+                Err(ref e) if *optional && e.kind() == io::ErrorKind::NotFound => (),
+                Err(e) => {
+                    return Err(io::Error::new(
+                        e.kind(),
+                        format!("{}: {}", path.display(), e),
+                    ))
+                }
+            }
+        }
+        Ok(vars)
+    }
+}
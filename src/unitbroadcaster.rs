@@ -5,7 +5,7 @@ use std::sync::{Arc, Mutex};
 use std::time;
 
 use unit::{UnitKind, UnitName};
-use unitmanager::ManagerControlMessage;
+use unitmanager::{ManagerControlMessage, ManagerStatusMessage};
 
 #[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub enum UnitStatus {
@@ -360,6 +360,9 @@ pub enum UnitEvent {
     /// A unit made a request to a Manager, which will be passed to the main thread.
     ManagerRequest(ManagerControlMessage),
 
+    /// The Manager sent a status message to its interfaces.
+    ManagerStatus(ManagerStatusMessage),
+
     /// The system is shutting down.
     Shutdown,
 }
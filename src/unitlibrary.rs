@@ -12,7 +12,7 @@ use unitmanager::UnitManager;
 use units::interface::InterfaceDescription;
 use units::jig::JigDescription;
 use units::logger::LoggerDescription;
-use units::scenario::ScenarioDescription;
+use units::scenario::{PlannedTest, ScenarioDescription};
 use units::test::TestDescription;
 use units::trigger::TriggerDescription;
 
@@ -267,6 +267,34 @@ impl UnitLibrary {
             }
         }
 
+        // 2b. Mark scenarios that include a dirty scenario as dirty, so that they pick up its changes.
+        loop {
+            let mut includers = vec![];
+            for (scenario_name, scenario_description) in self.scenario_descriptions.borrow().iter()
+            {
+                if self.dirty_scenarios.borrow().contains_key(scenario_name) {
+                    continue;
+                }
+                if let Some(UnitStatus::LoadFailed(_)) = statuses.get(scenario_name) {
+                    continue;
+                }
+                if self
+                    .dirty_scenarios
+                    .borrow()
+                    .keys()
+                    .any(|dirty| scenario_description.includes_scenario(dirty))
+                {
+                    includers.push(scenario_name.clone());
+                }
+            }
+            if includers.is_empty() {
+                break;
+            }
+            for scenario_name in includers {
+                self.dirty_scenarios.borrow_mut().insert(scenario_name, ());
+            }
+        }
+
         // 3. Delete any "dirty" objects that were Deleted.
         {
             let mut to_remove = vec![];
@@ -398,7 +426,17 @@ impl UnitLibrary {
         // 8. Load all Tests that are compatible with this Jig.
         load_units!(self, statuses, dirty_tests, test_descriptions, load_test);
 
-        // 9. Load all Scenarios that are compatible with this Jig.
+        // 9. Load all Scenarios that are compatible with this Jig, once
+        //    we know which scenarios each one includes.
+        {
+            let mut descriptions = self.scenario_descriptions.borrow_mut();
+            let all_descriptions = descriptions.clone();
+            for (id, _) in self.dirty_scenarios.borrow().iter() {
+                if let Some(description) = descriptions.get_mut(id) {
+                    description.resolve_includes(&all_descriptions);
+                }
+            }
+        }
         load_units!(
             self,
             statuses,
@@ -428,10 +466,7 @@ impl UnitLibrary {
     pub fn process_message(&self, evt: &UnitEvent) {
         match evt {
             UnitEvent::Status(ref msg) => {
-                let &UnitStatusEvent {
-                    ref name,
-                    ref status,
-                } = msg;
+                let UnitStatusEvent { name, status } = msg;
 
                 match status {
                     UnitStatus::LoadStarted(ref path) => {
@@ -554,6 +589,36 @@ impl UnitLibrary {
         self.unit_manager.borrow().process_message(evt);
     }
 
+    /// Work out which tests a scenario would run for the given jig, and why,
+    /// without running anything.  The jig is assumed to be attached.
+    pub fn plan(
+        &self,
+        jig_name: Option<&UnitName>,
+        scenario_name: &UnitName,
+    ) -> Result<Vec<PlannedTest>, String> {
+        let manager = self.unit_manager.borrow();
+        if let Some(jig_name) = jig_name {
+            match self.jig_descriptions.borrow().get(jig_name) {
+                Some(description) => manager.assume_jig(description),
+                None => return Err(format!("jig {} not found", jig_name)),
+            }
+        }
+        for description in self.test_descriptions.borrow().values() {
+            manager.load_test(description).ok();
+        }
+
+        let descriptions = self.scenario_descriptions.borrow();
+        let mut description = match descriptions.get(scenario_name) {
+            Some(description) => description.clone(),
+            None => return Err(format!("scenario {} not found", scenario_name)),
+        };
+        description.resolve_includes(&descriptions);
+        manager
+            .load_scenario(&description)
+            .and_then(|_| description.plan(&manager))
+            .map_err(|e| format!("unable to plan scenario {}: {}", scenario_name, e))
+    }
+
     #[cfg(test)]
     pub fn get_manager(&self) -> &RefCell<UnitManager> {
         &self.unit_manager
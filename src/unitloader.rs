@@ -24,6 +24,7 @@ impl UnitLoader {
             UnitEvent::Category(_) => (),
             UnitEvent::Log(_) => (),
             UnitEvent::ManagerRequest(_) => (),
+            UnitEvent::ManagerStatus(_) => (),
         }
     }
 
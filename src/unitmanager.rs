@@ -1,17 +1,22 @@
 // The UnitManager contains all units that are Selected.  This includes
 // units that are Active.
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
-use config::Config;
+use serde_json::Value;
+
+use config::{Config, RESERVED_VAR_PREFIX};
 use unit::{
-    UnitActivateError, UnitDeactivateError, UnitDeselectError, UnitIncompatibleReason, UnitKind,
-    UnitName, UnitSelectError,
+    RestartPolicy, UnitActivateError, UnitDeactivateError, UnitDeselectError,
+    UnitIncompatibleReason, UnitKind, UnitName, UnitSelectError,
 };
 use unitbroadcaster::{LogEntry, UnitBroadcaster, UnitEvent, UnitStatus, UnitStatusEvent};
 use units::interface::{Interface, InterfaceDescription};
@@ -53,7 +58,7 @@ macro_rules! load {
     }};
 }
 
-#[derive(Debug, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub enum FieldType {
     Name,
     Description,
@@ -69,7 +74,7 @@ impl fmt::Display for FieldType {
 }
 
 /// Messages for Library -> Unit communication
-#[derive(Debug, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub enum ManagerStatusMessage {
     /// Return the first name of the jig we're running on.
     Jig(
@@ -101,7 +106,14 @@ pub enum ManagerStatusMessage {
     Running(UnitName),
 
     /// A scenario has started
-    Start(UnitName),
+    Start(UnitName, RunMetadata),
+
+    /// The run that just started picks up where the last one left off,
+    /// keeping the results of the tests that passed then.
+    Resumed(
+        UnitName,      /* Scenario name */
+        Vec<UnitName>, /* Tests kept */
+    ),
 
     /// Indicates that a test passed successfully.
     Pass(UnitName, String /* log message */),
@@ -113,15 +125,264 @@ pub enum ManagerStatusMessage {
         String, /* log message */
     ),
 
+    /// Indicates that a test failed, but will be run again.
+    Retry(
+        UnitName,
+        u32,    /* attempt that failed */
+        i32,    /* return code */
+        String, /* log message */
+    ),
+
     /// Indicates that a test was skipped for some reason.
     Skipped(UnitName, String /* reason */),
 
     /// Sent when a scenario has finished running.
     Finished(
-        UnitName, /* Scenario name */
-        u32,      /* Result code */
-        String,   /* Reason for finishing */
+        UnitName,        /* Scenario name */
+        u32,             /* Result code */
+        String,          /* Reason for finishing */
+        Option<PathBuf>, /* Run directory */
     ),
+
+    /// Make sure the unit is still alive.  It must answer with a PONG.
+    Ping(u32 /* Ping ID */),
+
+    /// A snapshot of the current scenario, sent in response to STATUS.
+    ScenarioStatus(
+        UnitName,         /* Scenario name */
+        String,           /* State */
+        Option<Duration>, /* Time elapsed, if it has been started */
+        Option<Duration>, /* Timeout */
+    ),
+
+    /// A snapshot of one test in the current scenario, sent in response to STATUS.
+    TestStatus(
+        UnitName,         /* Test name */
+        String,           /* State */
+        Option<Duration>, /* Time elapsed, if it has been started */
+        Option<Duration>, /* Timeout */
+        String,           /* Reason for failure */
+    ),
+
+    /// A test is asking the operator a question.  The first ANSWER wins.
+    Ask(
+        u32,      /* Prompt ID */
+        UnitName, /* Test name */
+        String,   /* Question */
+    ),
+
+    /// A test reported a measurement, and it was checked against the test's limits.
+    Measurement(UnitName /* Test name */, Measurement),
+
+    /// A test set a variable, which is added to the run's metadata.
+    Set(
+        UnitName, /* Test name */
+        String,   /* Key */
+        String,   /* Value */
+    ),
+
+    /// A run of a repeating scenario finished, along with the totals so far.
+    Iteration(UnitName /* Scenario name */, Iteration),
+}
+
+impl ManagerStatusMessage {
+    /// Encode this message as a JSON object, suitable for line-delimited
+    /// JSON streams.  Every object carries a "type" field naming the message.
+    pub fn to_json(&self) -> Value {
+        match self {
+            ManagerStatusMessage::Jig(jig) => json!({
+                "type": "jig",
+                "jig": jig.as_ref().map(|j| j.id()),
+            }),
+            ManagerStatusMessage::Scenarios(list) => json!({
+                "type": "scenarios",
+                "scenarios": list.iter().map(|s| s.id()).collect::<Vec<_>>(),
+            }),
+            ManagerStatusMessage::Scenario(scenario) => json!({
+                "type": "scenario",
+                "scenario": scenario.as_ref().map(|s| s.id()),
+            }),
+            ManagerStatusMessage::Tests(scenario, tests) => json!({
+                "type": "tests",
+                "scenario": scenario.id(),
+                "tests": tests.iter().map(|t| t.id()).collect::<Vec<_>>(),
+            }),
+            ManagerStatusMessage::Hello(version) => json!({
+                "type": "hello",
+                "version": version,
+            }),
+            ManagerStatusMessage::Describe(id, field, value) => json!({
+                "type": "describe",
+                "unit": id.id(),
+                "unit_type": id.kind().to_string(),
+                "field": field.to_string(),
+                "value": value,
+            }),
+            ManagerStatusMessage::Log(l) => json!({
+                "type": "log",
+                "message_type": l.kind().as_str(),
+                "unit": l.id().id(),
+                "unit_type": l.id().kind().to_string(),
+                "unix_time": l.secs(),
+                "unix_time_nsecs": l.nsecs(),
+                "message": l.message(),
+            }),
+            ManagerStatusMessage::Running(test) => json!({
+                "type": "running",
+                "test": test.id(),
+            }),
+            ManagerStatusMessage::Start(scenario, metadata) => json!({
+                "type": "start",
+                "scenario": scenario.id(),
+                "metadata": metadata,
+            }),
+            ManagerStatusMessage::Resumed(scenario, tests) => json!({
+                "type": "resume",
+                "scenario": scenario.id(),
+                "tests": tests.iter().map(|t| t.id()).collect::<Vec<_>>(),
+            }),
+            ManagerStatusMessage::Pass(test, message) => json!({
+                "type": "pass",
+                "test": test.id(),
+                "message": message,
+            }),
+            ManagerStatusMessage::Fail(test, code, message) => json!({
+                "type": "fail",
+                "test": test.id(),
+                "code": code,
+                "message": message,
+            }),
+            ManagerStatusMessage::Retry(test, attempt, code, message) => json!({
+                "type": "retry",
+                "test": test.id(),
+                "attempt": attempt,
+                "code": code,
+                "message": message,
+            }),
+            ManagerStatusMessage::Skipped(test, reason) => json!({
+                "type": "skip",
+                "test": test.id(),
+                "reason": reason,
+            }),
+            ManagerStatusMessage::Finished(scenario, result, reason, run_directory) => json!({
+                "type": "finish",
+                "scenario": scenario.id(),
+                "result": result,
+                "reason": reason,
+                "run_directory": run_directory,
+            }),
+            ManagerStatusMessage::Ping(id) => json!({
+                "type": "ping",
+                "id": id,
+            }),
+            ManagerStatusMessage::ScenarioStatus(scenario, state, elapsed, timeout) => json!({
+                "type": "status",
+                "scenario": scenario.id(),
+                "state": state,
+                "elapsed_ms": elapsed.map(|d| d.as_millis() as u64),
+                "timeout_ms": timeout.map(|d| d.as_millis() as u64),
+            }),
+            ManagerStatusMessage::TestStatus(test, state, elapsed, timeout, reason) => json!({
+                "type": "test_status",
+                "test": test.id(),
+                "state": state,
+                "elapsed_ms": elapsed.map(|d| d.as_millis() as u64),
+                "timeout_ms": timeout.map(|d| d.as_millis() as u64),
+                "reason": reason,
+            }),
+            ManagerStatusMessage::Ask(id, test, question) => json!({
+                "type": "ask",
+                "id": id,
+                "test": test.id(),
+                "question": question,
+            }),
+            ManagerStatusMessage::Measurement(test, m) => json!({
+                "type": "measurement",
+                "test": test.id(),
+                "name": m.name,
+                "value": m.value,
+                "units": m.units,
+                "low": m.low,
+                "high": m.high,
+                "passed": m.passed,
+            }),
+            ManagerStatusMessage::Set(test, key, value) => json!({
+                "type": "set",
+                "test": test.id(),
+                "key": key,
+                "value": value,
+            }),
+            ManagerStatusMessage::Iteration(scenario, i) => json!({
+                "type": "iteration",
+                "scenario": scenario.id(),
+                "iteration": i.number,
+                "result": if i.passed { "pass" } else { "fail" },
+                "passed": i.total_passed,
+                "failed": i.total_failed,
+                "last": i.last,
+            }),
+        }
+    }
+}
+
+/// Extra information attached to a run, such as a scanned serial number.
+pub type RunMetadata = BTreeMap<String, String>;
+
+/// How a test that was kept from an earlier run is reported when a run is resumed.
+pub const RESUMED_MESSAGE: &str = "passed in an earlier run";
+
+/// The run metadata key, or test variable, that identifies the device under test.
+pub const DUT_KEY: &str = "serial";
+
+/// A value reported by a test, along with the limits it was checked against.
+#[derive(Debug, Clone)]
+pub struct Measurement {
+    pub name: String,
+    pub value: f64,
+    pub units: Option<String>,
+    pub low: Option<f64>,
+    pub high: Option<f64>,
+    pub passed: bool,
+}
+
+// Messages must be comparable and hashable, so numbers are compared bit-for-bit.
+impl PartialEq for Measurement {
+    fn eq(&self, other: &Measurement) -> bool {
+        self.name == other.name
+            && self.value.to_bits() == other.value.to_bits()
+            && self.units == other.units
+            && self.low.map(f64::to_bits) == other.low.map(f64::to_bits)
+            && self.high.map(f64::to_bits) == other.high.map(f64::to_bits)
+            && self.passed == other.passed
+    }
+}
+
+impl Eq for Measurement {}
+
+impl Hash for Measurement {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        self.value.to_bits().hash(state);
+        self.units.hash(state);
+        self.low.map(f64::to_bits).hash(state);
+        self.high.map(f64::to_bits).hash(state);
+        self.passed.hash(state);
+    }
+}
+
+/// The result of one run of a repeating scenario.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Iteration {
+    /// Which run this was, starting from 1.
+    pub number: u32,
+    pub passed: bool,
+
+    /// How many runs have passed and failed so far, including this one.
+    pub total_passed: u32,
+    pub total_failed: u32,
+
+    /// Whether the scenario is done repeating.
+    pub last: bool,
 }
 
 /// Messages for Unit -> Library communication
@@ -139,12 +400,19 @@ pub enum ManagerControlMessageContents {
     /// Get a list of tests, either from the current scenario (None) or a specific scenario (Some)
     Tests(Option<UnitName>),
 
+    /// Get the state of the current scenario and each of its tests.
+    Status,
+
     /// An error message from a particular interface.
     Error(String /* Error message contents */),
 
     /// Sent to a unit when it is first loaded, including "HELLO" messages.
     InitialGreeting,
 
+    /// A request from one client of an interface that accepts connections.
+    /// Replies to it go to that client alone.
+    FromClient(u32 /* client ID */, Box<ManagerControlMessageContents>),
+
     /// Tells the Manager to advance the current scenario.
     AdvanceScenario(i32 /* result code of last step */),
 
@@ -163,9 +431,19 @@ pub enum ManagerControlMessageContents {
     /// Start running a scenario, or the default scenario if None
     StartScenario(Option<UnitName>),
 
+    /// Start running a scenario, and attach information such as a serial number to the run.
+    StartScenarioWithMetadata(Option<UnitName>, RunMetadata),
+
+    /// Run the current scenario again, keeping the tests that passed last time.
+    /// If the serial number of the device is given, it must match the last run's.
+    ResumeScenario(Option<String>),
+
     /// Start running a given test.
     StartTest(UnitName),
 
+    /// Run a single test on its own, optionally along with the tests it requires.
+    RunTest(UnitName, bool /* include dependencies */),
+
     /// Stop running a given test.
     StopTest(UnitName),
 
@@ -192,6 +470,36 @@ pub enum ManagerControlMessageContents {
 
     /// Abort the currently-running tests
     AbortTests,
+
+    /// A unit has answered a PING with the given ID.
+    Pong(String /* Ping ID */),
+
+    /// Bring a unit back up after it exited, according to its restart policy.
+    RestartUnit,
+
+    /// Periodic request to ping supervised units and reap unresponsive ones.
+    CheckLiveness,
+
+    /// A scenario's Timeout has passed.
+    ScenarioTimedOut,
+
+    /// A repeating scenario has finished one run.
+    Iteration(Iteration),
+
+    /// Start the next run of a repeating scenario.
+    RepeatScenario,
+
+    /// A test wants to ask the operator a question.
+    Ask(String /* Question */),
+
+    /// Answer a question asked by a test.
+    Answer(String /* Prompt ID */, String /* Answer */),
+
+    /// A test has reported a measurement.
+    Measurement(Measurement),
+
+    /// A test has set a variable for the rest of the run.
+    Set(String /* Key */, String /* Value */),
 }
 
 #[derive(PartialEq, Eq, Hash, Debug, Clone)]
@@ -207,6 +515,21 @@ impl ManagerControlMessage {
             contents,
         }
     }
+
+    /// A request from the given client of a unit, if it has more than one.
+    pub fn from_client(
+        id: &UnitName,
+        client: Option<u32>,
+        contents: ManagerControlMessageContents,
+    ) -> Self {
+        match client {
+            Some(client) => Self::new(
+                id,
+                ManagerControlMessageContents::FromClient(client, Box::new(contents)),
+            ),
+            None => Self::new(id, contents),
+        }
+    }
 }
 
 pub struct UnitManager {
@@ -245,6 +568,30 @@ pub struct UnitManager {
 
     /// A list of active units.  These units must also be selected.
     active: Rc<RefCell<HashMap<UnitName, ()>>>,
+
+    /// PINGs that have been sent but not yet answered, along with when they were sent.
+    outstanding_pings: RefCell<HashMap<UnitName, (u32, Instant)>>,
+
+    /// The ID to use for the next PING.
+    next_ping_id: RefCell<u32>,
+
+    /// The selected scenario, set aside while a single test is being run.
+    parked_scenario: RefCell<Option<Rc<RefCell<Scenario>>>>,
+
+    /// True if the current scenario was made up to run a single test.
+    running_single_test: RefCell<bool>,
+
+    /// The metadata the current scenario was started with, for repeated runs.
+    run_metadata: RefCell<RunMetadata>,
+
+    /// When each unit was recently restarted, for enforcing StartLimitBurst.
+    restart_history: RefCell<HashMap<UnitName, Vec<Instant>>>,
+
+    /// Questions that have been asked but not yet answered, and who asked them.
+    outstanding_prompts: RefCell<HashMap<u32, UnitName>>,
+
+    /// The ID to use for the next prompt.
+    next_prompt_id: RefCell<u32>,
 }
 
 impl UnitManager {
@@ -254,6 +601,10 @@ impl UnitManager {
         let monitor_broadcaster = broadcaster.clone();
         thread::spawn(move || Self::control_message_monitor(receiver, monitor_broadcaster));
 
+        let ping_interval = *config.lock().unwrap().ping_interval();
+        let liveness_sender = sender.clone();
+        thread::spawn(move || Self::liveness_timer(liveness_sender, ping_interval));
+
         UnitManager {
             cfg: config.clone(),
             bc: broadcaster.clone(),
@@ -272,6 +623,15 @@ impl UnitManager {
             current_jig: Rc::new(RefCell::new(None)),
 
             control_sender: sender,
+
+            outstanding_pings: RefCell::new(HashMap::new()),
+            next_ping_id: RefCell::new(0),
+            parked_scenario: RefCell::new(None),
+            running_single_test: RefCell::new(false),
+            run_metadata: RefCell::new(RunMetadata::new()),
+            restart_history: RefCell::new(HashMap::new()),
+            outstanding_prompts: RefCell::new(HashMap::new()),
+            next_prompt_id: RefCell::new(0),
         }
     }
 
@@ -285,6 +645,23 @@ impl UnitManager {
         }
     }
 
+    /// Runs in a separate thread and periodically asks the manager to check on its units.
+    fn liveness_timer(sender: Sender<ManagerControlMessage>, interval: Duration) {
+        let id = UnitName::internal("supervisor");
+        loop {
+            thread::sleep(interval);
+            if sender
+                .send(ManagerControlMessage::new(
+                    &id,
+                    ManagerControlMessageContents::CheckLiveness,
+                ))
+                .is_err()
+            {
+                break;
+            }
+        }
+    }
+
     pub fn get_control_channel(&self) -> Sender<ManagerControlMessage> {
         self.control_sender.clone()
     }
@@ -317,6 +694,15 @@ impl UnitManager {
         load!(self, jigs, desceription)
     }
 
+    /// Treat a jig as present without checking for it, so that the units
+    /// for a jig that isn't attached can still be planned.
+    pub fn assume_jig(&self, description: &JigDescription) {
+        self.jigs.borrow_mut().insert(
+            description.id().clone(),
+            Rc::new(RefCell::new(Jig::new(description))),
+        );
+    }
+
     pub fn load_scenario(
         &self,
         desceription: &ScenarioDescription,
@@ -629,7 +1015,7 @@ impl UnitManager {
     fn activate_interface(&self, id: &UnitName) -> Result<(), UnitActivateError> {
         // Activate the interface, which actually starts it up.
         match self.interfaces.borrow().get(id) {
-            Some(i) => i.borrow_mut().activate(self, &*self.cfg.lock().unwrap()),
+            Some(i) => i.borrow_mut().activate(self, &self.cfg.lock().unwrap()),
             None => Err(UnitActivateError::UnitNotFound),
         }
     }
@@ -637,7 +1023,7 @@ impl UnitManager {
     fn activate_trigger(&self, id: &UnitName) -> Result<(), UnitActivateError> {
         // Activate the interface, which actually starts it up.
         match self.triggers.borrow().get(id) {
-            Some(i) => i.borrow_mut().activate(self, &*self.cfg.lock().unwrap()),
+            Some(i) => i.borrow_mut().activate(self, &self.cfg.lock().unwrap()),
             None => Err(UnitActivateError::UnitNotFound),
         }
     }
@@ -645,7 +1031,7 @@ impl UnitManager {
     fn activate_logger(&self, id: &UnitName) -> Result<(), UnitActivateError> {
         // Activate the interface, which actually starts it up.
         match self.loggers.borrow().get(id) {
-            Some(i) => i.borrow_mut().activate(self, &*self.cfg.lock().unwrap()),
+            Some(i) => i.borrow_mut().activate(self, &self.cfg.lock().unwrap()),
             None => Err(UnitActivateError::UnitNotFound),
         }
     }
@@ -662,7 +1048,7 @@ impl UnitManager {
                     Err(UnitActivateError::UnitNotSelected)
                 } else {
                     // Activate this jig.
-                    s.borrow_mut().activate(self, &*self.cfg.lock().unwrap())
+                    s.borrow_mut().activate(self, &self.cfg.lock().unwrap())
                 }
             }
         }
@@ -684,7 +1070,7 @@ impl UnitManager {
                         id.clone(),
                         "scenario starting".to_string(),
                     )));
-                    s.borrow_mut().activate(self, &*self.cfg.lock().unwrap())
+                    s.borrow_mut().activate(self, &self.cfg.lock().unwrap())
                 }
             }
         }
@@ -693,7 +1079,7 @@ impl UnitManager {
     fn activate_test(&self, id: &UnitName) -> Result<(), UnitActivateError> {
         match self.tests.borrow().get(id) {
             None => Err(UnitActivateError::UnitNotFound),
-            Some(s) => s.borrow_mut().activate(self, &*self.cfg.lock().unwrap()),
+            Some(s) => s.borrow_mut().activate(self, &self.cfg.lock().unwrap()),
         }
     }
 
@@ -703,16 +1089,10 @@ impl UnitManager {
             return;
         }
 
-        let result = match *id.kind() {
-            UnitKind::Interface => self.deactivate_interface(id),
-            UnitKind::Jig => self.deactivate_jig(id),
-            UnitKind::Logger => self.deactivate_logger(id),
-            UnitKind::Scenario => self.deactivate_scenario(id),
-            UnitKind::Test => self.deactivate_test(id),
-            UnitKind::Trigger => self.deactivate_trigger(id),
-            UnitKind::Internal => Ok(()),
-        };
-        match result {
+        // An inactive unit can't answer PINGs, so stop waiting for them.
+        self.outstanding_pings.borrow_mut().remove(id);
+
+        match self.stop_unit(id) {
             Ok(_) => {
                 self.active.borrow_mut().remove(id);
                 self.bc
@@ -731,6 +1111,103 @@ impl UnitManager {
         }
     }
 
+    fn stop_unit(&self, id: &UnitName) -> Result<(), UnitDeactivateError> {
+        match *id.kind() {
+            UnitKind::Interface => self.deactivate_interface(id),
+            UnitKind::Jig => self.deactivate_jig(id),
+            UnitKind::Logger => self.deactivate_logger(id),
+            UnitKind::Scenario => self.deactivate_scenario(id),
+            UnitKind::Test => self.deactivate_test(id),
+            UnitKind::Trigger => self.deactivate_trigger(id),
+            // The only internal unit that runs is the scenario made up to run
+            // a single test.
+            UnitKind::Internal => self.deactivate_scenario(id),
+        }
+    }
+
+    /// A long-running unit has exited or stopped responding.  Reap it,
+    /// and bring it back up if its restart policy says to.
+    fn reap_unit(&self, id: &UnitName, reason: String, failed: bool) {
+        // The process has already gone away, so the unit is no longer active
+        // even if it didn't exit cleanly.
+        let failed = self.stop_unit(id).is_err() || failed;
+        self.active.borrow_mut().remove(id);
+        self.outstanding_pings.borrow_mut().remove(id);
+
+        self.bc
+            .broadcast(&UnitEvent::Status(UnitStatusEvent::new_active_failed(
+                id, reason,
+            )));
+
+        self.schedule_restart(id, failed);
+    }
+
+    fn restart_policy(&self, id: &UnitName) -> Option<RestartPolicy> {
+        match *id.kind() {
+            UnitKind::Interface => self
+                .interfaces
+                .borrow()
+                .get(id)
+                .map(|u| u.borrow().restart_policy().clone()),
+            UnitKind::Logger => self
+                .loggers
+                .borrow()
+                .get(id)
+                .map(|u| u.borrow().restart_policy().clone()),
+            UnitKind::Trigger => self
+                .triggers
+                .borrow()
+                .get(id)
+                .map(|u| u.borrow().restart_policy().clone()),
+            _ => None,
+        }
+    }
+
+    /// Arrange for a unit to be activated again after its RestartSec delay,
+    /// unless it has already been restarted StartLimitBurst times recently.
+    fn schedule_restart(&self, id: &UnitName, failed: bool) {
+        let policy = match self.restart_policy(id) {
+            Some(p) => p,
+            None => return,
+        };
+        if !policy.should_restart(failed) {
+            return;
+        }
+
+        let now = Instant::now();
+        {
+            let mut history = self.restart_history.borrow_mut();
+            let restarts = history.entry(id.clone()).or_default();
+            restarts.retain(|t| now.duration_since(*t) < *policy.start_limit_interval());
+            if restarts.len() as u32 >= policy.start_limit_burst() {
+                self.bc
+                    .broadcast(&UnitEvent::Status(UnitStatusEvent::new_active_failed(
+                        id,
+                        format!(
+                            "start limit hit: restarted {} times within {} seconds",
+                            restarts.len(),
+                            policy.start_limit_interval().as_secs()
+                        ),
+                    )));
+                return;
+            }
+            restarts.push(now);
+        }
+
+        let sender = self.control_sender.clone();
+        let restart_id = id.clone();
+        let delay = *policy.delay();
+        thread::spawn(move || {
+            thread::sleep(delay);
+            sender
+                .send(ManagerControlMessage::new(
+                    &restart_id,
+                    ManagerControlMessageContents::RestartUnit,
+                ))
+                .ok();
+        });
+    }
+
     fn deactivate_interface(&self, id: &UnitName) -> Result<(), UnitDeactivateError> {
         let interfaces = self.interfaces.borrow();
         match interfaces.get(id) {
@@ -902,7 +1379,7 @@ impl UnitManager {
                     }
                 }
                 for (unit_id, reason) in units_to_deactivate {
-                    self.deactivate(&unit_id, &reason);
+                    self.communication_failed(&unit_id, reason);
                 }
             }
             _ => (),
@@ -910,10 +1387,7 @@ impl UnitManager {
     }
 
     fn status_message(&self, msg: &UnitStatusEvent) {
-        let &UnitStatusEvent {
-            ref name,
-            ref status,
-        } = msg;
+        let UnitStatusEvent { name, status } = msg;
         match status {
             UnitStatus::Loaded => match name.kind() {
                 UnitKind::Jig => self.broadcast_jig_named(name),
@@ -931,15 +1405,23 @@ impl UnitManager {
     }
 
     fn manager_request(&self, msg: &ManagerControlMessage) {
-        let &ManagerControlMessage {
-            sender: ref sender_name,
-            contents: ref msg,
+        let ManagerControlMessage {
+            sender: sender_name,
+            contents: msg,
         } = msg;
 
+        // Replies go to just the client that asked, if the request came from one.
+        let (client, msg) = match *msg {
+            ManagerControlMessageContents::FromClient(client, ref inner) => {
+                (Some(client), &**inner)
+            }
+            ref msg => (None, msg),
+        };
+
         match *msg {
-            ManagerControlMessageContents::Scenarios => self.send_scenarios_to(sender_name),
+            ManagerControlMessageContents::Scenarios => self.send_scenarios_to(sender_name, client),
             ManagerControlMessageContents::Tests(ref scenario_name) => {
-                self.send_tests_to(sender_name, scenario_name)
+                self.send_tests_to(sender_name, client, scenario_name)
             }
             ManagerControlMessageContents::Log(ref txt) => self.bc.broadcast(&UnitEvent::Log(
                 LogEntry::new_info(sender_name.clone(), txt.clone()),
@@ -964,23 +1446,31 @@ impl UnitManager {
                     err.clone(),
                 )));
             }
-            ManagerControlMessageContents::Jig => self.send_jig_to(sender_name),
+            ManagerControlMessageContents::Jig => self.send_jig_to(sender_name, client),
             ManagerControlMessageContents::InitialGreeting => {
                 // Send some initial information to the client.
-                self.send_hello_to(sender_name);
-                self.send_jig_to(sender_name);
-                self.send_scenarios_to(sender_name);
+                self.send_hello_to(sender_name, client);
+                self.send_jig_to(sender_name, client);
+                self.send_scenarios_to(sender_name, client);
                 // If there is a scenario selected, send that too.
                 if let Some(ref sc) = *self.current_scenario.borrow() {
-                    self.send_scenario_to(sender_name, &sc.borrow().id().clone());
+                    self.send_scenario_to(sender_name, client, &sc.borrow().id().clone());
                 }
+                // Let the client catch up on any run in progress.
+                self.send_status_to(sender_name, client);
             }
+            ManagerControlMessageContents::Status => self.send_status_to(sender_name, client),
             ManagerControlMessageContents::ChildExited => {
-                self.bc
-                    .broadcast(&UnitEvent::Status(UnitStatusEvent::new_active_failed(
-                        sender_name,
-                        "Unit unexpectedly exited".to_owned(),
-                    )));
+                // Units that were deliberately deactivated also close their pipes.
+                if self.active.borrow().contains_key(sender_name) {
+                    self.reap_unit(sender_name, "Unit unexpectedly exited".to_owned(), false);
+                }
+            }
+            ManagerControlMessageContents::RestartUnit => {
+                // The unit may have been deselected while we were waiting.
+                if self.selected.borrow().contains_key(sender_name) {
+                    self.activate(sender_name);
+                }
             }
             ManagerControlMessageContents::AdvanceScenario(result) => {
                 match *self.current_scenario.borrow() {
@@ -999,31 +1489,14 @@ impl UnitManager {
                 )));
             }
             ManagerControlMessageContents::StartScenario(ref scenario_name_opt) => {
-                // If a scenario exists and is running, don't start a new one.
-                if let Some(ref scenario) = *self.current_scenario.borrow() {
-                    if scenario.borrow().is_running() {
-                        self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
-                            sender_name.clone(),
-                            "unable to start scenario: scenario not idle".to_owned(),
-                        )));
-                        return;
-                    }
-                }
-                let scenario_name = if let Some(ref scenario_name) = *scenario_name_opt {
-                    self.select(scenario_name);
-                    scenario_name.clone()
-                } else {
-                    match *self.current_scenario.borrow() {
-                        None => {
-                            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(sender_name.clone(), "unable to start scenario: no scenario selected and no scenario specified".to_owned())));
-                            return;
-                        }
-                        Some(ref scenario) => scenario.borrow().id().clone(),
-                    }
-                };
-
-                self.activate(&scenario_name);
-                self.broadcast_message(ManagerStatusMessage::Start(scenario_name));
+                self.start_scenario(sender_name, scenario_name_opt, RunMetadata::new())
+            }
+            ManagerControlMessageContents::StartScenarioWithMetadata(
+                ref scenario_name_opt,
+                ref metadata,
+            ) => self.start_scenario(sender_name, scenario_name_opt, metadata.clone()),
+            ManagerControlMessageContents::ResumeScenario(ref serial) => {
+                self.resume_scenario(sender_name, serial)
             }
             ManagerControlMessageContents::Skip(ref test_name, ref reason) => {
                 self.broadcast_skipped(test_name, reason);
@@ -1032,22 +1505,58 @@ impl UnitManager {
                 self.broadcast_message(ManagerStatusMessage::Running(sender_name.clone()));
             }
             ManagerControlMessageContents::TestFinished(result, ref message) => {
-                self.broadcast_message(match result {
-                    0 => ManagerStatusMessage::Pass(sender_name.clone(), message.clone()),
-                    i => ManagerStatusMessage::Fail(sender_name.clone(), i, message.clone()),
+                // Nobody is left to hear the answer to any open questions.
+                self.outstanding_prompts
+                    .borrow_mut()
+                    .retain(|_, test| test != sender_name);
+                // A failure that's about to be retried isn't the test's final result.
+                let retry = match *self.current_scenario.borrow() {
+                    Some(ref scenario) => scenario.borrow().pending_retry(sender_name, result),
+                    None => None,
+                };
+                self.broadcast_message(match (result, retry) {
+                    (0, _) => ManagerStatusMessage::Pass(sender_name.clone(), message.clone()),
+                    (i, Some(attempt)) => ManagerStatusMessage::Retry(
+                        sender_name.clone(),
+                        attempt,
+                        i,
+                        message.clone(),
+                    ),
+                    (i, None) => {
+                        ManagerStatusMessage::Fail(sender_name.clone(), i, message.clone())
+                    }
                 });
             }
             ManagerControlMessageContents::ScenarioFinished(code, ref message) => {
                 // Deactivate the current scenario.
                 // Since a scenario is finishing, the current scenario MUST not be None.
-                {
+                let run_directory = {
                     let cs = self.current_scenario.borrow();
-                    self.deactivate(cs.as_ref().unwrap().borrow().id(), message);
+                    let scenario = cs.as_ref().unwrap().borrow();
+                    self.deactivate(scenario.id(), message);
+                    scenario.run_directory()
+                };
+                self.broadcast_finished(sender_name, code, message, run_directory);
+
+                // Put back whatever scenario was selected before a single test was run.
+                if self.running_single_test.replace(false) {
+                    *self.current_scenario.borrow_mut() = self.parked_scenario.borrow_mut().take();
                 }
-                self.broadcast_finished(sender_name, code, message);
             }
             ManagerControlMessageContents::StartTest(ref test_name) => {
-                self.activate(test_name);
+                // A retry may come due after its scenario was stopped.
+                let wanted = match *self.current_scenario.borrow() {
+                    Some(ref scenario) if scenario.borrow().id() == sender_name => {
+                        scenario.borrow().accept_test_start(test_name)
+                    }
+                    _ => false,
+                };
+                if wanted {
+                    self.activate(test_name);
+                }
+            }
+            ManagerControlMessageContents::RunTest(ref test_name, with_dependencies) => {
+                self.run_single_test(sender_name, test_name, with_dependencies)
             }
             ManagerControlMessageContents::StopTest(ref test_name) => {
                 self.deactivate(test_name, "controller requested test stop");
@@ -1065,23 +1574,483 @@ impl UnitManager {
             }
             ManagerControlMessageContents::AbortTests => {
                 if let Some(scenario) = &mut *self.current_scenario.borrow_mut() {
-                    scenario.borrow().indicate_failure();
-                    for test in (&*self.tests.borrow()).values() {
+                    scenario.borrow().stop_repeating(&self.control_sender);
+                    scenario.borrow().abort(&self.control_sender);
+                    for test in (*self.tests.borrow()).values() {
                         test.borrow().deactivate(self).ok();
                     }
                 }
             }
+            ManagerControlMessageContents::Pong(ref ping_id) => {
+                self.receive_pong(sender_name, ping_id)
+            }
+            ManagerControlMessageContents::CheckLiveness => self.check_liveness(),
+            ManagerControlMessageContents::Iteration(ref iteration) => {
+                self.broadcast_message(ManagerStatusMessage::Iteration(
+                    sender_name.clone(),
+                    iteration.clone(),
+                ));
+            }
+            ManagerControlMessageContents::RepeatScenario => self.repeat_scenario(sender_name),
+            ManagerControlMessageContents::ScenarioTimedOut => {
+                if let Some(ref scenario) = *self.current_scenario.borrow() {
+                    let scenario = scenario.borrow();
+                    if scenario.id() == sender_name {
+                        scenario.time_out(&self.control_sender);
+                    }
+                }
+            }
+            ManagerControlMessageContents::Ask(ref question) => self.ask(sender_name, question),
+            ManagerControlMessageContents::Answer(ref prompt_id, ref answer) => {
+                self.answer(sender_name, prompt_id, answer)
+            }
+            ManagerControlMessageContents::Measurement(ref measurement) => {
+                self.broadcast_message(ManagerStatusMessage::Measurement(
+                    sender_name.clone(),
+                    measurement.clone(),
+                ));
+            }
+            ManagerControlMessageContents::Set(ref key, ref value) => {
+                self.set_variable(sender_name, key, value)
+            }
+            // Requests are only ever wrapped once.
+            ManagerControlMessageContents::FromClient(_, _) => (),
         }
     }
 
-    pub fn send_hello_to(&self, sender_name: &UnitName) {
-        self.send_messages_to(
+    /// Store a variable set by a test in the current scenario, so that it is
+    /// passed on to the tests and support commands that run after it.
+    fn set_variable(&self, test_name: &UnitName, key: &str, value: &str) {
+        let error = if key.is_empty() || key.contains('=') {
+            Some(format!("invalid variable name: {}", key))
+        } else if key.starts_with(RESERVED_VAR_PREFIX) {
+            Some(format!(
+                "variable names beginning with {} are reserved: {}",
+                RESERVED_VAR_PREFIX, key
+            ))
+        } else {
+            None
+        };
+        if let Some(message) = error {
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                test_name.clone(),
+                message,
+            )));
+            return;
+        }
+
+        match *self.current_scenario.borrow() {
+            Some(ref scenario) => scenario.borrow().set_variable(key, value),
+            None => {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    test_name.clone(),
+                    format!("no scenario is running to set {} in", key),
+                )));
+                return;
+            }
+        }
+        self.cfg.lock().unwrap().set_run_variable(key, value);
+        self.broadcast_message(ManagerStatusMessage::Set(
+            test_name.clone(),
+            key.to_owned(),
+            value.to_owned(),
+        ));
+    }
+
+    /// Forward a test's question to every interface.  If the test has a
+    /// PromptTimeout, answer it with the PromptDefault once that expires.
+    fn ask(&self, test_name: &UnitName, question: &str) {
+        let test = match self.get_test_named(test_name) {
+            Some(t) => t,
+            None => {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    test_name.clone(),
+                    "only tests may ask questions".to_owned(),
+                )));
+                return;
+            }
+        };
+
+        let prompt_id = {
+            let mut next_prompt_id = self.next_prompt_id.borrow_mut();
+            *next_prompt_id = next_prompt_id.wrapping_add(1);
+            *next_prompt_id
+        };
+        self.outstanding_prompts
+            .borrow_mut()
+            .insert(prompt_id, test_name.clone());
+
+        if let Some(timeout) = *test.borrow().prompt_timeout() {
+            let default = test.borrow().prompt_default().clone();
+            let thr_control = self.control_sender.clone();
+            let thr_test_name = test_name.clone();
+            thread::spawn(move || {
+                thread::sleep(timeout);
+                thr_control
+                    .send(ManagerControlMessage::new(
+                        &thr_test_name,
+                        ManagerControlMessageContents::Answer(prompt_id.to_string(), default),
+                    ))
+                    .ok();
+            });
+        }
+
+        self.broadcast_message(ManagerStatusMessage::Ask(
+            prompt_id,
+            test_name.clone(),
+            question.to_owned(),
+        ));
+    }
+
+    /// Write the first answer to a prompt to the stdin of the test that asked it.
+    fn answer(&self, sender_name: &UnitName, prompt_id: &str, answer: &str) {
+        let test_name = match prompt_id
+            .parse::<u32>()
+            .ok()
+            .and_then(|id| self.outstanding_prompts.borrow_mut().remove(&id))
+        {
+            Some(t) => t,
+            None => {
+                // A prompt timing out after it was answered is not an error.
+                if *sender_name.kind() != UnitKind::Test {
+                    self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                        sender_name.clone(),
+                        format!("no open prompt with id {}", prompt_id),
+                    )));
+                }
+                return;
+            }
+        };
+
+        self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(
+            test_name.clone(),
+            format!(
+                "prompt {} answered by {}: {}",
+                prompt_id, sender_name, answer
+            ),
+        )));
+        if let Some(test) = self.get_test_named(&test_name) {
+            if let Err(e) = test.borrow().answer(answer) {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    test_name.clone(),
+                    format!("unable to answer prompt: {}", e),
+                )));
+            }
+        }
+    }
+
+    /// Start running the named scenario, or the selected one if None.
+    fn start_scenario(
+        &self,
+        sender_name: &UnitName,
+        scenario_name_opt: &Option<UnitName>,
+        metadata: RunMetadata,
+    ) {
+        // If a scenario exists and is running, don't start a new one.
+        if let Some(ref scenario) = *self.current_scenario.borrow() {
+            if scenario.borrow().is_running() {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    sender_name.clone(),
+                    "unable to start scenario: scenario not idle".to_owned(),
+                )));
+                return;
+            }
+        }
+        let scenario_name = if let Some(ref scenario_name) = *scenario_name_opt {
+            self.select(scenario_name);
+            scenario_name.clone()
+        } else {
+            match *self.current_scenario.borrow() {
+                None => {
+                    self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                        sender_name.clone(),
+                        "unable to start scenario: no scenario selected and no scenario specified"
+                            .to_owned(),
+                    )));
+                    return;
+                }
+                Some(ref scenario) => scenario.borrow().id().clone(),
+            }
+        };
+
+        if !metadata.is_empty() {
+            let pairs: Vec<String> = metadata
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect();
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_info(
+                scenario_name.clone(),
+                format!("run metadata: {}", pairs.join(" ")),
+            )));
+        }
+
+        if let Some(ref scenario) = *self.current_scenario.borrow() {
+            scenario.borrow().reset_iterations();
+        }
+        *self.run_metadata.borrow_mut() = metadata.clone();
+        self.activate(&scenario_name);
+        self.broadcast_message(ManagerStatusMessage::Start(scenario_name, metadata));
+    }
+
+    /// Run the current scenario again for the same device, re-running only
+    /// the tests that didn't pass last time.
+    fn resume_scenario(&self, sender_name: &UnitName, serial: &Option<String>) {
+        let (scenario_name, kept, variables) = match *self.current_scenario.borrow() {
+            None => {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    sender_name.clone(),
+                    "unable to resume scenario: no scenario selected".to_owned(),
+                )));
+                return;
+            }
+            Some(ref scenario) => {
+                let scenario = scenario.borrow();
+                let result = if scenario.is_running() {
+                    Err("scenario not idle".to_owned())
+                } else {
+                    self.check_resumed_dut(&scenario.variables(), serial)
+                        .and_then(|_| scenario.prepare_resume())
+                };
+                match result {
+                    Ok(kept) => (scenario.id().clone(), kept, scenario.variables()),
+                    Err(e) => {
+                        self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                            sender_name.clone(),
+                            format!("unable to resume scenario: {}", e),
+                        )));
+                        return;
+                    }
+                }
+            }
+        };
+
+        // The run carries on with the metadata it was started with, along
+        // with anything its tests have set since.
+        let mut metadata = self.run_metadata.borrow().clone();
+        metadata.extend(variables);
+        self.activate(&scenario_name);
+        self.broadcast_message(ManagerStatusMessage::Start(scenario_name.clone(), metadata));
+        self.broadcast_message(ManagerStatusMessage::Resumed(scenario_name, kept));
+    }
+
+    /// Make sure a resumed run is for the same device as the last run.  The
+    /// last run's device is whatever its tests SET as the serial number, or
+    /// else the serial number it was started with.  If it had one, the
+    /// request to resume must name the same device.
+    fn check_resumed_dut(
+        &self,
+        variables: &RunMetadata,
+        serial: &Option<String>,
+    ) -> Result<(), String> {
+        let run_metadata = self.run_metadata.borrow();
+        match (
+            serial,
+            variables.get(DUT_KEY).or_else(|| run_metadata.get(DUT_KEY)),
+        ) {
+            (None, None) => Ok(()),
+            (None, Some(last)) => Err(format!(
+                "the last run was for device {}, so its {} must be given",
+                last, DUT_KEY
+            )),
+            (Some(serial), None) => Err(format!(
+                "the last run didn't record a {}, so it can't be matched to {}",
+                DUT_KEY, serial
+            )),
+            (Some(serial), Some(last)) if last != serial => Err(format!(
+                "device {} is not the device from the last run ({})",
+                serial, last
+            )),
+            (Some(_), Some(_)) => Ok(()),
+        }
+    }
+
+    /// Run a repeating scenario again, with the metadata it was first started with.
+    fn repeat_scenario(&self, scenario_name: &UnitName) {
+        match *self.current_scenario.borrow() {
+            Some(ref scenario) => {
+                let scenario = scenario.borrow();
+                // Someone else may have started a run while we were waiting.
+                if scenario.id() != scenario_name
+                    || scenario.is_running()
+                    || !scenario.is_repeating()
+                {
+                    return;
+                }
+            }
+            None => return,
+        }
+        self.activate(scenario_name);
+        self.broadcast_message(ManagerStatusMessage::Start(
+            scenario_name.clone(),
+            self.run_metadata.borrow().clone(),
+        ));
+    }
+
+    /// Run one test by building a scenario around it, and setting the
+    /// selected scenario aside until it finishes.
+    fn run_single_test(&self, sender_name: &UnitName, test_name: &UnitName, with_deps: bool) {
+        if let Some(ref scenario) = *self.current_scenario.borrow() {
+            if scenario.borrow().is_running() {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    sender_name.clone(),
+                    "unable to run test: scenario not idle".to_owned(),
+                )));
+                return;
+            }
+        }
+        if self.get_test_named(test_name).is_none() {
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                sender_name.clone(),
+                format!("unable to run test: test {} not found", test_name),
+            )));
+            return;
+        }
+
+        let scenario = match ScenarioDescription::for_test(test_name, with_deps, self)
+            .load(self, &self.cfg.lock().unwrap())
+        {
+            Ok(s) => Rc::new(RefCell::new(s)),
+            Err(e) => {
+                self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                    sender_name.clone(),
+                    format!("unable to run test {}: {}", test_name, e),
+                )));
+                return;
+            }
+        };
+        let scenario_id = scenario.borrow().id().clone();
+
+        if !self.running_single_test.replace(true) {
+            *self.parked_scenario.borrow_mut() = self.current_scenario.borrow_mut().take();
+        }
+        *self.current_scenario.borrow_mut() = Some(scenario.clone());
+        for test_id in &scenario.borrow().test_sequence() {
+            self.select(test_id);
+        }
+
+        let result = scenario
+            .borrow_mut()
+            .activate(self, &self.cfg.lock().unwrap());
+        match result {
+            Ok(_) => {
+                self.active.borrow_mut().insert(scenario_id.clone(), ());
+                self.bc
+                    .broadcast(&UnitEvent::Status(UnitStatusEvent::new_active(
+                        &scenario_id,
+                    )));
+                self.broadcast_message(ManagerStatusMessage::Start(
+                    scenario_id,
+                    RunMetadata::new(),
+                ));
+            }
+            Err(e) => {
+                self.bc
+                    .broadcast(&UnitEvent::Status(UnitStatusEvent::new_active_failed(
+                        &scenario_id,
+                        format!("unable to activate: {}", e),
+                    )));
+                self.running_single_test.replace(false);
+                *self.current_scenario.borrow_mut() = self.parked_scenario.borrow_mut().take();
+            }
+        }
+    }
+
+    /// Deactivate any unit that failed to answer its last PING in time,
+    /// then send a fresh PING to every supervised unit that is still active.
+    /// Timeouts are only noticed here, so in effect they are rounded up to a
+    /// multiple of the ping interval.
+    fn check_liveness(&self) {
+        let now = Instant::now();
+        let expired: Vec<(UnitName, u32, Duration)> = self
+            .outstanding_pings
+            .borrow()
+            .iter()
+            .filter_map(|(unit_id, (ping_id, sent))| {
+                let timeout = *self.restart_policy(unit_id)?.ping_timeout();
+                if now.duration_since(*sent) >= timeout {
+                    Some((unit_id.clone(), *ping_id, timeout))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        for (unit_id, ping_id, timeout) in expired {
+            let reason = format!(
+                "no PONG received for PING {} within {} ms",
+                ping_id,
+                timeout.as_millis()
+            );
+            self.reap_unit(&unit_id, reason, true);
+        }
+
+        // Only units that asked for Ping=yes are expected to answer.
+        let to_ping: Vec<UnitName> = self
+            .active
+            .borrow()
+            .keys()
+            .filter(|unit_id| {
+                self.restart_policy(unit_id)
+                    .map(|policy| policy.ping())
+                    .unwrap_or(false)
+            })
+            .filter(|unit_id| !self.outstanding_pings.borrow().contains_key(unit_id))
+            .filter(|unit_id| !self.is_listener(unit_id))
+            .cloned()
+            .collect();
+
+        for unit_id in to_ping {
+            let ping_id = {
+                let mut next_ping_id = self.next_ping_id.borrow_mut();
+                *next_ping_id = next_ping_id.wrapping_add(1);
+                *next_ping_id
+            };
+            self.outstanding_pings
+                .borrow_mut()
+                .insert(unit_id.clone(), (ping_id, Instant::now()));
+            self.send_messages_to(&unit_id, vec![ManagerStatusMessage::Ping(ping_id)]);
+        }
+    }
+
+    fn is_listener(&self, id: &UnitName) -> bool {
+        match self.interfaces.borrow().get(id) {
+            Some(interface) => interface.borrow().is_listener(),
+            None => false,
+        }
+    }
+
+    fn receive_pong(&self, sender_name: &UnitName, ping_id: &str) {
+        let matches = match self.outstanding_pings.borrow().get(sender_name) {
+            Some((expected, _)) => expected.to_string() == ping_id,
+            None => false,
+        };
+        if matches {
+            self.outstanding_pings.borrow_mut().remove(sender_name);
+        } else {
+            self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
+                sender_name.clone(),
+                format!("unexpected PONG {}", ping_id),
+            )));
+        }
+    }
+
+    pub fn send_status_to(&self, sender_name: &UnitName, client: Option<u32>) {
+        let messages = match *self.current_scenario.borrow() {
+            None => return,
+            Some(ref scenario) => scenario.borrow().status(),
+        };
+        self.send_replies_to(sender_name, client, messages);
+    }
+
+    pub fn send_hello_to(&self, sender_name: &UnitName, client: Option<u32>) {
+        self.send_replies_to(
             sender_name,
+            client,
             vec![ManagerStatusMessage::Hello("Jig/20 1.0".to_owned())],
         );
     }
 
-    pub fn send_jig_to(&self, sender_name: &UnitName) {
+    pub fn send_jig_to(&self, sender_name: &UnitName, client: Option<u32>) {
         let messages = match *self.current_jig.borrow() {
             None => vec![ManagerStatusMessage::Jig(None)],
             Some(ref jig_rc) => {
@@ -1101,11 +2070,11 @@ impl UnitManager {
                 ]
             }
         };
-        self.send_messages_to(sender_name, messages);
+        self.send_replies_to(sender_name, client, messages);
     }
 
     /// Send all available scenarios to the specified endpoint.
-    pub fn send_scenarios_to(&self, sender_name: &UnitName) {
+    pub fn send_scenarios_to(&self, sender_name: &UnitName, client: Option<u32>) {
         let mut messages = vec![ManagerStatusMessage::Scenarios(
             self.scenarios.borrow().keys().cloned().collect(),
         )];
@@ -1121,10 +2090,15 @@ impl UnitManager {
                 scenario.borrow().description().clone(),
             ));
         }
-        self.send_messages_to(sender_name, messages);
+        self.send_replies_to(sender_name, client, messages);
     }
 
-    pub fn send_scenario_to(&self, sender_name: &UnitName, scenario_name: &UnitName) {
+    pub fn send_scenario_to(
+        &self,
+        sender_name: &UnitName,
+        client: Option<u32>,
+        scenario_name: &UnitName,
+    ) {
         let messages = match self.scenarios.borrow().get(scenario_name) {
             None => vec![ManagerStatusMessage::Scenario(None)],
             Some(scenario_rc) => {
@@ -1151,12 +2125,17 @@ impl UnitManager {
                 messages
             }
         };
-        self.send_messages_to(sender_name, messages);
+        self.send_replies_to(sender_name, client, messages);
     }
 
     /// Send a list of tests to the specified recipient.
     /// If no scenario name is specified, send the current scenario.
-    pub fn send_tests_to(&self, sender_name: &UnitName, scenario_name_opt: &Option<UnitName>) {
+    pub fn send_tests_to(
+        &self,
+        sender_name: &UnitName,
+        client: Option<u32>,
+        scenario_name_opt: &Option<UnitName>,
+    ) {
         let scenario_id = match *scenario_name_opt {
             Some(ref n) => n.clone(),
             None => match *self.current_scenario.borrow() {
@@ -1171,8 +2150,11 @@ impl UnitManager {
                 }
             },
         };
-        let scenarios = self.scenarios.borrow();
-        let scenario_rc_opt = scenarios.get(&scenario_id);
+        // A scenario running a single test isn't in the list of scenarios.
+        let scenario_rc_opt = match *self.current_scenario.borrow() {
+            Some(ref cs) if *cs.borrow().id() == scenario_id => Some(cs.clone()),
+            _ => self.get_scenario_named(&scenario_id),
+        };
         match scenario_rc_opt {
             None => self.bc.broadcast(&UnitEvent::Log(LogEntry::new_error(
                 sender_name.clone(),
@@ -1180,8 +2162,9 @@ impl UnitManager {
             ))),
             Some(sc_ref) => {
                 let scenario = sc_ref.borrow();
-                self.send_messages_to(
+                self.send_replies_to(
                     sender_name,
+                    client,
                     vec![ManagerStatusMessage::Tests(
                         scenario.id().clone(),
                         scenario.test_sequence(),
@@ -1197,10 +2180,7 @@ impl UnitManager {
             None => {}
             Some(ref j) => {
                 let jig = j.borrow();
-                for (interface_id, _) in self.interfaces.borrow().iter() {
-                    let messages = vec![ManagerStatusMessage::Jig(Some(jig.id().clone()))];
-                    self.send_messages_to(interface_id, messages);
-                }
+                self.broadcast_messages(vec![ManagerStatusMessage::Jig(Some(jig.id().clone()))]);
             }
         }
     }
@@ -1208,9 +2188,7 @@ impl UnitManager {
     fn broadcast_scenario_list(&self) {
         let msg =
             ManagerStatusMessage::Scenarios(self.scenarios.borrow().keys().cloned().collect());
-        for (interface_id, _) in self.interfaces.borrow().iter() {
-            self.send_messages_to(interface_id, vec![msg.clone()]);
-        }
+        self.broadcast_message(msg);
     }
 
     fn broadcast_selected_scenario(&self) {
@@ -1219,16 +2197,10 @@ impl UnitManager {
             None => {}
             Some(ref j) => {
                 let scenario = j.borrow();
-                for (interface_id, _) in self.interfaces.borrow().iter() {
-                    let messages = vec![
-                        ManagerStatusMessage::Scenario(Some(scenario.id().clone())),
-                        ManagerStatusMessage::Tests(
-                            scenario.id().clone(),
-                            scenario.test_sequence(),
-                        ),
-                    ];
-                    self.send_messages_to(interface_id, messages);
-                }
+                self.broadcast_messages(vec![
+                    ManagerStatusMessage::Scenario(Some(scenario.id().clone())),
+                    ManagerStatusMessage::Tests(scenario.id().clone(), scenario.test_sequence()),
+                ]);
             }
         }
     }
@@ -1239,22 +2211,15 @@ impl UnitManager {
             Some(s) => s.clone(),
             None => return,
         };
-        for (interface_id, _) in self.interfaces.borrow().iter() {
-            let jig = jig.borrow();
-            let messages = vec![
-                ManagerStatusMessage::Describe(
-                    jig.id().clone(),
-                    FieldType::Name,
-                    jig.name().clone(),
-                ),
-                ManagerStatusMessage::Describe(
-                    jig.id().clone(),
-                    FieldType::Description,
-                    jig.description().clone(),
-                ),
-            ];
-            self.send_messages_to(interface_id, messages);
-        }
+        let jig = jig.borrow();
+        self.broadcast_messages(vec![
+            ManagerStatusMessage::Describe(jig.id().clone(), FieldType::Name, jig.name().clone()),
+            ManagerStatusMessage::Describe(
+                jig.id().clone(),
+                FieldType::Description,
+                jig.description().clone(),
+            ),
+        ]);
     }
 
     fn broadcast_scenario_named(&self, scenario_id: &UnitName) {
@@ -1264,11 +2229,11 @@ impl UnitManager {
             None => return,
         };
 
+        // Rebroadcast the list of scenarios, since that may have changed.
         self.broadcast_scenario_list();
         let messages = {
             let scenario = scenario.borrow();
             vec![
-                // Rebroadcast the list of scenarios, since that may have changed.
                 ManagerStatusMessage::Describe(
                     scenario_id.clone(),
                     FieldType::Name,
@@ -1281,9 +2246,7 @@ impl UnitManager {
                 ),
             ]
         };
-        for (interface_id, _) in self.interfaces.borrow().iter() {
-            self.send_messages_to(interface_id, messages.clone());
-        }
+        self.broadcast_messages(messages);
     }
 
     fn broadcast_test_named(&self, unit_id: &UnitName) {
@@ -1292,64 +2255,153 @@ impl UnitManager {
             Some(s) => s.clone(),
             None => return,
         };
-        for (interface_id, _) in self.interfaces.borrow().iter() {
-            let unit = unit.borrow();
-            let messages = vec![
-                ManagerStatusMessage::Describe(
-                    unit_id.clone(),
-                    FieldType::Name,
-                    unit.name().clone(),
-                ),
-                ManagerStatusMessage::Describe(
-                    unit_id.clone(),
-                    FieldType::Description,
-                    unit.description().clone(),
-                ),
-            ];
-            self.send_messages_to(interface_id, messages);
-        }
+        let unit = unit.borrow();
+        self.broadcast_messages(vec![
+            ManagerStatusMessage::Describe(unit_id.clone(), FieldType::Name, unit.name().clone()),
+            ManagerStatusMessage::Describe(
+                unit_id.clone(),
+                FieldType::Description,
+                unit.description().clone(),
+            ),
+        ]);
     }
 
     fn broadcast_skipped(&self, unit_id: &UnitName, reason: &str) {
-        let msg = ManagerStatusMessage::Skipped(unit_id.clone(), reason.to_owned());
-        for (interface_id, _) in self.interfaces.borrow().iter() {
-            self.send_messages_to(interface_id, vec![msg.clone()]);
-        }
+        self.broadcast_message(ManagerStatusMessage::Skipped(
+            unit_id.clone(),
+            reason.to_owned(),
+        ));
     }
 
-    fn broadcast_finished(&self, unit_id: &UnitName, code: u32, message: &str) {
-        let msg = ManagerStatusMessage::Finished(unit_id.clone(), code, message.to_owned());
-        for (interface_id, _) in self.interfaces.borrow().iter() {
-            self.send_messages_to(interface_id, vec![msg.clone()]);
-        }
+    fn broadcast_finished(
+        &self,
+        unit_id: &UnitName,
+        code: u32,
+        message: &str,
+        run_directory: Option<PathBuf>,
+    ) {
+        self.broadcast_message(ManagerStatusMessage::Finished(
+            unit_id.clone(),
+            code,
+            message.to_owned(),
+            run_directory,
+        ));
     }
 
     fn broadcast_message(&self, msg: ManagerStatusMessage) {
+        self.broadcast_messages(vec![msg]);
+    }
+
+    /// Send messages to every interface, and also post them to the
+    /// broadcaster for anything listening in-process.
+    fn broadcast_messages(&self, messages: Vec<ManagerStatusMessage>) {
         for (interface_id, _) in self.interfaces.borrow().iter() {
-            self.send_messages_to(interface_id, vec![msg.clone()]);
+            self.send_messages_to(interface_id, messages.clone());
+        }
+
+        // Loggers only get the result events they asked for.
+        let mut logger_messages = vec![];
+        for (logger_id, logger) in self.loggers.borrow().iter() {
+            let wanted: Vec<ManagerStatusMessage> = messages
+                .iter()
+                .filter(|msg| logger.borrow().wants(msg))
+                .cloned()
+                .collect();
+            if !wanted.is_empty() {
+                logger_messages.push((logger_id.clone(), wanted));
+            }
+        }
+        for (logger_id, wanted) in logger_messages {
+            self.send_messages_to(&logger_id, wanted);
+        }
+
+        for msg in messages {
+            self.bc.broadcast(&UnitEvent::ManagerStatus(msg));
+        }
+    }
+
+    /// Send replies to a request.  Requests from one client of an interface
+    /// that accepts connections are answered to that client alone.
+    fn send_replies_to(
+        &self,
+        sender_name: &UnitName,
+        client: Option<u32>,
+        messages: Vec<ManagerStatusMessage>,
+    ) {
+        let client = match client {
+            Some(client) => client,
+            None => return self.send_messages_to(sender_name, messages),
+        };
+        if let Some(interface) = self.interfaces.borrow().get(sender_name) {
+            for msg in messages {
+                if interface.borrow().output_message_to(client, msg).is_err() {
+                    break;
+                }
+            }
         }
     }
 
     /// Send a Vec<ManagerStatusMessage> to a specific endpoint.
     pub fn send_messages_to(&self, sender_name: &UnitName, messages: Vec<ManagerStatusMessage>) {
         let mut deactivate_reason = None;
-        if *sender_name.kind() == UnitKind::Interface {
-            let interface_table = self.interfaces.borrow();
-            let interface = interface_table
-                .get(sender_name)
-                .expect("Unable to find Interface in the library");
-            for msg in messages {
-                if let Err(e) = interface.borrow().output_message(msg) {
-                    deactivate_reason = Some(e);
-                    break;
+        match *sender_name.kind() {
+            UnitKind::Interface => {
+                let interface_table = self.interfaces.borrow();
+                let interface = interface_table
+                    .get(sender_name)
+                    .expect("Unable to find Interface in the library");
+                for msg in messages {
+                    if let Err(e) = interface.borrow().output_message(msg) {
+                        deactivate_reason = Some(e);
+                        break;
+                    }
+                }
+            }
+            UnitKind::Logger => {
+                let logger_table = self.loggers.borrow();
+                let logger = logger_table
+                    .get(sender_name)
+                    .expect("Unable to find Logger in the library");
+                for msg in messages {
+                    if let Err(e) = logger.borrow().output_message(msg) {
+                        deactivate_reason = Some(e);
+                        break;
+                    }
+                }
+            }
+            UnitKind::Trigger => {
+                let trigger_table = self.triggers.borrow();
+                let trigger = trigger_table
+                    .get(sender_name)
+                    .expect("Unable to find Trigger in the library");
+                for msg in messages {
+                    if let Err(e) = trigger.borrow().output_message(msg) {
+                        deactivate_reason = Some(e);
+                        break;
+                    }
+                }
+            }
+            // Internal units live on the broadcaster, so send replies there.
+            UnitKind::Internal => {
+                for msg in messages {
+                    self.bc.broadcast(&UnitEvent::ManagerStatus(msg));
                 }
             }
+            _ => (),
         }
         if let Some(deactivate_reason) = deactivate_reason {
-            self.deactivate(
+            self.communication_failed(
                 sender_name,
-                format!("communication error: {}", deactivate_reason).as_str(),
+                format!("communication error: {}", deactivate_reason),
             );
         }
     }
+
+    /// A unit couldn't be written to, which generally means its program has
+    /// gone away.  Treat it as having failed, so it gets restarted.
+    fn communication_failed(&self, id: &UnitName, reason: String) {
+        if self.active.borrow().contains_key(id) {
+            self.reap_unit(id, reason, true);
+        }
+    }
 }
@@ -1,25 +1,26 @@
 extern crate runny;
+extern crate serde_json;
 extern crate systemd_parser;
 
 use std::cell::RefCell;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::io::{BufRead, BufReader, Error, Read, Write};
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::Sender;
 use std::thread;
 use std::time::Duration;
 
-use config::Config;
+use config::{command_with_environment, Config};
 use unit::{
-    UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitDeselectError,
-    UnitIncompatibleReason, UnitName, UnitSelectError,
+    RestartPolicy, UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitDeselectError,
+    UnitEnvironment, UnitIncompatibleReason, UnitName, UnitSelectError,
 };
 use unitmanager::{
     ManagerControlMessage, ManagerControlMessageContents, ManagerStatusMessage, UnitManager,
 };
+use units::listener::{ListenAddress, Listener};
 
 use self::runny::running::{Running, RunningOutput};
-use self::runny::Runny;
 use self::systemd_parser::items::DirectiveEntry;
 
 #[derive(Clone, Copy)]
@@ -28,6 +29,47 @@ enum InterfaceFormat {
     Json,
 }
 
+/// Requests that may be sent by a JSON-formatted interface.  Each request
+/// is a single-line object whose "type" field selects the verb.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum JsonRequest {
+    Scenarios,
+    Scenario {
+        scenario: String,
+    },
+    Tests {
+        scenario: Option<String>,
+    },
+    Jig,
+    Status,
+    Log {
+        message: String,
+    },
+    Start {
+        scenario: Option<String>,
+    },
+    Resume {
+        serial: Option<String>,
+    },
+    Abort,
+    Shutdown {
+        reason: Option<String>,
+    },
+    Pong {
+        id: u32,
+    },
+    Run {
+        test: String,
+        #[serde(default)]
+        no_deps: bool,
+    },
+    Answer {
+        id: u32,
+        answer: String,
+    },
+}
+
 /// A struct defining an in-memory representation of a .Interface file
 #[derive(Clone)]
 pub struct InterfaceDescription {
@@ -54,6 +96,15 @@ pub struct InterfaceDescription {
 
     /// The path of the unit file
     unit_directory: PathBuf,
+
+    /// If set, accept clients on this socket instead of running ExecStart
+    listen_stream: Option<ListenAddress>,
+
+    /// When and how often to restart the interface if it exits
+    restart: RestartPolicy,
+
+    /// Variables to add to the program's environment
+    environment: UnitEnvironment,
 }
 
 impl InterfaceDescription {
@@ -78,6 +129,9 @@ impl InterfaceDescription {
             exec_start: "".to_owned(),
             working_directory: None,
             unit_directory: path.parent().unwrap().to_owned(),
+            listen_stream: None,
+            restart: RestartPolicy::default(),
+            environment: UnitEnvironment::default(),
         };
 
         for entry in unit_file.lookup_by_category("Interface") {
@@ -114,6 +168,17 @@ impl InterfaceDescription {
                             }
                         }
                     }
+                    "ListenStream" => {
+                        interface_description.listen_stream = match directive.value() {
+                            Some(s) => Some(ListenAddress::from_str(s)),
+                            None => {
+                                return Err(UnitDescriptionError::MissingValue(
+                                    "Interface".to_owned(),
+                                    "ListenStream".to_owned(),
+                                ))
+                            }
+                        }
+                    }
                     "Format" => {
                         interface_description.format = match directive.value() {
                             None => InterfaceFormat::Text,
@@ -131,7 +196,27 @@ impl InterfaceDescription {
                             },
                         }
                     }
-                    &_ => (),
+                    key => {
+                        if !interface_description.environment.parse_directive(
+                            "Interface",
+                            key,
+                            directive.value(),
+                        )? {
+                            interface_description.restart.parse_directive(
+                                "Interface",
+                                key,
+                                directive.value(),
+                            )?;
+                        }
+                    }
+                }
+            } else if let DirectiveEntry::Many(ref directives) = entry {
+                for directive in directives {
+                    interface_description.environment.parse_directive(
+                        "Interface",
+                        directive.key(),
+                        directive.value(),
+                    )?;
                 }
             }
         }
@@ -178,6 +263,7 @@ impl InterfaceDescription {
 pub struct Interface {
     desc: InterfaceDescription,
     process: RefCell<Option<Running>>,
+    listener: RefCell<Option<Listener>>,
     terminate_timeout: Duration,
 }
 
@@ -186,6 +272,7 @@ impl Interface {
         Interface {
             desc: desc.clone(),
             process: RefCell::new(None),
+            listener: RefCell::new(None),
             terminate_timeout: *config.terminate_timeout(),
         }
     }
@@ -194,6 +281,10 @@ impl Interface {
         &self.desc.id
     }
 
+    pub fn restart_policy(&self) -> &RestartPolicy {
+        &self.desc.restart
+    }
+
     pub fn select(&self) -> Result<(), UnitSelectError> {
         Ok(())
     }
@@ -207,28 +298,54 @@ impl Interface {
         manager: &UnitManager,
         config: &Config,
     ) -> Result<(), UnitActivateError> {
-        let mut running = Runny::new(&self.desc.exec_start)
-            .directory(&Some(config.working_directory(
-                &self.desc.unit_directory,
-                &self.desc.working_directory,
-            )))
-            .start()?;
+        if let Some(ref address) = self.desc.listen_stream {
+            return self.activate_listener(manager, address);
+        }
+
+        let vars = self
+            .desc
+            .environment
+            .resolve(&self.desc.unit_directory)
+            .map_err(UnitActivateError::EnvironmentFailed)?;
+        let mut running = command_with_environment(
+            &self.desc.exec_start,
+            &config.environment(self.id(), &self.desc.unit_directory, &vars),
+        )
+        .directory(&Some(config.working_directory(
+            &self.desc.unit_directory,
+            &self.desc.working_directory,
+        )))
+        .start()?;
 
         let stdout = running.take_output();
         let stderr = running.take_error();
 
         let control_sender = manager.get_control_channel();
         let control_sender_id = self.id().clone();
-        if let InterfaceFormat::Text = self.desc.format {
-            // Pass control to an out-of-object thread, and shuttle communications
-            // from stdout onto the control_sender channel.
-            let thr_sender_id = control_sender_id.clone();
-            let thr_sender = control_sender.clone();
-            thread::spawn(move || Self::text_read(thr_sender_id, thr_sender, stdout));
-            let thr_sender_id = control_sender_id.clone();
-            let thr_sender = control_sender.clone();
-            thread::spawn(move || Self::text_read_stderr(thr_sender_id, thr_sender, stderr));
-        };
+        // Pass control to an out-of-object thread, and shuttle communications
+        // from stdout onto the control_sender channel.
+        let thr_sender_id = control_sender_id.clone();
+        let thr_sender = control_sender.clone();
+        let format = self.desc.format;
+        thread::spawn(move || {
+            match format {
+                InterfaceFormat::Text => {
+                    Self::text_read(thr_sender_id.clone(), None, thr_sender.clone(), stdout)
+                }
+                InterfaceFormat::Json => {
+                    Self::json_read(thr_sender_id.clone(), None, thr_sender.clone(), stdout)
+                }
+            }
+            thr_sender
+                .send(ManagerControlMessage::new(
+                    &thr_sender_id,
+                    ManagerControlMessageContents::ChildExited,
+                ))
+                .expect("interface couldn't send exit message to controller");
+        });
+        let thr_sender_id = control_sender_id.clone();
+        let thr_sender = control_sender.clone();
+        thread::spawn(move || Self::text_read_stderr(thr_sender_id, thr_sender, stderr));
 
         *self.process.borrow_mut() = Some(running);
 
@@ -243,7 +360,49 @@ impl Interface {
         Ok(())
     }
 
+    /// Accept clients on a socket rather than running a program.  Each client
+    /// speaks the same protocol a program would on its stdin and stdout.
+    fn activate_listener(
+        &self,
+        manager: &UnitManager,
+        address: &ListenAddress,
+    ) -> Result<(), UnitActivateError> {
+        let control_sender = manager.get_control_channel();
+        let control_sender_id = self.id().clone();
+        let format = self.desc.format;
+
+        let listener = Listener::start(address, move |client, connection| {
+            // Send some initial configuration to the new client, and only to it.
+            control_sender
+                .send(ManagerControlMessage::from_client(
+                    &control_sender_id,
+                    Some(client),
+                    ManagerControlMessageContents::InitialGreeting,
+                ))
+                .ok();
+
+            let thr_sender_id = control_sender_id.clone();
+            let thr_sender = control_sender.clone();
+            match format {
+                InterfaceFormat::Text => {
+                    Self::text_read(thr_sender_id, Some(client), thr_sender, connection)
+                }
+                InterfaceFormat::Json => {
+                    Self::json_read(thr_sender_id, Some(client), thr_sender, connection)
+                }
+            }
+        })
+        .map_err(|e| UnitActivateError::ListenFailed(address.to_string(), e))?;
+
+        *self.listener.borrow_mut() = Some(listener);
+        Ok(())
+    }
+
     pub fn deactivate(&self) -> Result<(), UnitDeactivateError> {
+        if let Some(listener) = self.listener.borrow_mut().take() {
+            listener.stop();
+        }
+
         if let Some(process) = self.process.borrow_mut().take() {
             match process.terminate(Some(self.terminate_timeout)) {
                 Ok(retval) => match retval {
@@ -257,16 +416,59 @@ impl Interface {
         }
     }
 
+    /// Encode a message the way this interface's clients expect it.
+    fn encode(&self, msg: ManagerStatusMessage) -> Result<Vec<u8>, Error> {
+        let mut buf = vec![];
+        match self.desc.format {
+            InterfaceFormat::Text => Self::text_write(&mut buf, msg)?,
+            InterfaceFormat::Json => Self::json_write(&mut buf, msg)?,
+        }
+        Ok(buf)
+    }
+
+    /// Write a message out to one client of an interface that accepts
+    /// connections, such as a reply to that client's request.
+    pub fn output_message_to(&self, client: u32, msg: ManagerStatusMessage) -> Result<(), Error> {
+        match *self.listener.borrow() {
+            Some(ref listener) => {
+                listener.write_to(client, &self.encode(msg)?);
+                Ok(())
+            }
+            None => self.output_message(msg),
+        }
+    }
+
     /// Cause a MessageControlContents to be written out.
     pub fn output_message(&self, msg: ManagerStatusMessage) -> Result<(), Error> {
+        // Encode the message once and send it to every connected client.
+        if let Some(ref listener) = *self.listener.borrow() {
+            listener.write_all(&self.encode(msg)?);
+            return Ok(());
+        }
+
+        let mut process_opt = self.process.borrow_mut();
+
+        if process_opt.is_none() {
+            return Err(Error::other("no process running"));
+        }
+
+        let process = process_opt.as_mut().unwrap();
+
         match self.desc.format {
-            InterfaceFormat::Text => self.text_write(msg),
-            InterfaceFormat::Json => self.json_write(msg),
+            InterfaceFormat::Text => Self::text_write(process, msg),
+            InterfaceFormat::Json => Self::json_write(process, msg),
         }
     }
 
-    fn json_write(&self, _: ManagerStatusMessage) -> Result<(), Error> {
-        unimplemented!();
+    /// Interfaces that accept connections are part of exclave itself, and
+    /// don't need to answer PINGs.
+    pub fn is_listener(&self) -> bool {
+        self.desc.listen_stream.is_some()
+    }
+
+    /// Write a ManagerStatusMessage as a single line of JSON.
+    fn json_write<W: Write>(process: &mut W, msg: ManagerStatusMessage) -> Result<(), Error> {
+        writeln!(process, "{}", msg.to_json())
     }
 
     fn cfti_escape(msg: &str) -> String {
@@ -276,16 +478,24 @@ impl Interface {
             .replace("\r", "\\r")
     }
 
-    /// Write a UnitInterfaceMessage to a Text-formatted output.
-    fn text_write(&self, msg: ManagerStatusMessage) -> Result<(), Error> {
-        let mut process_opt = self.process.borrow_mut();
-
-        if process_opt.is_none() {
-            return Err(Error::new(ErrorKind::Other, "no process running"));
+    /// Durations are sent as a number of milliseconds, or "-" if unknown.
+    fn duration_ms(duration: Option<Duration>) -> String {
+        match duration {
+            Some(d) => d.as_millis().to_string(),
+            None => "-".to_owned(),
         }
+    }
 
-        let process = process_opt.as_mut().unwrap();
+    /// Measurement limits are sent as a number, or "-" if there is none.
+    fn limit(limit: Option<f64>) -> String {
+        match limit {
+            Some(l) => l.to_string(),
+            None => "-".to_owned(),
+        }
+    }
 
+    /// Write a UnitInterfaceMessage to a Text-formatted output.
+    fn text_write<W: Write>(process: &mut W, msg: ManagerStatusMessage) -> Result<(), Error> {
         match msg {
             ManagerStatusMessage::Jig(j) => match j {
                 Some(jig_name) => writeln!(
@@ -334,19 +544,66 @@ impl Interface {
             ManagerStatusMessage::Running(test) => {
                 writeln!(process, "RUNNING {}", Self::cfti_escape(test.id()))
             }
-            ManagerStatusMessage::Skipped(test, reason) => writeln!(
+            ManagerStatusMessage::Retry(test, attempt, _code, reason) => writeln!(
                 process,
-                "SKIP {} {}",
+                "RETRY {} {} {}",
                 Self::cfti_escape(test.id()),
+                attempt,
                 Self::cfti_escape(&reason)
             ),
-            ManagerStatusMessage::Finished(scenario, result, reason) => writeln!(
+            ManagerStatusMessage::Measurement(test, m) => writeln!(
                 process,
-                "FINISH {} {} {}",
+                "MEASUREMENT {} {} {} {} {} {} {}",
+                Self::cfti_escape(test.id()),
+                Self::cfti_escape(&m.name),
+                m.value,
+                if m.passed { "pass" } else { "fail" },
+                Self::cfti_escape(m.units.as_deref().unwrap_or("-")),
+                Self::limit(m.low),
+                Self::limit(m.high)
+            ),
+            ManagerStatusMessage::Set(test, key, value) => writeln!(
+                process,
+                "SET {} {} {}",
+                Self::cfti_escape(test.id()),
+                Self::cfti_escape(&key),
+                Self::cfti_escape(&value)
+            ),
+            ManagerStatusMessage::Iteration(scenario, i) => writeln!(
+                process,
+                "ITERATION {} {} {} {} {} {}",
                 Self::cfti_escape(scenario.id()),
-                result,
+                i.number,
+                if i.passed { "pass" } else { "fail" },
+                i.total_passed,
+                i.total_failed,
+                if i.last { "done" } else { "repeating" }
+            ),
+            ManagerStatusMessage::Skipped(test, reason) => writeln!(
+                process,
+                "SKIP {} {}",
+                Self::cfti_escape(test.id()),
                 Self::cfti_escape(&reason)
             ),
+            ManagerStatusMessage::Finished(scenario, result, reason, run_directory) => {
+                match run_directory {
+                    Some(path) => writeln!(
+                        process,
+                        "FINISH {} {} {} {}",
+                        Self::cfti_escape(scenario.id()),
+                        result,
+                        Self::cfti_escape(&reason),
+                        Self::cfti_escape(&path.to_string_lossy())
+                    ),
+                    None => writeln!(
+                        process,
+                        "FINISH {} {} {}",
+                        Self::cfti_escape(scenario.id()),
+                        result,
+                        Self::cfti_escape(&reason)
+                    ),
+                }
+            }
             ManagerStatusMessage::Fail(test, _code, reason) => writeln!(
                 process,
                 "FAIL {} {}",
@@ -359,17 +616,41 @@ impl Interface {
                 Self::cfti_escape(test.id()),
                 Self::cfti_escape(&reason)
             ),
-            ManagerStatusMessage::Start(scenario) => {
+            ManagerStatusMessage::Start(scenario, _) => {
                 writeln!(process, "START {}", Self::cfti_escape(scenario.id()))
-            } /*
-              //            BroadcastMessageContents::Hello(name) => writeln!(stdin,
-              //                                                "HELLO {}", name),
-              //            BroadcastMessageContents::Ping(val) => writeln!(stdin,
-              //                                                "PING {}", val),
-              BroadcastMessageContents::Shutdown(reason) => writeln!(stdin, "EXIT {}", reason),
-
-              BroadcastMessageContents::Start(scenario) => writeln!(stdin, "START {}", scenario),
-              */
+            }
+            ManagerStatusMessage::Resumed(scenario, tests) => {
+                write!(process, "RESUMED {}", Self::cfti_escape(scenario.id()))?;
+                for test in &tests {
+                    write!(process, " {}", Self::cfti_escape(test.id()))?;
+                }
+                writeln!(process)
+            }
+            ManagerStatusMessage::Ping(id) => writeln!(process, "PING {}", id),
+            ManagerStatusMessage::ScenarioStatus(scenario, state, elapsed, timeout) => writeln!(
+                process,
+                "STATUS {} {} {} {}",
+                Self::cfti_escape(scenario.id()),
+                state,
+                Self::duration_ms(elapsed),
+                Self::duration_ms(timeout)
+            ),
+            ManagerStatusMessage::TestStatus(test, state, elapsed, timeout, reason) => writeln!(
+                process,
+                "TESTSTATUS {} {} {} {} {}",
+                Self::cfti_escape(test.id()),
+                state,
+                Self::duration_ms(elapsed),
+                Self::duration_ms(timeout),
+                Self::cfti_escape(&reason)
+            ),
+            ManagerStatusMessage::Ask(id, test, question) => writeln!(
+                process,
+                "ASK {} {} {}",
+                id,
+                Self::cfti_escape(test.id()),
+                Self::cfti_escape(&question)
+            ),
         }
     }
 
@@ -428,9 +709,17 @@ impl Interface {
         }
     }
 
-    fn text_read(id: UnitName, control: Sender<ManagerControlMessage>, stdout: RunningOutput) {
-        for line in BufReader::new(stdout).lines() {
-            let line = line.expect("Unable to get next line");
+    fn text_read<R: Read>(
+        id: UnitName,
+        client: Option<u32>,
+        control: Sender<ManagerControlMessage>,
+        input: R,
+    ) {
+        for line in BufReader::new(input).lines() {
+            let line = match line {
+                Ok(l) => l,
+                Err(_) => break,
+            };
             let mut words: Vec<String> = line
                 .split_whitespace()
                 .map(|x| Self::cfti_unescape(x.to_owned()))
@@ -448,7 +737,7 @@ impl Interface {
                 "scenarios" => ManagerControlMessageContents::Scenarios,
                 "scenario" => match UnitName::from_str(
                     words
-                        .get(0)
+                        .first()
                         .unwrap_or(&"".to_owned())
                         .to_lowercase()
                         .as_str(),
@@ -476,6 +765,7 @@ impl Interface {
                     }
                 }
                 "jig" => ManagerControlMessageContents::Jig,
+                "status" => ManagerControlMessageContents::Status,
                 "log" => ManagerControlMessageContents::Log(words.join(" ")),
                 "start" => {
                     if words.is_empty() {
@@ -483,7 +773,7 @@ impl Interface {
                     } else {
                         match UnitName::from_str(
                             words
-                                .get(0)
+                                .first()
                                 .unwrap_or(&"".to_owned())
                                 .to_lowercase()
                                 .as_str(),
@@ -504,27 +794,132 @@ impl Interface {
                         ManagerControlMessageContents::Shutdown(Some(words.join(" ")))
                     }
                 }
+                "resume" => ManagerControlMessageContents::ResumeScenario(words.first().cloned()),
                 "abort" => ManagerControlMessageContents::AbortTests,
-                /*
-                "pong" => ControlMessageContents::Pong(words[0].to_lowercase()),
-                "hello" => ControlMessageContents::Hello(words.join(" ")),
-                */
+                "run" => {
+                    let with_deps = !words.iter().any(|w| w.to_lowercase() == "--no-deps");
+                    match words.iter().find(|w| !w.starts_with("--")) {
+                        None => ManagerControlMessageContents::Error(
+                            "No test name specified".to_owned(),
+                        ),
+                        Some(name) => match UnitName::from_str(&name.to_lowercase(), "test") {
+                            Ok(test_name) => {
+                                ManagerControlMessageContents::RunTest(test_name, with_deps)
+                            }
+                            Err(e) => ManagerControlMessageContents::Error(format!(
+                                "Invalid test name specified: {}",
+                                e
+                            )),
+                        },
+                    }
+                }
+                "pong" => ManagerControlMessageContents::Pong(words.join(" ")),
+                "answer" => {
+                    if words.is_empty() {
+                        ManagerControlMessageContents::Error("No prompt ID specified".to_owned())
+                    } else {
+                        let prompt_id = words.remove(0);
+                        ManagerControlMessageContents::Answer(prompt_id, words.join(" "))
+                    }
+                }
                 v => ManagerControlMessageContents::Unimplemented(v.to_owned(), words.join(" ")),
             };
 
             // If the send fails, that means the other end has closed the pipe.
             if control
-                .send(ManagerControlMessage::new(&id, response))
+                .send(ManagerControlMessage::from_client(&id, client, response))
+                .is_err()
+            {
+                break;
+            }
+        }
+    }
+
+    /// Convert a decoded JSON request into a message for the manager.
+    fn json_request(request: JsonRequest) -> ManagerControlMessageContents {
+        match request {
+            JsonRequest::Scenarios => ManagerControlMessageContents::Scenarios,
+            JsonRequest::Scenario { scenario } => {
+                match UnitName::from_str(&scenario.to_lowercase(), "scenario") {
+                    Err(e) => ManagerControlMessageContents::Error(format!(
+                        "Invalid scenario name: {}",
+                        e
+                    )),
+                    Ok(o) => ManagerControlMessageContents::Scenario(o),
+                }
+            }
+            JsonRequest::Tests { scenario: None } => ManagerControlMessageContents::Tests(None),
+            JsonRequest::Tests {
+                scenario: Some(scenario),
+            } => match UnitName::from_str(&scenario.to_lowercase(), "scenario") {
+                Err(e) => {
+                    ManagerControlMessageContents::Error(format!("Invalid scenario name: {}", e))
+                }
+                Ok(o) => ManagerControlMessageContents::Tests(Some(o)),
+            },
+            JsonRequest::Jig => ManagerControlMessageContents::Jig,
+            JsonRequest::Status => ManagerControlMessageContents::Status,
+            JsonRequest::Log { message } => ManagerControlMessageContents::Log(message),
+            JsonRequest::Start { scenario: None } => {
+                ManagerControlMessageContents::StartScenario(None)
+            }
+            JsonRequest::Start {
+                scenario: Some(scenario),
+            } => match UnitName::from_str(&scenario.to_lowercase(), "scenario") {
+                Err(e) => {
+                    ManagerControlMessageContents::Error(format!("Invalid scenario name: {}", e))
+                }
+                Ok(o) => ManagerControlMessageContents::StartScenario(Some(o)),
+            },
+            JsonRequest::Resume { serial } => ManagerControlMessageContents::ResumeScenario(serial),
+            JsonRequest::Abort => ManagerControlMessageContents::AbortTests,
+            JsonRequest::Shutdown { reason } => ManagerControlMessageContents::Shutdown(reason),
+            JsonRequest::Pong { id } => ManagerControlMessageContents::Pong(id.to_string()),
+            JsonRequest::Answer { id, answer } => {
+                ManagerControlMessageContents::Answer(id.to_string(), answer)
+            }
+            JsonRequest::Run { test, no_deps } => {
+                match UnitName::from_str(&test.to_lowercase(), "test") {
+                    Err(e) => {
+                        ManagerControlMessageContents::Error(format!("Invalid test name: {}", e))
+                    }
+                    Ok(o) => ManagerControlMessageContents::RunTest(o, !no_deps),
+                }
+            }
+        }
+    }
+
+    fn json_read<R: Read>(
+        id: UnitName,
+        client: Option<u32>,
+        control: Sender<ManagerControlMessage>,
+        input: R,
+    ) {
+        for line in BufReader::new(input).lines() {
+            let line = match line {
+                Ok(l) => l,
+                Err(_) => break,
+            };
+
+            // Don't crash if we get a blank line.
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let response = match serde_json::from_str::<JsonRequest>(&line) {
+                Ok(request) => Self::json_request(request),
+                Err(e) => {
+                    ManagerControlMessageContents::Error(format!("Invalid JSON request: {}", e))
+                }
+            };
+
+            // If the send fails, that means the other end has closed the pipe.
+            if control
+                .send(ManagerControlMessage::from_client(&id, client, response))
                 .is_err()
             {
                 break;
             }
         }
-        control
-            .send(ManagerControlMessage::new(
-                &id,
-                ManagerControlMessageContents::ChildExited,
-            ))
-            .expect("interface couldn't send exit message to controller");
     }
 }
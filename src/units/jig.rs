@@ -5,14 +5,13 @@ use std::fs::File;
 use std::io::Read;
 use std::path::{Path, PathBuf};
 
-use config::Config;
+use config::{command_with_environment, Config};
 use unit::{
     UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitDeselectError,
-    UnitIncompatibleReason, UnitName, UnitSelectError,
+    UnitEnvironment, UnitIncompatibleReason, UnitName, UnitSelectError,
 };
 use unitmanager::UnitManager;
 
-use self::runny::Runny;
 use self::systemd_parser::items::DirectiveEntry;
 
 /// A struct defining an in-memory representation of a .jig file
@@ -41,6 +40,9 @@ pub struct JigDescription {
 
     /// A file whose existence indicates this jig is compatible
     test_file: Option<String>,
+
+    /// Variables to add to the environment of every program run on this jig.
+    environment: UnitEnvironment,
 }
 
 impl JigDescription {
@@ -73,6 +75,7 @@ impl JigDescription {
             unit_directory: path.parent().unwrap().to_owned(),
             test_program: None,
             test_file: None,
+            environment: UnitEnvironment::default(),
         };
 
         for entry in unit_file.lookup_by_category("Jig") {
@@ -99,7 +102,21 @@ impl JigDescription {
                     "TestProgram" => {
                         jig_description.test_program = directive.value().map(|s| s.to_owned())
                     }
-                    &_ => (),
+                    key => {
+                        jig_description.environment.parse_directive(
+                            "Jig",
+                            key,
+                            directive.value(),
+                        )?;
+                    }
+                }
+            } else if let DirectiveEntry::Many(ref directives) = entry {
+                for directive in directives {
+                    jig_description.environment.parse_directive(
+                        "Jig",
+                        directive.key(),
+                        directive.value(),
+                    )?;
                 }
             }
         }
@@ -126,13 +143,25 @@ impl JigDescription {
         if let Some(ref cmd_str) = self.test_program {
             use std::io::{BufRead, BufReader};
 
-            let running = Runny::new(cmd_str)
-                .directory(&Some(
-                    config.working_directory(&self.unit_directory, &self.working_directory),
-                ))
-                .timeout(*config.timeout())
-                .path(config.paths().clone())
-                .start()?;
+            let vars = self
+                .environment
+                .resolve(&self.unit_directory)
+                .map_err(|e| {
+                    UnitIncompatibleReason::TestProgramFailed(format!(
+                        "{} (unable to read environment: {})",
+                        cmd_str, e
+                    ))
+                })?;
+            let running = command_with_environment(
+                cmd_str,
+                &config.environment(&self.id, &self.unit_directory, &vars),
+            )
+            .directory(&Some(
+                config.working_directory(&self.unit_directory, &self.working_directory),
+            ))
+            .timeout(*config.timeout())
+            .path(config.paths().clone())
+            .start()?;
 
             let mut reader = BufReader::new(running);
             let mut buf = String::new();
@@ -211,6 +240,17 @@ impl Jig {
         } else {
             config.clear_jig_working_directory();
         }
+        match self
+            .description
+            .environment
+            .resolve(&self.description.unit_directory)
+        {
+            Ok(vars) => config.set_jig_environment(&self.description.id, vars),
+            Err(e) => {
+                config.clear_jig_environment();
+                return Err(UnitActivateError::EnvironmentFailed(e));
+            }
+        }
         Ok(())
     }
 
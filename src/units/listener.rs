@@ -0,0 +1,311 @@
+// A socket listener that lets several clients attach to a single unit.
+// Every accepted connection is handed off to its own reader thread, and
+// anything written to the Listener is queued for every connected client,
+// or for just one of them.
+// Each client has its own writer thread, so a client that stops reading
+// can't hold up the others.  Clients whose queue fills up are dropped.
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How often the accept loop checks to see if it should stop.
+const ACCEPT_POLL_MS: u64 = 100;
+
+/// How many messages may be waiting for a client before it is dropped.
+const CLIENT_QUEUE_DEPTH: usize = 256;
+
+#[derive(Clone, Debug)]
+pub enum ListenAddress {
+    /// A "host:port" pair.
+    Tcp(String),
+
+    /// A path to a Unix domain socket.
+    Unix(PathBuf),
+}
+
+impl ListenAddress {
+    /// Anything containing a path separator is treated as a Unix socket,
+    /// and everything else is treated as a TCP address.
+    pub fn from_str(s: &str) -> ListenAddress {
+        if s.contains('/') {
+            ListenAddress::Unix(PathBuf::from(s))
+        } else {
+            ListenAddress::Tcp(s.to_owned())
+        }
+    }
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ListenAddress::Tcp(ref addr) => write!(f, "{}", addr),
+            ListenAddress::Unix(ref path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// One end of an accepted connection.
+pub enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Connection {
+    fn try_clone(&self) -> io::Result<Connection> {
+        match self {
+            Connection::Tcp(s) => Ok(Connection::Tcp(s.try_clone()?)),
+            #[cfg(unix)]
+            Connection::Unix(s) => Ok(Connection::Unix(s.try_clone()?)),
+        }
+    }
+
+    fn shutdown(&self) {
+        match self {
+            Connection::Tcp(s) => s.shutdown(Shutdown::Both).ok(),
+            #[cfg(unix)]
+            Connection::Unix(s) => s.shutdown(Shutdown::Both).ok(),
+        };
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(s) => s.read(buf),
+            #[cfg(unix)]
+            Connection::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(s) => s.write(buf),
+            #[cfg(unix)]
+            Connection::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(s) => s.flush(),
+            #[cfg(unix)]
+            Connection::Unix(s) => s.flush(),
+        }
+    }
+}
+
+enum SocketListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl SocketListener {
+    fn bind(address: &ListenAddress) -> io::Result<SocketListener> {
+        let listener = match address {
+            ListenAddress::Tcp(ref addr) => SocketListener::Tcp(TcpListener::bind(addr.as_str())?),
+            #[cfg(unix)]
+            ListenAddress::Unix(ref path) => {
+                // Remove any socket left over from a previous run, but
+                // refuse to clobber anything that isn't a socket.
+                if let Ok(metadata) = fs::symlink_metadata(path) {
+                    if !metadata.file_type().is_socket() {
+                        return Err(io::Error::new(
+                            ErrorKind::AlreadyExists,
+                            format!("{} exists and is not a socket", path.display()),
+                        ));
+                    }
+                    fs::remove_file(path)?;
+                }
+                SocketListener::Unix(UnixListener::bind(path)?)
+            }
+            #[cfg(not(unix))]
+            ListenAddress::Unix(_) => {
+                return Err(io::Error::new(
+                    ErrorKind::Other,
+                    "unix sockets are not supported on this platform",
+                ))
+            }
+        };
+        match listener {
+            SocketListener::Tcp(ref l) => l.set_nonblocking(true)?,
+            #[cfg(unix)]
+            SocketListener::Unix(ref l) => l.set_nonblocking(true)?,
+        }
+        Ok(listener)
+    }
+
+    fn accept(&self) -> io::Result<Connection> {
+        match self {
+            SocketListener::Tcp(l) => {
+                let (stream, _) = l.accept()?;
+                stream.set_nonblocking(false)?;
+                Ok(Connection::Tcp(stream))
+            }
+            #[cfg(unix)]
+            SocketListener::Unix(l) => {
+                let (stream, _) = l.accept()?;
+                stream.set_nonblocking(false)?;
+                Ok(Connection::Unix(stream))
+            }
+        }
+    }
+}
+
+/// A connected client, along with the queue feeding its writer thread.
+struct Client {
+    connection: Connection,
+    queue: SyncSender<Vec<u8>>,
+}
+
+impl Client {
+    /// Start a writer thread that drains the queue onto the connection.
+    fn new(connection: Connection) -> io::Result<Client> {
+        let mut writer = connection.try_clone()?;
+        let (queue, pending) = sync_channel::<Vec<u8>>(CLIENT_QUEUE_DEPTH);
+        thread::spawn(move || {
+            for buf in pending {
+                if writer.write_all(&buf).is_err() {
+                    break;
+                }
+            }
+            writer.shutdown();
+        });
+        Ok(Client { connection, queue })
+    }
+}
+
+pub struct Listener {
+    address: ListenAddress,
+    connections: Arc<Mutex<HashMap<u32, Client>>>,
+    running: Arc<AtomicBool>,
+}
+
+impl Listener {
+    /// Start listening on the given address.  `handler` is called from a new
+    /// thread for each client that connects, along with an ID that can be
+    /// passed to `write_to()`.  The client is disconnected once `handler` returns.
+    pub fn start<F>(address: &ListenAddress, handler: F) -> io::Result<Listener>
+    where
+        F: Fn(u32, Connection) + Send + Sync + 'static,
+    {
+        let socket = SocketListener::bind(address)?;
+        let connections = Arc::new(Mutex::new(HashMap::new()));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thr_connections = connections.clone();
+        let thr_running = running.clone();
+        thread::spawn(move || {
+            Self::accept_loop(socket, thr_connections, thr_running, Arc::new(handler))
+        });
+
+        Ok(Listener {
+            address: address.clone(),
+            connections,
+            running,
+        })
+    }
+
+    fn accept_loop<F>(
+        socket: SocketListener,
+        connections: Arc<Mutex<HashMap<u32, Client>>>,
+        running: Arc<AtomicBool>,
+        handler: Arc<F>,
+    ) where
+        F: Fn(u32, Connection) + Send + Sync + 'static,
+    {
+        let mut next_id = 0;
+        while running.load(Ordering::SeqCst) {
+            // Errors such as running out of file descriptors may not clear up
+            // straight away, so wait before trying again.
+            let connection = match socket.accept() {
+                Ok(c) => c,
+                Err(_) => {
+                    thread::sleep(Duration::from_millis(ACCEPT_POLL_MS));
+                    continue;
+                }
+            };
+            let reader = match connection.try_clone() {
+                Ok(r) => r,
+                Err(_) => continue,
+            };
+            let client = match Client::new(connection) {
+                Ok(c) => c,
+                Err(_) => continue,
+            };
+
+            let id = next_id;
+            next_id += 1;
+            connections.lock().unwrap().insert(id, client);
+
+            let thr_connections = connections.clone();
+            let thr_handler = handler.clone();
+            thread::spawn(move || {
+                thr_handler(id, reader);
+                if let Some(c) = thr_connections.lock().unwrap().remove(&id) {
+                    c.connection.shutdown();
+                }
+            });
+        }
+    }
+
+    /// Queue a buffer for every connected client.  This never blocks:
+    /// clients that have gone away or have fallen too far behind are
+    /// disconnected.
+    pub fn write_all(&self, buf: &[u8]) {
+        let mut connections = self.connections.lock().unwrap();
+        let mut dead = vec![];
+        for (id, client) in connections.iter() {
+            if client.queue.try_send(buf.to_vec()).is_err() {
+                dead.push(*id);
+            }
+        }
+        for id in dead {
+            if let Some(c) = connections.remove(&id) {
+                c.connection.shutdown();
+            }
+        }
+    }
+
+    /// Queue a buffer for one client, such as a reply to its request.
+    /// Like `write_all()`, this disconnects the client if it has fallen
+    /// too far behind.
+    pub fn write_to(&self, id: u32, buf: &[u8]) {
+        let mut connections = self.connections.lock().unwrap();
+        let failed = match connections.get(&id) {
+            Some(client) => client.queue.try_send(buf.to_vec()).is_err(),
+            None => false,
+        };
+        if failed {
+            if let Some(c) = connections.remove(&id) {
+                c.connection.shutdown();
+            }
+        }
+    }
+
+    /// Stop accepting new clients, and disconnect existing ones.
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+        for (_, client) in self.connections.lock().unwrap().drain() {
+            client.connection.shutdown();
+        }
+        if let ListenAddress::Unix(ref path) = self.address {
+            fs::remove_file(path).ok();
+        }
+    }
+}
@@ -4,16 +4,16 @@ extern crate systemd_parser;
 
 use std::cell::RefCell;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::io::{BufRead, BufReader, Error, Read, Write};
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::Sender;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use config::Config;
+use config::{command_with_environment, Config};
 use unit::{
-    UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitDeselectError,
-    UnitIncompatibleReason, UnitName, UnitSelectError,
+    RestartPolicy, UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitDeselectError,
+    UnitEnvironment, UnitIncompatibleReason, UnitName, UnitSelectError,
 };
 use unitbroadcaster::LogEntry;
 use unitmanager::{
@@ -21,7 +21,6 @@ use unitmanager::{
 };
 
 use self::runny::running::{Running, RunningOutput};
-use self::runny::Runny;
 use self::systemd_parser::items::DirectiveEntry;
 
 #[derive(Clone, Copy)]
@@ -30,6 +29,21 @@ enum LoggerFormat {
     Json,
 }
 
+/// Result events that a logger may subscribe to with "Events=".
+const RESULT_EVENTS: [&str; 11] = [
+    "start",
+    "resume",
+    "running",
+    "pass",
+    "fail",
+    "retry",
+    "skip",
+    "finish",
+    "measurement",
+    "set",
+    "iteration",
+];
+
 /// A struct defining an in-memory representation of a .logger file
 #[derive(Clone)]
 pub struct LoggerDescription {
@@ -57,8 +71,17 @@ pub struct LoggerDescription {
     /// The path to the unit file
     unit_directory: PathBuf,
 
+    /// When and how often to restart the logger if it exits
+    restart: RestartPolicy,
+
+    /// Variables to add to the program's environment
+    environment: UnitEnvironment,
+
     /// How long to wait for a terminate() call
     terminate_timeout: Duration,
+
+    /// Result events to send in addition to log messages
+    events: Vec<String>,
 }
 
 impl LoggerDescription {
@@ -83,7 +106,10 @@ impl LoggerDescription {
             exec_start: "".to_owned(),
             working_directory: None,
             unit_directory: path.parent().unwrap().to_owned(),
+            restart: RestartPolicy::default(),
+            environment: UnitEnvironment::default(),
             terminate_timeout: Duration::from_secs(5),
+            events: vec![],
         };
 
         for entry in unit_file.lookup_by_category("Logger") {
@@ -132,7 +158,51 @@ impl LoggerDescription {
                             },
                         }
                     }
-                    &_ => (),
+                    "Events" => {
+                        logger_description.events = vec![];
+                        for event in directive
+                            .value()
+                            .unwrap_or("")
+                            .split([',', ' '])
+                            .filter(|e| !e.is_empty())
+                        {
+                            let event = event.to_lowercase();
+                            if event == "all" {
+                                logger_description.events =
+                                    RESULT_EVENTS.iter().map(|e| e.to_string()).collect();
+                            } else if RESULT_EVENTS.contains(&event.as_str()) {
+                                logger_description.events.push(event);
+                            } else {
+                                return Err(UnitDescriptionError::InvalidValue(
+                                    "Logger".to_owned(),
+                                    "Events".to_owned(),
+                                    event,
+                                    RESULT_EVENTS.iter().map(|e| e.to_string()).collect(),
+                                ));
+                            }
+                        }
+                    }
+                    key => {
+                        if !logger_description.environment.parse_directive(
+                            "Logger",
+                            key,
+                            directive.value(),
+                        )? {
+                            logger_description.restart.parse_directive(
+                                "Logger",
+                                key,
+                                directive.value(),
+                            )?;
+                        }
+                    }
+                }
+            } else if let DirectiveEntry::Many(ref directives) = entry {
+                for directive in directives {
+                    logger_description.environment.parse_directive(
+                        "Logger",
+                        directive.key(),
+                        directive.value(),
+                    )?;
                 }
             }
         }
@@ -193,6 +263,10 @@ impl Logger {
         &self.description.id
     }
 
+    pub fn restart_policy(&self) -> &RestartPolicy {
+        &self.description.restart
+    }
+
     pub fn select(&self) -> Result<(), UnitSelectError> {
         Ok(())
     }
@@ -204,14 +278,19 @@ impl Logger {
     fn text_read(id: UnitName, control: Sender<ManagerControlMessage>, output: RunningOutput) {
         for line in BufReader::new(output).lines() {
             let line = line.expect("Unable to get next line");
+
+            // Loggers only ever talk back to answer a PING.  Anything else is an error.
+            let msg = {
+                let words: Vec<&str> = line.split_whitespace().collect();
+                if words.len() == 2 && words[0].eq_ignore_ascii_case("pong") {
+                    ManagerControlMessageContents::Pong(words[1].to_owned())
+                } else {
+                    ManagerControlMessageContents::LogError(line.clone())
+                }
+            };
+
             // If the send fails, that means the other end has closed the pipe.
-            if control
-                .send(ManagerControlMessage::new(
-                    &id,
-                    ManagerControlMessageContents::LogError(line),
-                ))
-                .is_err()
-            {
+            if control.send(ManagerControlMessage::new(&id, msg)).is_err() {
                 break;
             }
         }
@@ -222,12 +301,20 @@ impl Logger {
         manager: &UnitManager,
         config: &Config,
     ) -> Result<(), UnitActivateError> {
-        let mut running = Runny::new(self.description.exec_start.as_str())
-            .directory(&Some(config.working_directory(
-                &self.description.unit_directory,
-                &self.description.working_directory,
-            )))
-            .start()?;
+        let vars = self
+            .description
+            .environment
+            .resolve(&self.description.unit_directory)
+            .map_err(UnitActivateError::EnvironmentFailed)?;
+        let mut running = command_with_environment(
+            self.description.exec_start.as_str(),
+            &config.environment(self.id(), &self.description.unit_directory, &vars),
+        )
+        .directory(&Some(config.working_directory(
+            &self.description.unit_directory,
+            &self.description.working_directory,
+        )))
+        .start()?;
 
         // Have stdout and stderr log their output.
         let control_sender = manager.get_control_channel();
@@ -236,7 +323,16 @@ impl Logger {
         let stderr = running.take_error();
         let thr_sender_id = control_sender_id.clone();
         let thr_sender = control_sender.clone();
-        thread::spawn(move || Self::text_read(thr_sender_id, thr_sender, stdout));
+        thread::spawn(move || {
+            Self::text_read(thr_sender_id.clone(), thr_sender.clone(), stdout);
+            // Once stdout closes, the logger has gone away.
+            thr_sender
+                .send(ManagerControlMessage::new(
+                    &thr_sender_id,
+                    ManagerControlMessageContents::ChildExited,
+                ))
+                .ok();
+        });
         thread::spawn(move || Self::text_read(control_sender_id, control_sender, stderr));
 
         let control_sender = manager.get_control_channel();
@@ -274,7 +370,7 @@ impl Logger {
         let mut process_opt = self.process.borrow_mut();
 
         if process_opt.is_none() {
-            return Err(Error::new(ErrorKind::Other, "no process running"));
+            return Err(Error::other("no process running"));
         }
 
         let process = process_opt.as_mut().unwrap();
@@ -284,10 +380,166 @@ impl Logger {
                 LoggerFormat::Tsv => self.tsv_write(l, process),
                 LoggerFormat::Json => self.json_write(l, process),
             },
+            ManagerStatusMessage::Ping(id) => match self.description.format {
+                LoggerFormat::Tsv => writeln!(process, "ping\t{}", id),
+                LoggerFormat::Json => writeln!(process, "{}", msg.to_json()),
+            },
+            ref msg if self.wants(msg) => match self.description.format {
+                LoggerFormat::Tsv => self.tsv_write_event(msg, process),
+                LoggerFormat::Json => self.json_write_event(msg, process),
+            },
             _ => Ok(()),
         }
     }
 
+    /// The name of the result event carried by a message, if it is one.
+    fn event_name(msg: &ManagerStatusMessage) -> Option<&'static str> {
+        match msg {
+            ManagerStatusMessage::Start(_, _) => Some("start"),
+            ManagerStatusMessage::Resumed(_, _) => Some("resume"),
+            ManagerStatusMessage::Running(_) => Some("running"),
+            ManagerStatusMessage::Pass(_, _) => Some("pass"),
+            ManagerStatusMessage::Fail(_, _, _) => Some("fail"),
+            ManagerStatusMessage::Retry(_, _, _, _) => Some("retry"),
+            ManagerStatusMessage::Skipped(_, _) => Some("skip"),
+            ManagerStatusMessage::Finished(_, _, _, _) => Some("finish"),
+            ManagerStatusMessage::Measurement(_, _) => Some("measurement"),
+            ManagerStatusMessage::Set(_, _, _) => Some("set"),
+            ManagerStatusMessage::Iteration(_, _) => Some("iteration"),
+            _ => None,
+        }
+    }
+
+    /// Returns true if this logger subscribed to the result event in this message.
+    pub fn wants(&self, msg: &ManagerStatusMessage) -> bool {
+        match Self::event_name(msg) {
+            Some(name) => self.description.events.iter().any(|e| e == name),
+            None => false,
+        }
+    }
+
+    fn now() -> Duration {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_else(|_| Duration::new(0, 0))
+    }
+
+    /// Write a result event as a JSON record.
+    fn json_write_event(
+        &self,
+        msg: &ManagerStatusMessage,
+        process: &mut Running,
+    ) -> Result<(), Error> {
+        writeln!(process, "{}", Self::json_event_record(msg, Self::now()))
+    }
+
+    /// A result event as a JSON record, with the same fields sent to
+    /// interfaces plus a timestamp.
+    pub fn json_event_record(msg: &ManagerStatusMessage, now: Duration) -> serde_json::Value {
+        let mut record = msg.to_json();
+        record["unix_time"] = now.as_secs().into();
+        record["unix_time_nsecs"] = now.subsec_nanos().into();
+        record
+    }
+
+    /// Write a result event as a TSV record.
+    fn tsv_write_event(
+        &self,
+        msg: &ManagerStatusMessage,
+        process: &mut Running,
+    ) -> Result<(), Error> {
+        match Self::tsv_event_record(msg, Self::now()) {
+            Some(record) => writeln!(process, "{}", record),
+            None => Ok(()),
+        }
+    }
+
+    /// A result event as a TSV record.  Every event starts with the same
+    /// five fields, followed by fields of its own, each in its own column.
+    /// Fields that an event doesn't have a value for are left empty.
+    pub fn tsv_event_record(msg: &ManagerStatusMessage, now: Duration) -> Option<String> {
+        fn number(n: Option<f64>) -> String {
+            n.map(|n| n.to_string()).unwrap_or_default()
+        }
+        fn result(passed: bool) -> String {
+            if passed { "pass" } else { "fail" }.to_owned()
+        }
+
+        let (unit, fields) = match msg {
+            ManagerStatusMessage::Start(scenario, _) => (scenario, vec![]),
+            // Resumed runs list the tests that were kept from the earlier run,
+            // separated by spaces, in a single field.
+            ManagerStatusMessage::Resumed(scenario, tests) => (
+                scenario,
+                vec![tests
+                    .iter()
+                    .map(|t| t.id().as_str())
+                    .collect::<Vec<_>>()
+                    .join(" ")],
+            ),
+            ManagerStatusMessage::Running(test) => (test, vec![]),
+            ManagerStatusMessage::Pass(test, message) => {
+                (test, vec!["0".to_owned(), message.clone()])
+            }
+            ManagerStatusMessage::Fail(test, code, message) => {
+                (test, vec![code.to_string(), message.clone()])
+            }
+            ManagerStatusMessage::Retry(test, attempt, code, message) => (
+                test,
+                vec![attempt.to_string(), code.to_string(), message.clone()],
+            ),
+            ManagerStatusMessage::Skipped(test, reason) => (test, vec![reason.clone()]),
+            ManagerStatusMessage::Finished(scenario, code, reason, run_directory) => (
+                scenario,
+                vec![
+                    code.to_string(),
+                    reason.clone(),
+                    run_directory
+                        .as_ref()
+                        .map(|p| p.to_string_lossy().into_owned())
+                        .unwrap_or_default(),
+                ],
+            ),
+            ManagerStatusMessage::Measurement(test, m) => (
+                test,
+                vec![
+                    m.name.clone(),
+                    m.value.to_string(),
+                    m.units.clone().unwrap_or_default(),
+                    number(m.low),
+                    number(m.high),
+                    result(m.passed),
+                ],
+            ),
+            ManagerStatusMessage::Set(test, key, value) => (test, vec![key.clone(), value.clone()]),
+            ManagerStatusMessage::Iteration(scenario, i) => (
+                scenario,
+                vec![
+                    i.number.to_string(),
+                    result(i.passed),
+                    i.total_passed.to_string(),
+                    i.total_failed.to_string(),
+                    if i.last { "done" } else { "repeating" }.to_owned(),
+                ],
+            ),
+            _ => return None,
+        };
+
+        let mut record = format!(
+            "{}\t{}\t{}\t{}\t{}",
+            Self::event_name(msg)?,
+            Self::cfti_escape(unit.id()),
+            unit.kind(),
+            now.as_secs(),
+            now.subsec_nanos()
+        );
+        for field in fields {
+            record.push('\t');
+            record.push_str(&Self::cfti_escape(&field));
+        }
+        Some(record)
+    }
+
     fn json_write(&self, entry: LogEntry, process: &mut Running) -> Result<(), Error> {
         /*
         let mut object = json::JsonValue::new_object();
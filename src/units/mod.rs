@@ -1,5 +1,6 @@
 pub mod interface;
 pub mod jig;
+pub mod listener;
 pub mod logger;
 pub mod scenario;
 pub mod test;
@@ -4,28 +4,31 @@ extern crate runny;
 extern crate systemd_parser;
 
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
 use std::fs::File;
 use std::io::{BufRead, BufReader, Read};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::sync::mpsc::Sender;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
 use std::thread;
 use std::time::{Duration, Instant};
 
 use self::dependy::{Dependency, Dependy};
 use self::humantime::{parse_duration, DurationError};
 use self::runny::running::Running;
-use self::runny::Runny;
 use self::systemd_parser::items::DirectiveEntry;
 
-use config::Config;
+use config::{command_with_environment, limit_timeout, Config};
 use unit::{
     UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitDeselectError,
-    UnitIncompatibleReason, UnitName, UnitSelectError,
+    UnitEnvironment, UnitIncompatibleReason, UnitName, UnitSelectError,
 };
-use unitmanager::{ManagerControlMessage, ManagerControlMessageContents, UnitManager};
-use units::test::Test;
+use unitmanager::{
+    Iteration, ManagerControlMessage, ManagerControlMessageContents, ManagerStatusMessage,
+    RunMetadata, UnitManager,
+};
+use units::test::{Test, OUTPUT_DRAIN_TIMEOUT};
 
 struct AssumptionDependency {
     name: UnitName,
@@ -60,6 +63,44 @@ impl Dependency<UnitName> for AssumptionDependency {
     }
 }
 
+/// How a scenario is run over and over, such as for burn-in testing.
+#[derive(Clone, Debug, PartialEq)]
+enum Repeat {
+    /// Run the scenario this many times.
+    Count(u32),
+
+    /// Run the scenario until a run fails, at most this many times if given.
+    UntilFailure(Option<u32>),
+
+    /// Keep starting new runs until this much time has passed.
+    Duration(Duration),
+}
+
+/// Why the scenarios named in Includes couldn't be found.
+#[derive(Clone, Debug)]
+enum IncludeError {
+    NotFound(UnitName),
+    Loop(UnitName),
+}
+
+impl From<IncludeError> for UnitIncompatibleReason {
+    fn from(error: IncludeError) -> Self {
+        match error {
+            IncludeError::NotFound(name) => UnitIncompatibleReason::IncludeNotFound(name),
+            IncludeError::Loop(name) => UnitIncompatibleReason::IncludeLoop(name),
+        }
+    }
+}
+
+/// A command run by the scenario itself, rather than by one of its tests.
+struct SupportCommand {
+    command: String,
+    timeout: Option<Duration>,
+
+    /// What to call the command in the log, e.g. "execstart".
+    label: String,
+}
+
 /// A struct defining an in-memory representation of a .scenario file
 #[derive(Clone)]
 pub struct ScenarioDescription {
@@ -81,6 +122,13 @@ pub struct ScenarioDescription {
     /// A Vec<String> of tests that are considered to have passed without running them.
     assumptions: Vec<UnitName>,
 
+    /// Other scenarios whose tests and assumptions are part of this one.
+    includes: Vec<UnitName>,
+
+    /// Every scenario included by this one, directly or not, in the order
+    /// their tests run.  Filled in by resolve_includes().
+    included: Result<Vec<ScenarioDescription>, IncludeError>,
+
     /// The maximum duration, if any, for this scenario
     timeout: Option<Duration>,
 
@@ -110,6 +158,18 @@ pub struct ScenarioDescription {
 
     // The maximum amount of failures before the scenario should be stopped.
     stop_after_failure_count: Option<u32>,
+
+    /// How many tests may run at the same time.
+    max_parallel: usize,
+
+    /// Whether, and for how long, to keep running the scenario again once it finishes.
+    repeat: Option<Repeat>,
+
+    /// How long to wait between runs of a repeating scenario.
+    repeat_delay: Option<Duration>,
+
+    /// Variables to add to the environment of the scenario's programs and tests.
+    environment: UnitEnvironment,
 }
 
 impl ScenarioDescription {
@@ -141,6 +201,8 @@ impl ScenarioDescription {
             jigs: vec![],
             tests: vec![],
             assumptions: vec![],
+            includes: vec![],
+            included: Ok(vec![]),
 
             timeout: None,
 
@@ -154,6 +216,10 @@ impl ScenarioDescription {
             exec_stop_failure: None,
             exec_stop_failure_timeout: None,
             stop_after_failure_count: None,
+            max_parallel: 1,
+            repeat: None,
+            repeat_delay: None,
+            environment: UnitEnvironment::default(),
         };
 
         // Use this value as ExecStopSuccess and/or ExecStopFailure if ExecStop is
@@ -161,6 +227,10 @@ impl ScenarioDescription {
         let mut exec_stop = None;
         let mut exec_stop_timeout = None;
 
+        // Repeat= means different things depending on RepeatUntil=.
+        let mut repeat = None;
+        let mut repeat_until = None;
+
         for entry in unit_file.lookup_by_category("Scenario") {
             if let DirectiveEntry::Solo(ref directive) = entry {
                 match directive.key() {
@@ -179,7 +249,7 @@ impl ScenarioDescription {
                     }
                     "WorkingDirectory" => {
                         if let Some(wd) = directive.value() {
-                            PathBuf::from(wd);
+                            scenario_description.working_directory = Some(PathBuf::from(wd));
                         }
                     }
                     "Tests" => {
@@ -194,6 +264,12 @@ impl ScenarioDescription {
                             None => vec![],
                         }
                     }
+                    "Includes" => {
+                        scenario_description.includes = match directive.value() {
+                            Some(s) => UnitName::from_list(s, "scenario")?,
+                            None => vec![],
+                        }
+                    }
                     "ExecStart" => {
                         scenario_description.exec_start = directive.value().map(|s| s.to_owned())
                     }
@@ -252,7 +328,45 @@ impl ScenarioDescription {
                             Some(s) => Some(s.parse::<u32>()?),
                         }
                     }
-                    &_ => (),
+                    "Repeat" => repeat = directive.value().map(|s| s.to_owned()),
+                    "RepeatUntil" => repeat_until = directive.value().map(|s| s.to_owned()),
+                    "RepeatDelay" => {
+                        scenario_description.repeat_delay = match directive.value() {
+                            None => None,
+                            Some(s) => Some(Self::parse_time(s)?),
+                        }
+                    }
+                    "MaxParallel" => {
+                        scenario_description.max_parallel = match directive.value() {
+                            None => 1,
+                            Some(s) => match s.parse::<usize>()? {
+                                0 => {
+                                    return Err(UnitDescriptionError::InvalidValue(
+                                        "Scenario".to_owned(),
+                                        "MaxParallel".to_owned(),
+                                        s.to_owned(),
+                                        vec!["[a number of tests, at least 1]".to_owned()],
+                                    ))
+                                }
+                                n => n,
+                            },
+                        }
+                    }
+                    key => {
+                        scenario_description.environment.parse_directive(
+                            "Scenario",
+                            key,
+                            directive.value(),
+                        )?;
+                    }
+                }
+            } else if let DirectiveEntry::Many(ref directives) = entry {
+                for directive in directives {
+                    scenario_description.environment.parse_directive(
+                        "Scenario",
+                        directive.key(),
+                        directive.value(),
+                    )?;
                 }
             }
         }
@@ -275,9 +389,101 @@ impl ScenarioDescription {
             }
         }
 
+        scenario_description.repeat =
+            Self::parse_repeat(repeat.as_deref(), repeat_until.as_deref())?;
+
         Ok(scenario_description)
     }
 
+    /// Work out how a scenario repeats from its Repeat and RepeatUntil values.
+    fn parse_repeat(
+        repeat: Option<&str>,
+        until: Option<&str>,
+    ) -> Result<Option<Repeat>, UnitDescriptionError> {
+        let missing_repeat =
+            || UnitDescriptionError::MissingValue("Scenario".to_owned(), "Repeat".to_owned());
+        match until.unwrap_or("count") {
+            "count" => match repeat {
+                None if until.is_none() => Ok(None),
+                None => Err(missing_repeat()),
+                Some(s) => match s.parse::<u32>()? {
+                    0 => Err(UnitDescriptionError::InvalidValue(
+                        "Scenario".to_owned(),
+                        "Repeat".to_owned(),
+                        s.to_owned(),
+                        vec!["[a number of runs, at least 1]".to_owned()],
+                    )),
+                    n => Ok(Some(Repeat::Count(n))),
+                },
+            },
+            "failure" => Ok(Some(Repeat::UntilFailure(match repeat {
+                None => None,
+                Some(s) => Some(s.parse::<u32>()?),
+            }))),
+            "duration" => match repeat {
+                None => Err(missing_repeat()),
+                Some(s) => Ok(Some(Repeat::Duration(Self::parse_time(s)?))),
+            },
+            other => Err(UnitDescriptionError::InvalidValue(
+                "Scenario".to_owned(),
+                "RepeatUntil".to_owned(),
+                other.to_owned(),
+                vec![
+                    "count".to_owned(),
+                    "failure".to_owned(),
+                    "duration".to_owned(),
+                ],
+            )),
+        }
+    }
+
+    /// Describe a scenario that runs a single test.  Unless `with_dependencies`
+    /// is set, every other test is assumed to have passed, so that only the
+    /// named test is run.  The scenario gets an internal name, so that it
+    /// can't be confused with a scenario loaded from a file.
+    pub fn for_test(
+        test_name: &UnitName,
+        with_dependencies: bool,
+        manager: &UnitManager,
+    ) -> ScenarioDescription {
+        let assumptions = if with_dependencies {
+            vec![]
+        } else {
+            manager
+                .get_tests()
+                .borrow()
+                .keys()
+                .filter(|name| *name != test_name)
+                .cloned()
+                .collect()
+        };
+
+        ScenarioDescription {
+            id: UnitName::internal(&format!("run-{}", test_name.id())),
+            name: format!("Run {}", test_name.id()),
+            description: format!("Run the single test {}", test_name.id()),
+            jigs: vec![],
+            tests: vec![test_name.clone()],
+            assumptions,
+            includes: vec![],
+            included: Ok(vec![]),
+            timeout: None,
+            unit_directory: PathBuf::from("."),
+            working_directory: None,
+            exec_start: None,
+            exec_start_timeout: None,
+            exec_stop_success: None,
+            exec_stop_success_timeout: None,
+            exec_stop_failure: None,
+            exec_stop_failure_timeout: None,
+            stop_after_failure_count: None,
+            max_parallel: 1,
+            repeat: None,
+            repeat_delay: None,
+            environment: UnitEnvironment::default(),
+        }
+    }
+
     fn parse_time(time_str: &str) -> Result<Duration, DurationError> {
         if let Ok(val) = time_str.parse::<u64>() {
             Ok(Duration::from_secs(val))
@@ -295,6 +501,117 @@ impl ScenarioDescription {
         self.jigs.contains(name)
     }
 
+    /// Returns true if this scenario includes the named one, directly or not.
+    pub fn includes_scenario(&self, name: &UnitName) -> bool {
+        self.includes.contains(name)
+            || match self.included {
+                Ok(ref included) => included.iter().any(|d| d.id() == name),
+                Err(_) => false,
+            }
+    }
+
+    /// Look up the scenarios named in Includes, along with the ones they include.
+    /// Any problem is reported when the scenario is loaded.
+    pub fn resolve_includes(&mut self, descriptions: &HashMap<UnitName, ScenarioDescription>) {
+        let mut included = vec![];
+        self.included = self
+            .collect_includes(descriptions, &mut vec![self.id.clone()], &mut included)
+            .map(|_| included);
+    }
+
+    fn collect_includes(
+        &self,
+        descriptions: &HashMap<UnitName, ScenarioDescription>,
+        stack: &mut Vec<UnitName>,
+        included: &mut Vec<ScenarioDescription>,
+    ) -> Result<(), IncludeError> {
+        for name in &self.includes {
+            if stack.contains(name) {
+                return Err(IncludeError::Loop(name.clone()));
+            }
+            // A scenario included twice only runs its tests once.
+            if included.iter().any(|d| d.id() == name) {
+                continue;
+            }
+            let description = descriptions
+                .get(name)
+                .ok_or_else(|| IncludeError::NotFound(name.clone()))?;
+            stack.push(name.clone());
+            description.collect_includes(descriptions, stack, included)?;
+            stack.pop();
+
+            let mut description = description.clone();
+            description.included = Ok(vec![]);
+            included.push(description);
+        }
+        Ok(())
+    }
+
+    /// The scenarios included by this one, followed by this one.
+    fn with_included(&self) -> Result<Vec<&ScenarioDescription>, UnitIncompatibleReason> {
+        match self.included {
+            Ok(ref included) => Ok(included.iter().chain(Some(self)).collect()),
+            Err(ref e) => Err(e.clone().into()),
+        }
+    }
+
+    /// The ExecStart commands to run, with those of included scenarios first.
+    fn start_commands(&self) -> Vec<SupportCommand> {
+        self.with_included()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|d| {
+                d.support_command(&d.exec_start, d.exec_start_timeout, "execstart", self)
+            })
+            .collect()
+    }
+
+    /// The ExecStopSuccess or ExecStopFailure commands to run, with this
+    /// scenario's first and those of included scenarios after, in reverse.
+    fn stop_commands(&self, passed: bool) -> Vec<SupportCommand> {
+        self.with_included()
+            .unwrap_or_default()
+            .into_iter()
+            .rev()
+            .filter_map(|d| {
+                if passed {
+                    d.support_command(
+                        &d.exec_stop_success,
+                        d.exec_stop_success_timeout,
+                        "execstopsuccess",
+                        self,
+                    )
+                } else {
+                    d.support_command(
+                        &d.exec_stop_failure,
+                        d.exec_stop_failure_timeout,
+                        "execstopfailure",
+                        self,
+                    )
+                }
+            })
+            .collect()
+    }
+
+    fn support_command(
+        &self,
+        command: &Option<String>,
+        timeout: Option<Duration>,
+        label: &str,
+        including: &ScenarioDescription,
+    ) -> Option<SupportCommand> {
+        let label = if self.id == including.id {
+            label.to_owned()
+        } else {
+            format!("{} {}", self.id.id(), label)
+        };
+        command.as_ref().map(|command| SupportCommand {
+            command: command.clone(),
+            timeout,
+            label,
+        })
+    }
+
     /// Determine if a unit is compatible with this system.
     pub fn is_compatible(
         &self,
@@ -326,12 +643,109 @@ impl ScenarioDescription {
         config: &Config,
     ) -> Result<Scenario, UnitIncompatibleReason> {
         let (test_order, graph) = self.is_compatible(manager, config)?;
-        Ok(Scenario::new(self, test_order, manager, graph))
+        let assumptions = self.assumed_tests(manager)?;
+        Ok(Scenario::new(self, test_order, manager, graph, assumptions))
     }
 
     pub fn get_test_order(
         &self,
         manager: &UnitManager,
+    ) -> Result<(Vec<UnitName>, Dependy<UnitName>), UnitIncompatibleReason> {
+        // Included scenarios' tests come first, in the order they were included.
+        let mut test_names = vec![];
+        for test_name in self.with_included()?.iter().flat_map(|s| &s.tests) {
+            if !test_names.contains(test_name) {
+                test_names.push(test_name.clone());
+            }
+        }
+        Self::resolve_tests(manager, &test_names, &self.assumed_tests(manager)?)
+    }
+
+    /// The tests this scenario would run, in order, along with why each one is
+    /// part of it.  Assumed tests come last.  Nothing is run.
+    pub fn plan(&self, manager: &UnitManager) -> Result<Vec<PlannedTest>, UnitIncompatibleReason> {
+        let (test_order, _) = self.get_test_order(manager)?;
+        let assumptions = self.assumed_tests(manager)?;
+        let explicit: Vec<&UnitName> = self
+            .with_included()?
+            .iter()
+            .flat_map(|s| &s.tests)
+            .collect();
+        let tests_rc = manager.get_tests();
+        let tests = tests_rc.borrow();
+
+        let mut plan = vec![];
+        for test_name in test_order.iter().chain(&assumptions) {
+            let mut reasons = vec![];
+            if assumptions.contains(test_name) {
+                reasons.push(Inclusion::Assumed);
+            }
+            let mut aliases = vec![test_name.clone()];
+            if let Some(test) = tests.get(test_name) {
+                aliases.extend(test.borrow().provides().iter().cloned());
+            }
+            for alias in &aliases {
+                let mut reasons_for_alias = vec![];
+                if explicit.contains(&alias) {
+                    reasons_for_alias.push(Inclusion::Explicit);
+                }
+                for other_name in &test_order {
+                    let other = match tests.get(other_name) {
+                        Some(other) => other.borrow(),
+                        None => continue,
+                    };
+                    if other.requirements().contains(alias) {
+                        reasons_for_alias.push(Inclusion::RequiredBy(other_name.clone()));
+                    }
+                    if other.suggestions().contains(alias) {
+                        reasons_for_alias.push(Inclusion::SuggestedBy(other_name.clone()));
+                    }
+                }
+                if alias != test_name && !reasons_for_alias.is_empty() {
+                    reasons_for_alias.push(Inclusion::Provides(alias.clone()));
+                }
+                for reason in reasons_for_alias {
+                    if !reasons.contains(&reason) {
+                        reasons.push(reason);
+                    }
+                }
+            }
+            plan.push(PlannedTest {
+                name: test_name.clone(),
+                reasons,
+            });
+        }
+        Ok(plan)
+    }
+
+    /// The tests that are considered to have passed.  Tests assumed by an
+    /// included scenario are only assumed if no other part of this one runs them.
+    pub fn assumed_tests(
+        &self,
+        manager: &UnitManager,
+    ) -> Result<Vec<UnitName>, UnitIncompatibleReason> {
+        let scenarios = self.with_included()?;
+        let mut assumptions = self.assumptions.clone();
+        if scenarios.len() == 1 {
+            return Ok(assumptions);
+        }
+
+        let mut run = vec![];
+        for scenario in &scenarios {
+            run.extend(Self::resolve_tests(manager, &scenario.tests, &scenario.assumptions)?.0);
+        }
+        for test_name in scenarios.iter().flat_map(|s| &s.assumptions) {
+            if !run.contains(test_name) && !assumptions.contains(test_name) {
+                assumptions.push(test_name.clone());
+            }
+        }
+        Ok(assumptions)
+    }
+
+    fn resolve_tests(
+        manager: &UnitManager,
+        test_names: &[UnitName],
+        assumptions: &[UnitName],
     ) -> Result<(Vec<UnitName>, Dependy<UnitName>), UnitIncompatibleReason> {
         // Create a new dependency graph
         let mut graph = Dependy::new();
@@ -341,7 +755,7 @@ impl ScenarioDescription {
             let tests_rc = manager.get_tests();
             let tests = tests_rc.borrow();
             for (test_name, test) in tests.iter() {
-                if self.assumptions.contains(test_name) {
+                if assumptions.contains(test_name) {
                     let assumption_dep = AssumptionDependency::new(test_name.clone());
                     graph.add_dependency(&assumption_dep);
                 } else {
@@ -350,25 +764,56 @@ impl ScenarioDescription {
             }
         }
 
-        let mut test_names = vec![];
-        for test_name in &self.tests {
-            test_names.push(test_name.clone());
-        }
-
-        let test_sequence = graph.resolve_named_dependencies(&test_names)?;
+        let test_sequence = graph.resolve_named_dependencies(&test_names.to_vec())?;
         let mut test_order = vec![];
         for test_name in test_sequence {
             // Only add the test to the test order if it's not an assumption.
-            if !self.assumptions.contains(&test_name) {
+            if !assumptions.contains(&test_name) {
                 test_order.push(test_name);
             }
         }
 
-        // let test_order = trimmed_order;
         Ok((test_order, graph))
     }
 }
 
+/// Why a test is part of a scenario.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Inclusion {
+    /// The test is named in the scenario's Tests.
+    Explicit,
+
+    /// Another test in the scenario Requires this one.
+    RequiredBy(UnitName),
+
+    /// Another test in the scenario Suggests this one.
+    SuggestedBy(UnitName),
+
+    /// The test was asked for by a name that it Provides.
+    Provides(UnitName),
+
+    /// The test is assumed to have passed, and won't be run.
+    Assumed,
+}
+
+impl fmt::Display for Inclusion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Inclusion::Explicit => write!(f, "explicit"),
+            Inclusion::RequiredBy(ref test) => write!(f, "required by {}", test.id()),
+            Inclusion::SuggestedBy(ref test) => write!(f, "suggested by {}", test.id()),
+            Inclusion::Provides(ref name) => write!(f, "provides {}", name.id()),
+            Inclusion::Assumed => write!(f, "assumed"),
+        }
+    }
+}
+
+/// One test in a scenario's plan, as shown by "exclave plan".
+pub struct PlannedTest {
+    pub name: UnitName,
+    pub reasons: Vec<Inclusion>,
+}
+
 #[derive(Clone, PartialEq, Debug)]
 enum ScenarioState {
     /// The scenario has been loaded, and is ready to run.
@@ -377,8 +822,8 @@ enum ScenarioState {
     /// The scenario has started, but is waiting for ExecStart to finish
     PreStart,
 
-    /// The scenario is running, and is on step (u32)
-    Running(usize),
+    /// The scenario is running tests
+    Running,
 
     /// The scenario has succeeded, and is running the ExecStopSuccess step
     PostSuccess,
@@ -390,6 +835,19 @@ enum ScenarioState {
     ScenarioFinished,
 }
 
+impl ScenarioState {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            ScenarioState::Idle => "idle",
+            ScenarioState::PreStart => "prestart",
+            ScenarioState::Running => "running",
+            ScenarioState::PostSuccess => "post-success",
+            ScenarioState::PostFailure => "post-failure",
+            ScenarioState::ScenarioFinished => "finished",
+        }
+    }
+}
+
 #[derive(PartialEq, Clone, Debug)]
 pub enum TestState {
     /// A test has yet to be run.
@@ -408,6 +866,44 @@ pub enum TestState {
     Fail(String),
 }
 
+impl TestState {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            TestState::Pending => "pending",
+            TestState::Running => "running",
+            TestState::Pass => "pass",
+            TestState::Skip => "skip",
+            TestState::Fail(_) => "fail",
+        }
+    }
+}
+
+/// Progress through the runs of a repeating scenario.
+struct Iterations {
+    /// The run in progress (or most recently finished), starting from 1.
+    number: u32,
+    passed: u32,
+    failed: u32,
+
+    /// When the first run started.
+    started: Instant,
+
+    /// Set once the operator aborts, so that no more runs are started.
+    stopped: bool,
+}
+
+impl Iterations {
+    fn new() -> Iterations {
+        Iterations {
+            number: 0,
+            passed: 0,
+            failed: 0,
+            started: Instant::now(),
+            stopped: false,
+        }
+    }
+}
+
 pub struct Scenario {
     /// A reference to the scenario description that constructed this test.
     description: ScenarioDescription,
@@ -418,6 +914,12 @@ pub struct Scenario {
     /// A pointer to the tests that are part of this scenario.
     tests: HashMap<UnitName, Rc<RefCell<Test>>>,
 
+    /// Tests that are considered to have passed without running them.
+    assumptions: Vec<UnitName>,
+
+    /// The steps of the tests that are running right now.
+    running: RefCell<Vec<usize>>,
+
     /// The results of each individual test.
     test_states: HashMap<UnitName, Rc<RefCell<TestState>>>,
 
@@ -427,6 +929,16 @@ pub struct Scenario {
     /// How many tests have failed in this particular run.
     failures: Rc<RefCell<u32>>,
 
+    /// Whether this run went past the scenario's Timeout.
+    timed_out: RefCell<bool>,
+
+    /// Keeps the current run's Timeout watchdog waiting.  Dropping it
+    /// cancels the watchdog.
+    watchdog: RefCell<Option<Sender<()>>>,
+
+    /// Whether this run was aborted by an interface.
+    aborted: RefCell<bool>,
+
     /// The current state of the scenario, when activated.
     state: Rc<RefCell<ScenarioState>>,
 
@@ -434,12 +946,43 @@ pub struct Scenario {
     /// Used for PreStart and PostFinish scripts.
     support_wd: Rc<RefCell<PathBuf>>,
 
+    /// The directory created for this run's artifacts, if any.
+    run_directory: RefCell<Option<PathBuf>>,
+
+    /// Extra environment variables for PreStart and PostFinish scripts.
+    support_env: RefCell<Vec<(String, String)>>,
+
+    /// The support commands still to run in the current state.
+    support_commands: RefCell<VecDeque<SupportCommand>>,
+
+    /// Variables set by tests during the current run.
+    variables: RefCell<RunMetadata>,
+
     /// The dependency graph of tests.
     graph: Dependy<UnitName>,
 
     /// When the test was started.
     start_time: Instant,
 
+    /// When the scenario finished, if it has.
+    finish_time: Rc<RefCell<Option<Instant>>>,
+
+    /// When each test started running, and when it finished.
+    test_times: RefCell<HashMap<UnitName, (Instant, Option<Instant>)>>,
+
+    /// How many times each test has been run, for tests that have been retried.
+    attempts: RefCell<HashMap<UnitName, u32>>,
+
+    /// Tests that were stopped to be retried, and the result that they
+    /// failed with, until they are started again.
+    awaiting_retry: RefCell<HashMap<UnitName, i32>>,
+
+    /// How many times the scenario has been run, if it repeats.
+    iterations: RefCell<Iterations>,
+
+    /// Whether the next run picks up where the last one left off.
+    resuming: RefCell<bool>,
+
     /// The currently-executing program (if any)
     program: Rc<RefCell<Option<Running>>>,
 }
@@ -450,6 +993,7 @@ impl Scenario {
         test_order: Vec<UnitName>,
         manager: &UnitManager,
         graph: Dependy<UnitName>,
+        assumptions: Vec<UnitName>,
     ) -> Scenario {
         let mut tests = HashMap::new();
         let mut test_sequence = vec![];
@@ -467,14 +1011,29 @@ impl Scenario {
         Scenario {
             description: desc.clone(),
             tests,
+            assumptions,
             test_sequence,
+            running: RefCell::new(vec![]),
             test_states: test_state,
             exec_start_state: Rc::new(RefCell::new(TestState::Pending)),
             state: Rc::new(RefCell::new(ScenarioState::Idle)),
             support_wd: Rc::new(RefCell::new(desc.unit_directory.clone())),
+            run_directory: RefCell::new(None),
+            support_env: RefCell::new(vec![]),
+            support_commands: RefCell::new(VecDeque::new()),
+            variables: RefCell::new(RunMetadata::new()),
             failures: Rc::new(RefCell::new(0)),
+            timed_out: RefCell::new(false),
+            watchdog: RefCell::new(None),
+            aborted: RefCell::new(false),
             graph,
             start_time: Instant::now(),
+            finish_time: Rc::new(RefCell::new(None)),
+            test_times: RefCell::new(HashMap::new()),
+            attempts: RefCell::new(HashMap::new()),
+            awaiting_retry: RefCell::new(HashMap::new()),
+            iterations: RefCell::new(Iterations::new()),
+            resuming: RefCell::new(false),
             program: Rc::new(RefCell::new(None)),
         }
     }
@@ -511,14 +1070,55 @@ impl Scenario {
         // We'll communicate to the manager through this pipe.
         let ctrl = manager.get_control_channel();
 
-        // Start afresh and reset our failure count.
+        // Start afresh and reset our failure count.  A resumed run keeps the
+        // tests that passed last time, along with any variables they set.
+        let resuming = self.resuming.replace(false);
+        let kept = if resuming {
+            self.resumable_tests()
+        } else {
+            vec![]
+        };
+        if !resuming {
+            self.iterations.borrow_mut().number += 1;
+        }
         *self.failures.borrow_mut() = 0;
+        *self.timed_out.borrow_mut() = false;
+        *self.aborted.borrow_mut() = false;
+        self.awaiting_retry.borrow_mut().clear();
         self.start_time = Instant::now();
+        config.set_scenario_deadline(self.deadline());
+        *self.finish_time.borrow_mut() = None;
+        self.test_times
+            .borrow_mut()
+            .retain(|test_name, _| kept.contains(test_name));
+        self.attempts
+            .borrow_mut()
+            .retain(|test_name, _| kept.contains(test_name));
+        self.running.borrow_mut().clear();
+        if !resuming {
+            self.variables.borrow_mut().clear();
+        }
+        config.clear_run_variables();
+        for (key, value) in self.variables.borrow().iter() {
+            config.set_run_variable(key, value);
+        }
         *self.state.borrow_mut() = ScenarioState::Idle;
         *self.exec_start_state.borrow_mut() = TestState::Pending;
-        self.test_states.iter().for_each(|(_, item)| {
-            *item.borrow_mut() = TestState::Pending;
+        self.test_states.iter().for_each(|(test_name, item)| {
+            if !kept.contains(test_name) {
+                *item.borrow_mut() = TestState::Pending;
+            }
         });
+        if resuming {
+            ctrl.send(ManagerControlMessage::new(
+                self.id(),
+                ManagerControlMessageContents::Log(format!(
+                    "resuming previous run, keeping {} passed tests",
+                    kept.len()
+                )),
+            ))
+            .ok();
+        }
 
         // Re-assign our working directory.
         if let Some(ref wd) = &self.description.working_directory {
@@ -527,6 +1127,37 @@ impl Scenario {
             config.clear_scenario_working_directory();
         }
 
+        // Tests run as part of this scenario inherit its environment, along
+        // with that of any scenarios it includes.
+        match self
+            .description
+            .with_included()
+            .unwrap_or_default()
+            .iter()
+            .map(|d| d.environment.resolve(&d.unit_directory))
+            .collect::<Result<Vec<_>, _>>()
+            .map(|vars| vars.concat())
+        {
+            Ok(vars) => config.set_scenario_environment(self.id(), vars),
+            Err(e) => {
+                config.clear_scenario_environment();
+                let message = format!("unable to read environment: {}", e);
+                ctrl.send(ManagerControlMessage::new(
+                    self.id(),
+                    ManagerControlMessageContents::LogError(message.clone()),
+                ))
+                .ok();
+                *self.state.borrow_mut() = ScenarioState::ScenarioFinished;
+                *self.finish_time.borrow_mut() = Some(Instant::now());
+                ctrl.send(ManagerControlMessage::new(
+                    self.id(),
+                    ManagerControlMessageContents::ScenarioFinished(500, message),
+                ))
+                .ok();
+                return Ok(());
+            }
+        }
+
         // Since `config` doesn't get passed around anymore, create a copy of the `working_directory`
         // so that we can run support commands.
         *self.support_wd.borrow_mut() = config.working_directory(
@@ -534,6 +1165,65 @@ impl Scenario {
             &self.description.working_directory,
         );
 
+        // Give this run a directory of its own to collect artifacts in, if an
+        // output directory was given.  A resumed run carries on in the directory
+        // of the run it picks up from.
+        let previous_directory = self.run_directory.borrow().clone();
+        let run_directory = match previous_directory {
+            Some(ref path) if resuming => {
+                config.set_run_directory(path);
+                Ok(Some(path.clone()))
+            }
+            _ => config.create_run_directory(self.id().id()),
+        };
+        *self.run_directory.borrow_mut() = match run_directory {
+            Ok(None) => None,
+            Ok(Some(path)) => {
+                ctrl.send(ManagerControlMessage::new(
+                    self.id(),
+                    ManagerControlMessageContents::Log(format!(
+                        "run directory: {}",
+                        path.display()
+                    )),
+                ))
+                .ok();
+                Some(path)
+            }
+            Err(e) => {
+                config.clear_run_directory();
+                ctrl.send(ManagerControlMessage::new(
+                    self.id(),
+                    ManagerControlMessageContents::LogError(format!(
+                        "unable to create run directory: {}",
+                        e
+                    )),
+                ))
+                .ok();
+                None
+            }
+        };
+        *self.support_env.borrow_mut() =
+            config.environment(self.id(), &self.description.unit_directory, &[]);
+
+        // Stop whatever is running once the scenario's time is up, unless
+        // the run finishes first.
+        if let Some(timeout) = self.description.timeout {
+            let (watchdog, cancelled) = channel::<()>();
+            *self.watchdog.borrow_mut() = Some(watchdog);
+            let thr_control = ctrl.clone();
+            let id = self.id().clone();
+            thread::spawn(move || {
+                if let Err(RecvTimeoutError::Timeout) = cancelled.recv_timeout(timeout) {
+                    thr_control
+                        .send(ManagerControlMessage::new(
+                            &id,
+                            ManagerControlMessageContents::ScenarioTimedOut,
+                        ))
+                        .ok();
+                }
+            });
+        }
+
         // Cause the scenario to move to the next (i.e. first) phase.
         ctrl.send(ManagerControlMessage::new(
             self.id(),
@@ -545,11 +1235,57 @@ impl Scenario {
     }
 
     pub fn deactivate(&self) -> Result<(), UnitDeactivateError> {
+        self.watchdog.borrow_mut().take();
         Ok(())
     }
 
+    /// Remember a variable set by one of our tests for the rest of the run.
+    pub fn set_variable(&self, key: &str, value: &str) {
+        self.variables
+            .borrow_mut()
+            .insert(key.to_owned(), value.to_owned());
+    }
+
+    /// Variables set by tests during the current (or most recent) run.
+    pub fn variables(&self) -> RunMetadata {
+        self.variables.borrow().clone()
+    }
+
+    /// Make the next activation pick up where the last run left off,
+    /// and return the tests whose results will be kept.
+    pub fn prepare_resume(&self) -> Result<Vec<UnitName>, String> {
+        if *self.state.borrow() != ScenarioState::ScenarioFinished {
+            return Err("scenario has not finished a run".to_owned());
+        }
+        let kept = self.resumable_tests();
+        if *self.failures.borrow() == 0 && kept.len() == self.test_sequence.len() {
+            return Err("every test passed last time".to_owned());
+        }
+        *self.resuming.borrow_mut() = true;
+        Ok(kept)
+    }
+
+    /// The tests that passed in the last run, in order.  Daemons were stopped
+    /// when that run finished, so they always run again.
+    fn resumable_tests(&self) -> Vec<UnitName> {
+        self.test_sequence
+            .iter()
+            .map(|test| test.borrow())
+            .filter(|test| {
+                !test.is_daemon()
+                    && *self.test_states.get(test.id()).unwrap().borrow() == TestState::Pass
+            })
+            .map(|test| test.id().clone())
+            .collect()
+    }
+
+    /// The directory created for the current (or most recent) run, if any.
+    pub fn run_directory(&self) -> Option<PathBuf> {
+        self.run_directory.borrow().clone()
+    }
+
     pub fn uses_test(&self, test_name: &UnitName) -> bool {
-        self.tests.get(test_name).is_some()
+        self.tests.contains_key(test_name)
     }
 
     pub fn name(&self) -> &String {
@@ -564,6 +1300,74 @@ impl Scenario {
         *self.failures.borrow_mut() += 1;
     }
 
+    /// Start counting runs afresh, for a scenario that repeats.
+    pub fn reset_iterations(&self) {
+        *self.iterations.borrow_mut() = Iterations::new();
+    }
+
+    /// Returns true if the scenario will be run again once this run finishes.
+    pub fn is_repeating(&self) -> bool {
+        self.description.repeat.is_some() && !self.iterations.borrow().stopped
+    }
+
+    /// Don't start any more runs of a repeating scenario.
+    pub fn stop_repeating(&self, ctrl: &Sender<ManagerControlMessage>) {
+        if !self.is_repeating() {
+            return;
+        }
+        let mut iterations = self.iterations.borrow_mut();
+        iterations.stopped = true;
+        ctrl.send(ManagerControlMessage::new(
+            self.id(),
+            ManagerControlMessageContents::Log(format!(
+                "not repeating after run {} ({} passed, {} failed)",
+                iterations.number, iterations.passed, iterations.failed
+            )),
+        ))
+        .ok();
+    }
+
+    /// Abort the run if it has gone past the scenario's Timeout.  Running
+    /// tests and the ExecStart command are stopped, and the scenario moves
+    /// on to ExecStopFail once they've exited.
+    pub fn time_out(&self, ctrl: &Sender<ManagerControlMessage>) {
+        // The watchdog of an earlier run may still go off.
+        if !self.is_running() || !self.scenario_timed_out() || self.timed_out.replace(true) {
+            return;
+        }
+        self.indicate_failure();
+        ctrl.send(ManagerControlMessage::new(
+            self.id(),
+            ManagerControlMessageContents::LogError(format!(
+                "scenario timed out after {:?}",
+                self.start_time.elapsed()
+            )),
+        ))
+        .ok();
+
+        self.give_up_retries(ctrl);
+        match *self.state.borrow() {
+            ScenarioState::PreStart => {
+                if let Some(ref mut program) = *self.program.borrow_mut() {
+                    program.terminate(None).ok();
+                }
+            }
+            ScenarioState::Running => {
+                for &step in self.running.borrow().iter() {
+                    ctrl.send(ManagerControlMessage::new(
+                        self.id(),
+                        ManagerControlMessageContents::StopTest(
+                            self.test_sequence[step].borrow().id().clone(),
+                        ),
+                    ))
+                    .ok();
+                }
+            }
+            // Let the ExecStop commands clean up.
+            _ => (),
+        }
+    }
+
     // Given the current state, figure out the next test to run (if any)
     pub fn advance(
         &self,
@@ -575,14 +1379,33 @@ impl Scenario {
 
         // Run the test's stop() command if we just ran a test.
         match current_state {
-            ScenarioState::Running(step) => {
-                let test_id = self.test_sequence[step].borrow().id().clone();
-                if test_id != *last_unit {
+            ScenarioState::Running => {
+                let step = match self.running_step(last_unit) {
+                    Some(step) => step,
+                    None => {
+                        ctrl.send(ManagerControlMessage::new(
+                            self.id(),
+                            ManagerControlMessageContents::LogError(format!(
+                                "unit {} is not a currently-running test",
+                                last_unit
+                            )),
+                        ))
+                        .ok();
+                        return;
+                    }
+                };
+                let test_id = last_unit.clone();
+                if self.pending_retry(&test_id, last_result).is_some() {
+                    self.retry_test(&test_id, last_result, ctrl);
+                    return;
+                }
+                let attempts = self.attempts(&test_id);
+                if last_result == 0 && attempts > 1 {
                     ctrl.send(ManagerControlMessage::new(
-                        self.id(),
-                        ManagerControlMessageContents::LogError(format!(
-                            "unit {} is not the expected currently-running unit: {} (step {})",
-                            last_unit, test_id, step
+                        last_unit,
+                        ManagerControlMessageContents::Log(format!(
+                            "test passed on attempt {}",
+                            attempts
                         )),
                     ))
                     .ok();
@@ -603,6 +1426,10 @@ impl Scenario {
                     }
                 };
                 *self.test_states.get(&test_id).unwrap().borrow_mut() = result;
+                if let Some(times) = self.test_times.borrow_mut().get_mut(&test_id) {
+                    times.1 = Some(Instant::now());
+                }
+                self.running.borrow_mut().retain(|&s| s != step);
                 /* Run the test's STOP command */
                 if !self.test_sequence[step].borrow().is_daemon() {
                     ctrl.send(ManagerControlMessage::new(
@@ -611,6 +1438,23 @@ impl Scenario {
                     ))
                     .ok();
                 }
+
+                // Start whatever can run now that this test is done, and
+                // only move on once every test has finished.
+                self.start_ready_tests(ctrl);
+                if !self.running.borrow().is_empty() {
+                    return;
+                }
+            }
+            // Run the rest of the ExecStart commands, unless one failed.
+            ScenarioState::PreStart if last_result == 0 && self.run_next_support_cmd(ctrl) => {
+                return
+            }
+            // Every ExecStop command runs, even if an earlier one failed.
+            ScenarioState::PostSuccess | ScenarioState::PostFailure
+                if self.run_next_support_cmd(ctrl) =>
+            {
+                return
             }
             ScenarioState::PreStart => match last_result {
                 0 => *self.exec_start_state.borrow_mut() = TestState::Pass,
@@ -631,39 +1475,12 @@ impl Scenario {
             ScenarioState::Idle => (),
 
             // If we want to run a preroll command and it fails, log it and start the tests.
-            ScenarioState::PreStart => {
-                // Unwrap because we've already validated it exists by setting the state to PreStart.
-                let cmd = &self.description.exec_start.clone().unwrap();
-                self.run_support_cmd(cmd, ctrl, &self.description.exec_start_timeout, "execstart");
-            }
-            ScenarioState::Running(next_step) => {
-                let test = &self.test_sequence[next_step].borrow();
-                let test_timeout = test.timeout();
-                let _test_max_time = self.make_timeout(test_timeout);
-                ctrl.send(ManagerControlMessage::new(
-                    self.id(),
-                    ManagerControlMessageContents::StartTest(test.id().clone()),
-                ))
-                .ok();
-            }
-            ScenarioState::PostSuccess => {
-                let cmd = &self.description.exec_stop_success.clone().unwrap();
-                self.run_support_cmd(
-                    cmd,
-                    ctrl,
-                    &self.description.exec_stop_success_timeout,
-                    "execstopsuccess",
-                );
-            }
-            ScenarioState::PostFailure => {
-                let cmd = &self.description.exec_stop_failure.clone().unwrap();
-                self.run_support_cmd(
-                    cmd,
-                    ctrl,
-                    &self.description.exec_stop_failure_timeout,
-                    "execstopfailure",
-                );
+            // The commands were queued up when we checked that there were any to run.
+            ScenarioState::PreStart | ScenarioState::PostSuccess | ScenarioState::PostFailure => {
+                self.run_next_support_cmd(ctrl);
             }
+            // The first tests were started when we checked that there were any to run.
+            ScenarioState::Running => (),
 
             // If we're transitioning to the Finshed state, it means we just finished
             // running some tests.  Broadcast the result.
@@ -671,6 +1488,140 @@ impl Scenario {
         }
     }
 
+    /// How many times the named test has been run in this scenario.
+    fn attempts(&self, test_id: &UnitName) -> u32 {
+        *self.attempts.borrow().get(test_id).unwrap_or(&1)
+    }
+
+    /// The step of the named test, if it's running right now.
+    fn running_step(&self, test_id: &UnitName) -> Option<usize> {
+        self.running
+            .borrow()
+            .iter()
+            .cloned()
+            .find(|&step| self.test_sequence[step].borrow().id() == test_id)
+    }
+
+    /// If the named test is currently running, and it should be run again
+    /// after failing with `result`, return the attempt that just failed.
+    pub fn pending_retry(&self, test_id: &UnitName, result: i32) -> Option<u32> {
+        if *self.state.borrow() != ScenarioState::Running
+            || *self.timed_out.borrow()
+            || *self.aborted.borrow()
+        {
+            return None;
+        }
+        let step = self.running_step(test_id)?;
+        let test = self.test_sequence[step].borrow();
+        let attempts = self.attempts(test_id);
+        if test.should_retry(attempts, result) {
+            Some(attempts)
+        } else {
+            None
+        }
+    }
+
+    /// Stop the current test, and start it again once its RetryDelay has passed.
+    fn retry_test(&self, test_id: &UnitName, result: i32, ctrl: &Sender<ManagerControlMessage>) {
+        let attempts = self.attempts(test_id);
+        self.attempts
+            .borrow_mut()
+            .insert(test_id.clone(), attempts + 1);
+        self.awaiting_retry
+            .borrow_mut()
+            .insert(test_id.clone(), result);
+
+        let test = self.tests[test_id].borrow();
+        let delay = *test.retry_delay();
+        ctrl.send(ManagerControlMessage::new(
+            test_id,
+            ManagerControlMessageContents::LogError(format!(
+                "attempt {} of {} failed with return code {}, retrying",
+                attempts,
+                test.max_attempts(),
+                result
+            )),
+        ))
+        .ok();
+        ctrl.send(ManagerControlMessage::new(
+            self.id(),
+            ManagerControlMessageContents::StopTest(test_id.clone()),
+        ))
+        .ok();
+
+        let restart = ManagerControlMessage::new(
+            self.id(),
+            ManagerControlMessageContents::StartTest(test_id.clone()),
+        );
+        match delay {
+            None => {
+                ctrl.send(restart).ok();
+            }
+            Some(delay) => {
+                let thr_control = ctrl.clone();
+                thread::spawn(move || {
+                    thread::sleep(delay);
+                    thr_control.send(restart).ok();
+                });
+            }
+        }
+    }
+
+    /// Returns true if a StartTest sent by this scenario should still be
+    /// honoured, which it isn't once the run or the test has finished.
+    pub fn accept_test_start(&self, test_id: &UnitName) -> bool {
+        self.awaiting_retry.borrow_mut().remove(test_id);
+        *self.state.borrow() == ScenarioState::Running && self.running_step(test_id).is_some()
+    }
+
+    /// Stop the run because an interface asked for it.  Tests that fail
+    /// from here on aren't retried.
+    pub fn abort(&self, ctrl: &Sender<ManagerControlMessage>) {
+        if !self.is_running() || self.aborted.replace(true) {
+            return;
+        }
+        self.indicate_failure();
+        self.give_up_retries(ctrl);
+    }
+
+    /// Fail any tests that are waiting for their RetryDelay to pass, using
+    /// the result of their last attempt.
+    fn give_up_retries(&self, ctrl: &Sender<ManagerControlMessage>) {
+        for (test_id, result) in self.awaiting_retry.borrow_mut().drain() {
+            ctrl.send(ManagerControlMessage::new(
+                &test_id,
+                ManagerControlMessageContents::TestFinished(
+                    result,
+                    "not retried because the run was stopped".to_owned(),
+                ),
+            ))
+            .ok();
+            ctrl.send(ManagerControlMessage::new(
+                &test_id,
+                ManagerControlMessageContents::AdvanceScenario(result),
+            ))
+            .ok();
+        }
+    }
+
+    /// Queue up the support commands for the next state, and return true if there are any.
+    fn queue_support_cmds(&self, commands: Vec<SupportCommand>) -> bool {
+        *self.support_commands.borrow_mut() = commands.into_iter().collect();
+        !self.support_commands.borrow().is_empty()
+    }
+
+    /// Run the next queued support command, if there is one.
+    fn run_next_support_cmd(&self, ctrl: &Sender<ManagerControlMessage>) -> bool {
+        let next = self.support_commands.borrow_mut().pop_front();
+        match next {
+            Some(cmd) => {
+                self.run_support_cmd(&cmd.command, ctrl, &cmd.timeout, &cmd.label);
+                true
+            }
+            None => false,
+        }
+    }
+
     /// Run a support command (i.e. ExecStart, ExecStopSuccess, or ExecStopFailure).
     /// Will emit an AdvanceScenario message upon completion.
     fn run_support_cmd(
@@ -685,8 +1636,11 @@ impl Scenario {
             ManagerControlMessageContents::Log(format!("{}: starting [{}]", testname, cmd)),
         ))
         .ok();
-        let mut run_cmd = Runny::new(cmd);
-        if let Some(timeout) = *timeout {
+        // Variables set by tests can't be named like ours, so they can go last.
+        let mut environment = self.support_env.borrow().clone();
+        environment.extend(self.variables.borrow().clone());
+        let mut run_cmd = command_with_environment(cmd, &environment);
+        if let Some(timeout) = self.make_timeout(timeout) {
             run_cmd.timeout(timeout);
         }
         run_cmd.directory(&Some(self.support_wd.borrow().clone()));
@@ -710,7 +1664,7 @@ impl Scenario {
             }
         };
 
-        self.log_output(ctrl, &mut running);
+        let stdout_done = self.log_output(ctrl, &mut running);
 
         // Keep a waiter around in a separate thread to send that AdvanceScenario message upon completion.
         let thr_waiter = running.waiter();
@@ -720,6 +1674,8 @@ impl Scenario {
         let thr_testname = testname.to_owned();
         thread::spawn(move || {
             thr_waiter.wait();
+            // Log everything the command printed before moving on.
+            stdout_done.recv_timeout(OUTPUT_DRAIN_TIMEOUT).ok();
             thr_control
                 .send(ManagerControlMessage::new(
                     &id,
@@ -740,11 +1696,18 @@ impl Scenario {
         *self.program.borrow_mut() = Some(running);
     }
 
-    fn log_output(&self, control: &Sender<ManagerControlMessage>, process: &mut Running) {
+    fn log_output(
+        &self,
+        control: &Sender<ManagerControlMessage>,
+        process: &mut Running,
+    ) -> Receiver<()> {
         let stdout = process.take_output();
         let thr_control = control.clone();
         let id = self.id().clone();
+        // The receiver is disconnected once all of stdout has been read.
+        let (done, stdout_done) = channel();
         thread::spawn(move || {
+            let _done: Sender<()> = done;
             for line in BufReader::new(stdout).lines() {
                 let line = line.expect("Unable to get next line");
                 if thr_control
@@ -776,19 +1739,19 @@ impl Scenario {
                 }
             }
         });
+        stdout_done
     }
 
     /// Find the next state.
     /// If we're idle, start the test.
     /// The state order goes:
-    /// Idle -> [PreStart] -> Test(0) -> ... -> Test(n) -> [PostSuccess/Fail] -> Idle
+    /// Idle -> [PreStart] -> Running -> [PostSuccess/Fail] -> Idle
     ///
     fn find_next_state(
         &self,
         current_state: ScenarioState,
         ctrl: &Sender<ManagerControlMessage>,
     ) -> ScenarioState {
-        let test_count = self.tests.len();
         let failure_count = *self.failures.borrow();
 
         let new_state = match current_state {
@@ -797,35 +1760,13 @@ impl Scenario {
                 ScenarioState::PreStart
             }
 
-            // If we've just run the PreStart command, see if we need
-            // to run test 0, or skip straight to Success.
-            ScenarioState::PreStart => ScenarioState::Running(0),
+            // If we've just run the PreStart command, see if there
+            // are tests to run, or skip straight to Success.
+            ScenarioState::PreStart => ScenarioState::Running,
 
-            // If we just finished running a test, determine the next test to run.
-            ScenarioState::Running(i) if (i + 1) < test_count => {
-                match self.description.stop_after_failure_count {
-                    Some(count) => {
-                        if failure_count >= count {
-                            ScenarioState::PostFailure
-                        } else {
-                            ScenarioState::Running(i + 1)
-                        }
-                    }
-                    None => ScenarioState::Running(i + 1),
-                }
-            }
-            ScenarioState::Running(i) if (i + 1) >= test_count && failure_count > 0 => {
-                ScenarioState::PostFailure
-            }
-            ScenarioState::Running(i) if (i + 1) >= test_count && failure_count == 0 => {
-                ScenarioState::PostSuccess
-            }
-            ScenarioState::Running(i) => {
-                panic!(
-                    "Got into a weird state. Running({}), test_count: {}, failure_count: {}",
-                    i, test_count, failure_count
-                )
-            }
+            // Once no more tests can be run, we're done.
+            ScenarioState::Running if failure_count > 0 => ScenarioState::PostFailure,
+            ScenarioState::Running => ScenarioState::PostSuccess,
             ScenarioState::PostFailure => ScenarioState::ScenarioFinished,
             ScenarioState::PostSuccess => ScenarioState::ScenarioFinished,
             ScenarioState::ScenarioFinished => ScenarioState::ScenarioFinished,
@@ -854,54 +1795,138 @@ impl Scenario {
             // We can always enter the idle state.
             ScenarioState::Idle => true,
 
-            // Run an exec_start command before we run the first test.
-            ScenarioState::PreStart => self.description.exec_start.is_some(),
-
-            // Run a given test.
-            ScenarioState::Running(i) => {
-                let tests = &self.test_sequence;
-                let test = tests[i].borrow();
-                let test_name = test.id();
-                if self.scenario_timed_out() || i >= self.tests.len() {
-                    false
-                } else if let TestState::Fail(ref _x) = *self.exec_start_state.borrow() {
-                    // If the preroll command failed, then abort.
-                    false
-                } else if *self.test_states.get(test_name).unwrap().borrow() != TestState::Pending {
-                    // If the test isn't Pending (i.e. if it's skipped or failed), don't run it.
-                    false
-                }
-                // Make sure all required dependencies succeeded.
-                else if !self.all_dependencies_succeeded(test_name) {
-                    *self.test_states.get(test_name).unwrap().borrow_mut() = TestState::Skip;
-                    ctrl.send(ManagerControlMessage::new(
-                        self.id(),
-                        ManagerControlMessageContents::Skip(
-                            test_name.clone(),
-                            "dependency failed".to_owned(),
-                        ),
-                    ))
-                    .ok();
-                    false
-                } else {
-                    true
-                }
+            // Run exec_start commands before we run the first test.
+            ScenarioState::PreStart => self.queue_support_cmds(self.description.start_commands()),
+
+            // Run tests, if any of them can be run.
+            ScenarioState::Running => {
+                self.start_ready_tests(ctrl);
+                !self.running.borrow().is_empty()
             }
 
             // Run a script on scenario success.
-            ScenarioState::PostSuccess => self.description.exec_stop_success.is_some(),
+            ScenarioState::PostSuccess => {
+                self.queue_support_cmds(self.description.stop_commands(true))
+            }
 
             // Run a script on scenario failure.
-            ScenarioState::PostFailure => self.description.exec_stop_failure.is_some(),
+            ScenarioState::PostFailure => {
+                self.queue_support_cmds(self.description.stop_commands(false))
+            }
 
             // Presumably we can always finish a test.
             ScenarioState::ScenarioFinished => true,
         }
     }
 
+    /// Start every test that is ready to run, up to MaxParallel at once.
+    /// Tests are considered in order, and any whose requirements failed are skipped.
+    fn start_ready_tests(&self, ctrl: &Sender<ManagerControlMessage>) {
+        if self.scenario_timed_out() {
+            self.time_out(ctrl);
+            return;
+        }
+        // If the preroll command failed, then abort.
+        if let TestState::Fail(ref _x) = *self.exec_start_state.borrow() {
+            return;
+        }
+        if let Some(count) = self.description.stop_after_failure_count {
+            if *self.failures.borrow() >= count {
+                return;
+            }
+        }
+
+        for (step, test) in self.test_sequence.iter().enumerate() {
+            if self.running.borrow().len() >= self.description.max_parallel {
+                break;
+            }
+            let test = test.borrow();
+            let test_name = test.id();
+
+            // If the test isn't Pending (i.e. if it's skipped or failed), don't run it.
+            if *self.test_states.get(test_name).unwrap().borrow() != TestState::Pending
+                || self.running.borrow().contains(&step)
+                || !self.dependencies_finished(step)
+            {
+                continue;
+            }
+
+            // Make sure all required dependencies succeeded.
+            if !self.all_dependencies_succeeded(test_name) {
+                *self.test_states.get(test_name).unwrap().borrow_mut() = TestState::Skip;
+                ctrl.send(ManagerControlMessage::new(
+                    self.id(),
+                    ManagerControlMessageContents::Skip(
+                        test_name.clone(),
+                        "dependency failed".to_owned(),
+                    ),
+                ))
+                .ok();
+                continue;
+            }
+
+            if !self.can_run_alongside_others(&test) {
+                // Don't let later tests cut in front of one that has to run alone.
+                if test.is_exclusive() {
+                    break;
+                }
+                continue;
+            }
+
+            self.running.borrow_mut().push(step);
+            self.test_times
+                .borrow_mut()
+                .insert(test_name.clone(), (Instant::now(), None));
+            ctrl.send(ManagerControlMessage::new(
+                self.id(),
+                ManagerControlMessageContents::StartTest(test_name.clone()),
+            ))
+            .ok();
+        }
+    }
+
+    /// Returns true once every earlier test that the test at `step` requires
+    /// or suggests has finished, so that tests never overtake their dependencies.
+    fn dependencies_finished(&self, step: usize) -> bool {
+        let test = self.test_sequence[step].borrow();
+        let required = self.graph.required_parents_of_named(test.id());
+        let running = self.running.borrow();
+        self.test_sequence[..step]
+            .iter()
+            .enumerate()
+            .all(|(earlier_step, earlier)| {
+                let earlier = earlier.borrow();
+                let depended_on = required.contains(&earlier.id())
+                    || test
+                        .requirements()
+                        .iter()
+                        .chain(test.suggestions())
+                        .any(|name| name == earlier.id() || earlier.provides().contains(name));
+                !depended_on
+                    || (!running.contains(&earlier_step)
+                        && *self.test_states.get(earlier.id()).unwrap().borrow()
+                            != TestState::Pending)
+            })
+    }
+
+    /// Returns true if `test` can be started given the tests that are running now.
+    fn can_run_alongside_others(&self, test: &Test) -> bool {
+        let running = self.running.borrow();
+        if running.is_empty() {
+            return true;
+        }
+        if test.is_exclusive() {
+            return false;
+        }
+        running.iter().all(|&step| {
+            let other = self.test_sequence[step].borrow();
+            !other.is_exclusive() && !test.shares_resources_with(&other)
+        })
+    }
+
     fn all_dependencies_succeeded(&self, test_name: &UnitName) -> bool {
         for parent_name in self.graph.required_parents_of_named(test_name) {
-            if self.description.assumptions.contains(parent_name) {
+            if self.assumptions.contains(parent_name) {
                 return true;
             }
 
@@ -932,28 +1957,80 @@ impl Scenario {
         }
     }
 
-    fn make_timeout(&self, test_max_time: &Option<Duration>) -> Option<Duration> {
-        let now = Instant::now();
-        let scenario_elapsed_time = now.duration_since(self.start_time);
+    /// When the current run has to be finished by, if the scenario has a Timeout.
+    fn deadline(&self) -> Option<Instant> {
+        self.description
+            .timeout
+            .map(|timeout| self.start_time + timeout)
+    }
 
-        // If the test would take longer than the scenario has left, limit the test time.
-        if let Some(test_max_time) = *test_max_time {
-            if let Some(timeout) = self.description.timeout {
-                if (test_max_time + scenario_elapsed_time) > timeout {
-                    Some(timeout - scenario_elapsed_time)
-                } else {
-                    Some(test_max_time)
+    /// Limit a support command to the time the scenario has left.  Once the
+    /// scenario has timed out, ExecStopFail only has its own timeout, so that
+    /// it still gets a chance to clean up.
+    fn make_timeout(&self, max_time: &Option<Duration>) -> Option<Duration> {
+        if *self.timed_out.borrow() {
+            *max_time
+        } else {
+            limit_timeout(*max_time, self.deadline())
+        }
+    }
+
+    /// Count the run that just finished, and start another one after
+    /// RepeatDelay if the scenario hasn't finished repeating.
+    fn finish_iteration(&self, passed: bool, ctrl: &Sender<ManagerControlMessage>) {
+        let repeat = match self.description.repeat {
+            Some(ref repeat) => repeat,
+            None => return,
+        };
+        let mut iterations = self.iterations.borrow_mut();
+        if passed {
+            iterations.passed += 1;
+        } else {
+            iterations.failed += 1;
+        }
+        let again = !iterations.stopped
+            && match *repeat {
+                Repeat::Count(count) => iterations.number < count,
+                Repeat::UntilFailure(limit) => {
+                    passed && limit.is_none_or(|limit| iterations.number < limit)
                 }
-            } else {
-                Some(test_max_time)
+                Repeat::Duration(duration) => iterations.started.elapsed() < duration,
+            };
+        ctrl.send(ManagerControlMessage::new(
+            self.id(),
+            ManagerControlMessageContents::Iteration(Iteration {
+                number: iterations.number,
+                passed,
+                total_passed: iterations.passed,
+                total_failed: iterations.failed,
+                last: !again,
+            }),
+        ))
+        .ok();
+        if !again {
+            return;
+        }
+
+        let restart =
+            ManagerControlMessage::new(self.id(), ManagerControlMessageContents::RepeatScenario);
+        match self.description.repeat_delay {
+            None => {
+                ctrl.send(restart).ok();
+            }
+            Some(delay) => {
+                let thr_control = ctrl.clone();
+                thread::spawn(move || {
+                    thread::sleep(delay);
+                    thr_control.send(restart).ok();
+                });
             }
-        } else {
-            None
         }
     }
 
     // Post messages and terminate tests.
     fn finish_scenario(&self, ctrl: &Sender<ManagerControlMessage>) {
+        *self.finish_time.borrow_mut() = Some(Instant::now());
+        self.watchdog.borrow_mut().take();
         let failures = *self.failures.borrow();
         for test in &self.test_sequence {
             // Stop the test.  This will catch normal tests and daemons.
@@ -969,7 +2046,16 @@ impl Scenario {
             ManagerControlMessageContents::StopTest(self.id().clone()),
         ))
         .ok();
-        if failures > 0 {
+        if *self.timed_out.borrow() {
+            ctrl.send(ManagerControlMessage::new(
+                self.id(),
+                ManagerControlMessageContents::ScenarioFinished(
+                    failures + 500,
+                    "scenario timeout".to_owned(),
+                ),
+            ))
+            .ok();
+        } else if failures > 0 {
             ctrl.send(ManagerControlMessage::new(
                 self.id(),
                 ManagerControlMessageContents::ScenarioFinished(
@@ -985,6 +2071,57 @@ impl Scenario {
             ))
             .ok();
         }
+        self.finish_iteration(failures == 0, ctrl);
+    }
+
+    /// Describe the state of this scenario and each of its tests, so that
+    /// an interface that joins late can catch up.
+    pub fn status(&self) -> Vec<ManagerStatusMessage> {
+        let state = self.state.borrow().clone();
+        let now = Instant::now();
+
+        let elapsed = match state {
+            ScenarioState::Idle => None,
+            _ => Some(
+                self.finish_time
+                    .borrow()
+                    .unwrap_or(now)
+                    .duration_since(self.start_time),
+            ),
+        };
+        let mut messages = vec![ManagerStatusMessage::ScenarioStatus(
+            self.id().clone(),
+            state.as_str().to_owned(),
+            elapsed,
+            self.description.timeout,
+        )];
+
+        let test_times = self.test_times.borrow();
+        for (step, test) in self.test_sequence.iter().enumerate() {
+            let test = test.borrow();
+            let mut test_state = self.test_states.get(test.id()).unwrap().borrow().clone();
+
+            // Tests that are in progress are still marked Pending.
+            if test_state == TestState::Pending && self.running.borrow().contains(&step) {
+                test_state = TestState::Running;
+            }
+            let reason = match test_state {
+                TestState::Fail(ref reason) => reason.clone(),
+                _ => "".to_owned(),
+            };
+            let elapsed = test_times
+                .get(test.id())
+                .map(|&(start, end)| end.unwrap_or(now).duration_since(start));
+
+            messages.push(ManagerStatusMessage::TestStatus(
+                test.id().clone(),
+                test_state.as_str().to_owned(),
+                elapsed,
+                *test.timeout(),
+                reason,
+            ));
+        }
+        messages
     }
 
     // Determine if Scenario is running or idle
@@ -5,28 +5,35 @@ extern crate runny;
 extern crate systemd_parser;
 
 use std::cell::RefCell;
-use std::fs::File;
-use std::io::{BufRead, BufReader, Read};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Error, Read, Write};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::sync::mpsc::Sender;
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use self::dependy::Dependency;
 use self::humantime::{parse_duration, DurationError};
 use self::regex::Regex;
-use self::runny::running::{RunningOutput, RunningWaiter};
-use self::runny::Runny;
+use self::runny::running::{RunningInput, RunningOutput, RunningWaiter};
 use self::systemd_parser::items::DirectiveEntry;
 
-use config::Config;
+use config::{command_with_environment, Config};
 use unit::{
-    UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitDeselectError,
-    UnitIncompatibleReason, UnitName, UnitSelectError,
+    parse_bool, UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitDeselectError,
+    UnitEnvironment, UnitIncompatibleReason, UnitName, UnitSelectError,
 };
-use unitmanager::{ManagerControlMessage, ManagerControlMessageContents, UnitManager};
+use unitmanager::{ManagerControlMessage, ManagerControlMessageContents, Measurement, UnitManager};
+
+/// The result of a test that exited successfully, but reported a measurement
+/// that was out of limits.
+const MEASUREMENT_FAILED: i32 = -4;
+
+/// How long to wait for a program's output to be read once it has exited.
+pub const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
 
 #[derive(Debug, PartialEq, Clone)]
 enum TestType {
@@ -34,6 +41,86 @@ enum TestType {
     Daemon,
 }
 
+/// A kind of failure that a test may be retried after.
+#[derive(Debug, PartialEq, Clone)]
+enum RetryCondition {
+    /// The test exited with this return code.
+    ExitCode(i32),
+
+    /// The test ran for longer than its Timeout.
+    Timeout,
+}
+
+/// The range a measurement must fall within, from a Measure= directive.
+#[derive(Debug, Default, PartialEq, Clone)]
+struct MeasurementLimits {
+    low: Option<f64>,
+    high: Option<f64>,
+    units: Option<String>,
+}
+
+/// Checks the measurements that a test prints against its limits, and
+/// remembers the ones that failed.
+#[derive(Clone)]
+struct MeasurementChecker {
+    limits: Arc<HashMap<String, MeasurementLimits>>,
+    failures: Arc<Mutex<Vec<String>>>,
+}
+
+impl MeasurementChecker {
+    /// Check a measurement line of the form "[name] [value] [units]".  Units
+    /// are optional, but if both the test and its limits give them, they must match.
+    fn check(&self, line: &str) -> ManagerControlMessageContents {
+        let mut words = line.split_whitespace();
+        let name = words.next();
+        let value = words
+            .next()
+            .and_then(|v| v.parse::<f64>().ok())
+            .filter(|v| v.is_finite());
+        let units = words.collect::<Vec<_>>().join(" ");
+        let (name, value) = match (name, value) {
+            (Some(name), Some(value)) => (name.to_owned(), value),
+            _ => {
+                self.failures.lock().unwrap().push(line.to_owned());
+                return ManagerControlMessageContents::LogError(format!(
+                    "unable to parse measurement: {}",
+                    line
+                ));
+            }
+        };
+        let units = if units.is_empty() { None } else { Some(units) };
+
+        let limits = self.limits.get(&name).cloned().unwrap_or_default();
+        let passed = !matches!(limits.low, Some(low) if value < low)
+            && !matches!(limits.high, Some(high) if value > high)
+            && match (&units, &limits.units) {
+                (Some(reported), Some(expected)) => reported == expected,
+                _ => true,
+            };
+        if !passed {
+            self.failures.lock().unwrap().push(name.clone());
+        }
+        ManagerControlMessageContents::Measurement(Measurement {
+            name,
+            value,
+            units: units.or(limits.units),
+            low: limits.low,
+            high: limits.high,
+            passed,
+        })
+    }
+
+    /// A test that passed fails after all if any of its measurements didn't.
+    fn result(&self, result: i32, last_line: &Arc<Mutex<String>>) -> i32 {
+        let failures = self.failures.lock().unwrap();
+        if result != 0 || failures.is_empty() {
+            return result;
+        }
+        *last_line.lock().unwrap() = format!("measurement out of limits: {}", failures.join(", "));
+        MEASUREMENT_FAILED
+    }
+}
+
 /// A struct defining an in-memory representation of a .test file
 #[derive(Clone)]
 pub struct TestDescription {
@@ -90,6 +177,33 @@ pub struct TestDescription {
     /// working_directory: Directory to run progrms from, if any.
     working_directory: Option<PathBuf>,
 
+    /// How long to wait for an operator to answer a question before giving up.
+    prompt_timeout: Option<Duration>,
+
+    /// The answer to use when a question times out.
+    prompt_default: String,
+
+    /// How many more times to run the test if it fails.
+    retries: u32,
+
+    /// How long to wait before running the test again.
+    retry_delay: Option<Duration>,
+
+    /// Which failures to retry after.  If empty, every failure is retried.
+    retry_on: Vec<RetryCondition>,
+
+    /// Variables to add to the environment of the test's programs.
+    environment: UnitEnvironment,
+
+    /// The limits for each measurement the test reports, by name.
+    limits: HashMap<String, MeasurementLimits>,
+
+    /// Whether this test must run on its own, even in a parallel scenario.
+    exclusive: bool,
+
+    /// Shared things, such as a serial port, that only one test may use at a time.
+    resources: Vec<String>,
+
     /// The path to the unit file
     unit_directory: PathBuf,
 }
@@ -138,9 +252,21 @@ impl TestDescription {
             exec_stop_failure: None,
             exec_stop_success: None,
             working_directory: None,
+            prompt_timeout: None,
+            prompt_default: "".to_owned(),
+            retries: 0,
+            retry_delay: None,
+            retry_on: vec![],
+            environment: UnitEnvironment::default(),
+            limits: HashMap::new(),
+            exclusive: false,
+            resources: vec![],
             unit_directory: path.parent().unwrap().to_owned(),
         };
 
+        let mut exec_stop = None;
+        let mut exec_stop_timeout = None;
+
         for entry in unit_file.lookup_by_category("Test") {
             if let DirectiveEntry::Solo(directive) = entry {
                 match directive.key() {
@@ -229,19 +355,106 @@ impl TestDescription {
                             Some(s) => Some(Self::parse_time(s)?),
                         }
                     }
-                    "ExecStopFailure" => {
+                    "ExecStopFail" | "ExecStopFailure" => {
                         test_description.exec_stop_failure = directive.value().map(|s| s.to_owned())
                     }
-                    "ExecStopFailureTimeout" => {
+                    "ExecStopFailTimeout" | "ExecStopFailureTimeout" => {
                         test_description.exec_stop_failure_timeout = match directive.value() {
                             None => None,
                             Some(s) => Some(Self::parse_time(s)?),
                         }
                     }
-                    &_ => (),
+                    "ExecStop" => exec_stop = directive.value().map(|s| s.to_owned()),
+                    "ExecStopTimeout" => {
+                        exec_stop_timeout = match directive.value() {
+                            None => None,
+                            Some(s) => Some(Self::parse_time(s)?),
+                        }
+                    }
+                    "PromptTimeout" => {
+                        test_description.prompt_timeout = match directive.value() {
+                            None => None,
+                            Some(s) => Some(Self::parse_time(s)?),
+                        }
+                    }
+                    "PromptDefault" => {
+                        test_description.prompt_default = directive.value().unwrap_or("").to_owned()
+                    }
+                    "Retries" => {
+                        test_description.retries = match directive.value() {
+                            None => 0,
+                            Some(s) => s.parse::<u32>()?,
+                        }
+                    }
+                    "RetryDelay" => {
+                        test_description.retry_delay = match directive.value() {
+                            None => None,
+                            Some(s) => Some(Self::parse_time(s)?),
+                        }
+                    }
+                    "RetryOn" => {
+                        test_description.retry_on = match directive.value() {
+                            None => vec![],
+                            Some(s) => Self::parse_retry_on(s)?,
+                        }
+                    }
+                    "Measure" => {
+                        Self::parse_limit(&mut test_description.limits, directive.value())?
+                    }
+                    "Exclusive" => {
+                        test_description.exclusive =
+                            parse_bool("Test", "Exclusive", directive.value().unwrap_or(""))?
+                    }
+                    "Resources" => {
+                        test_description.resources = directive
+                            .value()
+                            .unwrap_or("")
+                            .split([',', ' '])
+                            .filter(|item| !item.is_empty())
+                            .map(|item| item.to_owned())
+                            .collect()
+                    }
+                    key => {
+                        test_description.environment.parse_directive(
+                            "Test",
+                            key,
+                            directive.value(),
+                        )?;
+                    }
                 }
+            } else if let DirectiveEntry::Many(directives) = entry {
+                for directive in directives {
+                    if directive.key() == "Measure" {
+                        Self::parse_limit(&mut test_description.limits, directive.value())?;
+                    } else {
+                        test_description.environment.parse_directive(
+                            "Test",
+                            directive.key(),
+                            directive.value(),
+                        )?;
+                    }
+                }
+            }
+        }
+        // ExecStop covers whichever of ExecStopSuccess and ExecStopFailure are missing.
+        if let Some(s) = exec_stop {
+            if test_description.exec_stop_failure.is_none() {
+                test_description.exec_stop_failure = Some(s.clone());
+            }
+            if test_description.exec_stop_success.is_none() {
+                test_description.exec_stop_success = Some(s);
             }
         }
+
+        if let Some(s) = exec_stop_timeout {
+            if test_description.exec_stop_failure_timeout.is_none() {
+                test_description.exec_stop_failure_timeout = Some(s);
+            }
+            if test_description.exec_stop_success_timeout.is_none() {
+                test_description.exec_stop_success_timeout = Some(s);
+            }
+        }
+
         if test_description.exec_start.is_empty() {
             return Err(UnitDescriptionError::MissingValue(
                 "Test".to_owned(),
@@ -251,6 +464,71 @@ impl TestDescription {
         Ok(test_description)
     }
 
+    /// Parse a "Measure=[name] Low=[number] High=[number] Units=[units]"
+    /// directive into the named measurement's limits.  Every field is optional.
+    fn parse_limit(
+        limits: &mut HashMap<String, MeasurementLimits>,
+        value: Option<&str>,
+    ) -> Result<(), UnitDescriptionError> {
+        let value = value.unwrap_or("");
+        let invalid = |item: &str| {
+            UnitDescriptionError::InvalidValue(
+                "Test".to_owned(),
+                "Measure".to_owned(),
+                item.to_owned(),
+                vec![
+                    "Low=[number]".to_owned(),
+                    "High=[number]".to_owned(),
+                    "Units=[units]".to_owned(),
+                ],
+            )
+        };
+        let mut words = value.split_whitespace();
+        let name = match words.next() {
+            Some(name) => name,
+            None => return Err(invalid(value)),
+        };
+        let mut measurement_limits = MeasurementLimits::default();
+        for item in words {
+            let number = |n: &str| match n.parse::<f64>() {
+                Ok(n) if n.is_finite() => Ok(n),
+                _ => Err(invalid(item)),
+            };
+            match item.split_once('=') {
+                Some(("Low", n)) => measurement_limits.low = Some(number(n)?),
+                Some(("High", n)) => measurement_limits.high = Some(number(n)?),
+                Some(("Units", units)) if !units.is_empty() => {
+                    measurement_limits.units = Some(units.to_owned())
+                }
+                _ => return Err(invalid(item)),
+            }
+        }
+        limits.insert(name.to_owned(), measurement_limits);
+        Ok(())
+    }
+
+    fn parse_retry_on(s: &str) -> Result<Vec<RetryCondition>, UnitDescriptionError> {
+        let mut conditions = vec![];
+        for item in s.split([',', ' ']).filter(|item| !item.is_empty()) {
+            if item.eq_ignore_ascii_case("timeout") {
+                conditions.push(RetryCondition::Timeout);
+            } else {
+                match item.parse::<i32>() {
+                    Ok(code) => conditions.push(RetryCondition::ExitCode(code)),
+                    Err(_) => {
+                        return Err(UnitDescriptionError::InvalidValue(
+                            "Test".to_owned(),
+                            "RetryOn".to_owned(),
+                            item.to_owned(),
+                            vec!["timeout".to_owned(), "[exit code]".to_owned()],
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(conditions)
+    }
+
     fn parse_time(time_str: &str) -> Result<Duration, DurationError> {
         if let Ok(val) = time_str.parse::<u64>() {
             Ok(Duration::from_secs(val))
@@ -277,11 +555,100 @@ impl TestDescription {
     }
 }
 
+/// Everything needed to run a test's ExecStopSuccess or ExecStopFailure
+/// from the thread that is waiting on the test.
+#[derive(Clone)]
+struct StopCommands {
+    success: Option<String>,
+    success_timeout: Option<Duration>,
+    failure: Option<String>,
+    failure_timeout: Option<Duration>,
+    directory: PathBuf,
+    environment: Vec<(String, String)>,
+}
+
+impl StopCommands {
+    /// Run the stop command for a test that passed or failed, and log its
+    /// output as the test's own.  Returns the command's result, or 0 if
+    /// there is no command to run.
+    fn run(&self, id: &UnitName, ctrl: &Sender<ManagerControlMessage>, passed: bool) -> i32 {
+        let (cmd, timeout, name) = if passed {
+            (&self.success, &self.success_timeout, "execstopsuccess")
+        } else {
+            (&self.failure, &self.failure_timeout, "execstopfailure")
+        };
+        let cmd = match *cmd {
+            Some(ref c) => c,
+            None => return 0,
+        };
+        let log = |contents| {
+            ctrl.send(ManagerControlMessage::new(id, contents)).ok();
+        };
+
+        log(ManagerControlMessageContents::Log(format!(
+            "{}: starting [{}]",
+            name, cmd
+        )));
+        let mut run_cmd = command_with_environment(cmd, &self.environment);
+        if let Some(timeout) = *timeout {
+            run_cmd.timeout(timeout);
+        }
+        run_cmd.directory(&Some(self.directory.clone()));
+        let mut running = match run_cmd.start() {
+            Ok(r) => r,
+            Err(e) => {
+                log(ManagerControlMessageContents::LogError(format!(
+                    "{}: unable to run command: {:?}",
+                    name, e
+                )));
+                return -3;
+            }
+        };
+
+        // Read both streams to the end, so that all output is logged before the result.
+        let stderr = running.take_error();
+        let thr_control = ctrl.clone();
+        let thr_id = id.clone();
+        let stderr_thread = thread::spawn(move || {
+            for line in BufReader::new(stderr).lines() {
+                let line = match line {
+                    Ok(l) => l,
+                    Err(_) => break,
+                };
+                thr_control
+                    .send(ManagerControlMessage::new(
+                        &thr_id,
+                        ManagerControlMessageContents::LogError(line),
+                    ))
+                    .ok();
+            }
+        });
+        for line in BufReader::new(running.take_output()).lines() {
+            match line {
+                Ok(l) => log(ManagerControlMessageContents::Log(l)),
+                Err(_) => break,
+            }
+        }
+        stderr_thread.join().ok();
+        running.wait().ok();
+
+        let result = running.result();
+        log(ManagerControlMessageContents::Log(format!(
+            "{}: finished [{}] with result {}",
+            name, cmd, result
+        )));
+        result
+    }
+}
+
 pub struct Test {
     description: TestDescription,
     program: Rc<RefCell<Option<RunningWaiter>>>,
+    stdin: RefCell<Option<RunningInput>>,
     result_arc: Arc<Mutex<Option<i32>>>,
     last_line: Arc<Mutex<String>>,
+    timed_out: Arc<Mutex<bool>>,
+    measurement_failures: Arc<Mutex<Vec<String>>>,
 }
 
 impl Test {
@@ -289,8 +656,11 @@ impl Test {
         Test {
             description: desc.clone(),
             program: Rc::new(RefCell::new(None)),
+            stdin: RefCell::new(None),
             result_arc: Arc::new(Mutex::new(None)),
             last_line: Arc::new(Mutex::new("".to_owned())),
+            timed_out: Arc::new(Mutex::new(false)),
+            measurement_failures: Arc::new(Mutex::new(vec![])),
         }
     }
 
@@ -341,6 +711,26 @@ impl Test {
         }
     }
 
+    /// Tell the manager that the test couldn't be run at all, so the
+    /// scenario can move on.
+    fn report_start_failure(id: &UnitName, ctrl: &Sender<ManagerControlMessage>, message: String) {
+        ctrl.send(ManagerControlMessage::new(
+            id,
+            ManagerControlMessageContents::LogError(message.clone()),
+        ))
+        .unwrap();
+        ctrl.send(ManagerControlMessage::new(
+            id,
+            ManagerControlMessageContents::TestFinished(-3, message),
+        ))
+        .ok();
+        ctrl.send(ManagerControlMessage::new(
+            id,
+            ManagerControlMessageContents::AdvanceScenario(-3),
+        ))
+        .ok();
+    }
+
     pub fn activate(
         &mut self,
         manager: &UnitManager,
@@ -351,6 +741,12 @@ impl Test {
         let id = self.id().clone();
 
         *self.result_arc.lock().unwrap() = None;
+        *self.timed_out.lock().unwrap() = false;
+        self.measurement_failures.lock().unwrap().clear();
+        let measurements = MeasurementChecker {
+            limits: Arc::new(self.description.limits.clone()),
+            failures: self.measurement_failures.clone(),
+        };
 
         // Announce to the world that we've started considering this test.
         ctrl.send(ManagerControlMessage::new(
@@ -360,57 +756,74 @@ impl Test {
         .ok();
 
         let cmd = &self.description.exec_start;
-        let timeout = &self.description.timeout;
+        // A test may not run past the end of its scenario's Timeout.
+        let timeout = config.limit_timeout(self.description.timeout);
 
-        let mut cmd = Runny::new(cmd);
-        if let Some(timeout) = *timeout {
-            cmd.timeout(timeout);
-        }
-        cmd.directory(&Some(config.working_directory(
+        let directory = config.working_directory(
             &self.description.unit_directory,
             &self.description.working_directory,
-        )));
+        );
+        let environment = match self
+            .description
+            .environment
+            .resolve(&self.description.unit_directory)
+        {
+            Ok(vars) => config.environment(&id, &self.description.unit_directory, &vars),
+            Err(e) => {
+                Self::report_start_failure(&id, &ctrl, format!("unable to start test: {}", e));
+                return Err(UnitActivateError::EnvironmentFailed(e));
+            }
+        };
+        let mut cmd = command_with_environment(cmd, &environment);
+        if let Some(timeout) = timeout {
+            cmd.timeout(timeout);
+        }
+        cmd.directory(&Some(directory.clone()));
         let mut running = match cmd.start() {
             Ok(r) => r,
             Err(e) => {
-                ctrl.send(ManagerControlMessage::new(
-                    &id,
-                    ManagerControlMessageContents::LogError(format!(
-                        "unable to start test: {:?}",
-                        e
-                    )),
-                ))
-                .unwrap();
-                ctrl.send(ManagerControlMessage::new(
-                    &id,
-                    ManagerControlMessageContents::TestFinished(
-                        -3,
-                        format!("unable to start test: {:?}", e),
-                    ),
-                ))
-                .ok();
-                ctrl.send(ManagerControlMessage::new(
-                    &id,
-                    ManagerControlMessageContents::AdvanceScenario(-3),
-                ))
-                .ok();
+                Self::report_start_failure(&id, &ctrl, format!("unable to start test: {:?}", e));
                 return Err(UnitActivateError::ExecFailed(e));
             }
         };
+        let stop_commands = StopCommands {
+            success: self.description.exec_stop_success.clone(),
+            success_timeout: self.description.exec_stop_success_timeout,
+            failure: self.description.exec_stop_failure.clone(),
+            failure_timeout: self.description.exec_stop_failure_timeout,
+            directory,
+            environment,
+        };
+
+        // Answers to any questions the test asks get written to its stdin.
+        *self.stdin.borrow_mut() = Some(running.take_input());
 
         // Keep track of the last line, which we can use to report test status.
         let last_line = self.last_line.clone();
 
+        // Keep a transcript of everything the test prints in the run directory.
+        let mut stdout_transcript = self.open_transcript(config, "stdout", &ctrl);
+        let stderr_transcript = self.open_transcript(config, "stderr", &ctrl);
+
         let waiter = running.waiter();
         let thr_control = ctrl.clone();
         let thr_last_line = self.last_line.clone();
         let thr_result_arc = self.result_arc.clone();
+        let thr_timed_out = self.timed_out.clone();
+        let thr_timeout = timeout;
+        let start_time = Instant::now();
         match self.description.test_type {
             TestType::Daemon => {
                 let daemon_ready_string = self.description.test_daemon_ready.clone();
 
                 thread::spawn(move || {
-                    Self::log_error(&id, &ctrl, running.take_error(), &last_line);
+                    Self::log_error(
+                        &id,
+                        &ctrl,
+                        running.take_error(),
+                        &last_line,
+                        stderr_transcript,
+                    );
                     let buf_reader = BufReader::new(running.take_output());
                     let buf_lines = buf_reader.lines();
                     let mut buf_iter = buf_lines;
@@ -435,6 +848,7 @@ impl Test {
                                         ))
                                         .ok();
                                     running.terminate(Some(Duration::from_secs(1))).ok();
+                                    stop_commands.run(&id, &thr_control, false);
                                     // thr_control.send(ManagerControlMessage::new(&id, ManagerControlMessageContents::TestFinished(-2, thr_last_line.lock().unwrap().clone()))).ok();
                                     Self::send_finished_once(
                                         &id,
@@ -446,6 +860,7 @@ impl Test {
                                     return;
                                 }
                                 Ok(line) => {
+                                    Self::transcribe(&mut stdout_transcript, &line);
                                     thr_control
                                         .send(ManagerControlMessage::new(
                                             &id,
@@ -476,6 +891,7 @@ impl Test {
                                 ))
                                 .ok();
                             running.terminate(Some(Duration::from_secs(1))).ok();
+                            stop_commands.run(&id, &thr_control, false);
                             Self::send_finished_once(
                                 &id,
                                 &thr_control,
@@ -491,14 +907,18 @@ impl Test {
                     let thr_thr_control = thr_control.clone();
                     let thr_thr_last_line = last_line.clone();
                     let thr_id = id.clone();
+                    let thr_measurements = measurements.clone();
+                    // The receiver is disconnected once all of stdout has been read.
+                    let (done, stdout_done) = channel();
                     thread::spawn(move || {
+                        let _done: Sender<()> = done;
                         for line in buf_iter {
                             let line = line.expect("Unable to get next line");
-                            *thr_thr_last_line.lock().unwrap() = line.clone();
+                            Self::transcribe(&mut stdout_transcript, &line);
                             if thr_thr_control
                                 .send(ManagerControlMessage::new(
                                     &thr_id,
-                                    ManagerControlMessageContents::Log(line),
+                                    Self::output_line(line, &thr_thr_last_line, &thr_measurements),
                                 ))
                                 .is_err()
                             {
@@ -515,10 +935,20 @@ impl Test {
                         ))
                         .ok();
                     running.wait().ok();
+
+                    // A daemon that was stopped at the end of the scenario has already passed.
+                    let result = match *thr_result_arc.lock().unwrap() {
+                        Some(result) => result,
+                        None => running.result(),
+                    };
+                    // Make sure every measurement has been seen before deciding the result.
+                    stdout_done.recv_timeout(OUTPUT_DRAIN_TIMEOUT).ok();
+                    let result = measurements.result(result, &thr_last_line);
+                    let stop_result = stop_commands.run(&id, &thr_control, result == 0);
                     Self::send_finished_once(
                         &id,
                         &thr_control,
-                        running.result(),
+                        Self::result_after_stop(result, stop_result, &thr_last_line),
                         &thr_result_arc,
                         &thr_last_line,
                     );
@@ -526,21 +956,55 @@ impl Test {
             }
             TestType::Simple => {
                 // Keep a waiter around in a separate thread to send that AdvanceScenario message upon completion.
-                Self::log_output(&id, &ctrl, running.take_output(), &last_line);
-                Self::log_error(&id, &ctrl, running.take_error(), &last_line);
+                let stdout_done = Self::log_output(
+                    &id,
+                    &ctrl,
+                    running.take_output(),
+                    &last_line,
+                    stdout_transcript,
+                    &measurements,
+                );
+                Self::log_error(
+                    &id,
+                    &ctrl,
+                    running.take_error(),
+                    &last_line,
+                    stderr_transcript,
+                );
                 thread::spawn(move || {
                     running.wait().ok();
+
+                    // Runny doesn't say why a process was killed, so check the clock.
+                    if let Some(timeout) = thr_timeout {
+                        if running.result() != 0 && start_time.elapsed() >= timeout {
+                            *thr_timed_out.lock().unwrap() = true;
+                            thr_control
+                                .send(ManagerControlMessage::new(
+                                    &id,
+                                    ManagerControlMessageContents::LogError(format!(
+                                        "test timed out after {:?}",
+                                        timeout
+                                    )),
+                                ))
+                                .ok();
+                        }
+                    }
+                    // Make sure every measurement has been seen before deciding the result.
+                    stdout_done.recv_timeout(OUTPUT_DRAIN_TIMEOUT).ok();
+                    let result = measurements.result(running.result(), &thr_last_line);
+                    let stop_result = stop_commands.run(&id, &thr_control, result == 0);
+                    let result = Self::result_after_stop(result, stop_result, &thr_last_line);
                     Self::send_finished_once(
                         &id,
                         &thr_control,
-                        running.result(),
+                        result,
                         &thr_result_arc,
                         &thr_last_line,
                     );
                     thr_control
                         .send(ManagerControlMessage::new(
                             &id,
-                            ManagerControlMessageContents::AdvanceScenario(running.result()),
+                            ManagerControlMessageContents::AdvanceScenario(result),
                         ))
                         .ok();
                 });
@@ -566,15 +1030,73 @@ impl Test {
             }
             running.terminate(&None);
         }
+        self.stdin.borrow_mut().take();
         Ok(())
     }
 
+    /// Write the operator's answer to a question to the test's stdin.
+    pub fn answer(&self, answer: &str) -> Result<(), Error> {
+        match *self.stdin.borrow_mut() {
+            Some(ref mut stdin) => writeln!(stdin, "{}", answer),
+            None => Err(Error::other("test is not running")),
+        }
+    }
+
+    pub fn prompt_timeout(&self) -> &Option<Duration> {
+        &self.description.prompt_timeout
+    }
+
+    pub fn prompt_default(&self) -> &String {
+        &self.description.prompt_default
+    }
+
+    /// Returns true if a test that has been run `attempts` times, and just
+    /// failed with `result`, should be run again.  Daemons are never retried.
+    pub fn should_retry(&self, attempts: u32, result: i32) -> bool {
+        if result == 0 || self.is_daemon() || attempts > self.description.retries {
+            return false;
+        }
+        if self.description.retry_on.is_empty() {
+            return true;
+        }
+        let timed_out = *self.timed_out.lock().unwrap();
+        self.description
+            .retry_on
+            .iter()
+            .any(|condition| match *condition {
+                RetryCondition::ExitCode(code) => code == result,
+                RetryCondition::Timeout => timed_out,
+            })
+    }
+
+    /// The total number of times this test may be run.
+    pub fn max_attempts(&self) -> u32 {
+        self.description.retries + 1
+    }
+
+    pub fn retry_delay(&self) -> &Option<Duration> {
+        &self.description.retry_delay
+    }
+
     /// is_daemon() can be used to determine if a test should be stopped
     /// now, or when the scenario is finished.
     pub fn is_daemon(&self) -> bool {
         self.description.test_type == TestType::Daemon
     }
 
+    /// Exclusive tests never run at the same time as another test.
+    pub fn is_exclusive(&self) -> bool {
+        self.description.exclusive
+    }
+
+    /// Returns true if this test and `other` need some of the same resources.
+    pub fn shares_resources_with(&self, other: &Test) -> bool {
+        self.description
+            .resources
+            .iter()
+            .any(|r| other.description.resources.contains(r))
+    }
+
     pub fn id(&self) -> &UnitName {
         &self.description.id
     }
@@ -591,23 +1113,102 @@ impl Test {
         &self.description.timeout
     }
 
+    /// A test that passed fails after all if its ExecStopSuccess fails.
+    fn result_after_stop(result: i32, stop_result: i32, last_line: &Arc<Mutex<String>>) -> i32 {
+        if result != 0 || stop_result == 0 {
+            return result;
+        }
+        *last_line.lock().unwrap() = format!("ExecStopSuccess failed with result {}", stop_result);
+        stop_result
+    }
+
+    /// Open the file that this test's stdout or stderr is copied to, if
+    /// there is a run directory to put it in.  Tests that run more than
+    /// once in a run append to the same transcript.
+    fn open_transcript(
+        &self,
+        config: &Config,
+        stream: &str,
+        ctrl: &Sender<ManagerControlMessage>,
+    ) -> Option<File> {
+        let path = config
+            .run_directory()?
+            .join(format!("{}.{}", self.id().id(), stream));
+        match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(f) => Some(f),
+            Err(e) => {
+                ctrl.send(ManagerControlMessage::new(
+                    self.id(),
+                    ManagerControlMessageContents::LogError(format!(
+                        "unable to open transcript {}: {}",
+                        path.display(),
+                        e
+                    )),
+                ))
+                .ok();
+                None
+            }
+        }
+    }
+
+    fn transcribe(transcript: &mut Option<File>, line: &str) {
+        if let Some(ref mut file) = *transcript {
+            writeln!(file, "{}", line).ok();
+        }
+    }
+
+    /// Turn a line printed by a test into a message for the manager.
+    /// Lines beginning with "ASK " are questions for the operator, lines
+    /// beginning with "MEASURE " are measurements, "SET [key] [value]" sets a
+    /// variable for the rest of the run, and everything else is logged and
+    /// remembered as the last line.
+    fn output_line(
+        line: String,
+        last_line: &Arc<Mutex<String>>,
+        measurements: &MeasurementChecker,
+    ) -> ManagerControlMessageContents {
+        if let Some(question) = line.strip_prefix("ASK ") {
+            return ManagerControlMessageContents::Ask(question.to_owned());
+        }
+        if let Some(measurement) = line.strip_prefix("MEASURE ") {
+            return measurements.check(measurement);
+        }
+        if let Some(variable) = line.strip_prefix("SET ") {
+            let variable = variable.trim_start();
+            return match variable.split_once(char::is_whitespace) {
+                Some((key, value)) => {
+                    ManagerControlMessageContents::Set(key.to_owned(), value.trim().to_owned())
+                }
+                None => ManagerControlMessageContents::Set(variable.to_owned(), "".to_owned()),
+            };
+        }
+        *last_line.lock().unwrap() = line.clone();
+        ManagerControlMessageContents::Log(line)
+    }
+
     fn log_output(
         id: &UnitName,
         control: &Sender<ManagerControlMessage>,
         stdout: RunningOutput,
         last_line: &Arc<Mutex<String>>,
-    ) {
+        mut transcript: Option<File>,
+        measurements: &MeasurementChecker,
+    ) -> Receiver<()> {
         let thr_control = control.clone();
         let thr_last_line = last_line.clone();
         let thr_id = id.clone();
+        let thr_measurements = measurements.clone();
+        // The receiver is disconnected once all of stdout has been read.
+        let (done, stdout_done) = channel();
         thread::spawn(move || {
+            let _done: Sender<()> = done;
             for line in BufReader::new(stdout).lines() {
                 let line = line.expect("Unable to get next line");
-                *thr_last_line.lock().unwrap() = line.clone();
+                Self::transcribe(&mut transcript, &line);
                 if thr_control
                     .send(ManagerControlMessage::new(
                         &thr_id,
-                        ManagerControlMessageContents::Log(line),
+                        Self::output_line(line, &thr_last_line, &thr_measurements),
                     ))
                     .is_err()
                 {
@@ -615,6 +1216,7 @@ impl Test {
                 }
             }
         });
+        stdout_done
     }
 
     fn log_error(
@@ -622,6 +1224,7 @@ impl Test {
         control: &Sender<ManagerControlMessage>,
         stderr: RunningOutput,
         last_line: &Arc<Mutex<String>>,
+        mut transcript: Option<File>,
     ) {
         let thr_control = control.clone();
         let thr_last_line = last_line.clone();
@@ -629,6 +1232,7 @@ impl Test {
         thread::spawn(move || {
             for line in BufReader::new(stderr).lines() {
                 let line = line.expect("Unable to get next line");
+                Self::transcribe(&mut transcript, &line);
                 *thr_last_line.lock().unwrap() = line.clone();
                 if thr_control
                     .send(ManagerControlMessage::new(
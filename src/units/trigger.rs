@@ -1,22 +1,27 @@
 extern crate runny;
+extern crate serde_json;
 extern crate systemd_parser;
 
 use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Read};
+use std::io::{BufRead, BufReader, Error, Read, Write};
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::Sender;
 use std::thread;
 
-use config::Config;
+use config::{command_with_environment, Config};
 use unit::{
-    UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitDeselectError,
-    UnitIncompatibleReason, UnitName, UnitSelectError,
+    RestartPolicy, UnitActivateError, UnitDeactivateError, UnitDescriptionError, UnitDeselectError,
+    UnitEnvironment, UnitIncompatibleReason, UnitName, UnitSelectError,
+};
+use unitmanager::{
+    ManagerControlMessage, ManagerControlMessageContents, ManagerStatusMessage, RunMetadata,
+    UnitManager,
 };
-use unitmanager::{ManagerControlMessage, ManagerControlMessageContents, UnitManager};
 
 use self::runny::running::{Running, RunningOutput};
-use self::runny::Runny;
+use self::serde_json::Value;
 use self::systemd_parser::items::DirectiveEntry;
 
 #[derive(Clone, Copy)]
@@ -25,6 +30,27 @@ enum TriggerFormat {
     Json,
 }
 
+/// Requests that may be sent by a JSON-formatted trigger, one object per line.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum JsonRequest {
+    Hello {
+        version: Option<String>,
+    },
+    Start {
+        scenario: Option<String>,
+        #[serde(default)]
+        metadata: BTreeMap<String, Value>,
+    },
+    Stop,
+    Log {
+        message: String,
+    },
+    Pong {
+        id: u32,
+    },
+}
+
 /// A struct defining an in-memory representation of a .Trigger file
 #[derive(Clone)]
 pub struct TriggerDescription {
@@ -51,6 +77,12 @@ pub struct TriggerDescription {
 
     /// The path to the unit file
     unit_directory: PathBuf,
+
+    /// When and how often to restart the trigger if it exits
+    restart: RestartPolicy,
+
+    /// Variables to add to the program's environment
+    environment: UnitEnvironment,
 }
 
 impl TriggerDescription {
@@ -75,6 +107,8 @@ impl TriggerDescription {
             exec_start: "".to_owned(),
             working_directory: None,
             unit_directory: path.parent().unwrap().to_owned(),
+            restart: RestartPolicy::default(),
+            environment: UnitEnvironment::default(),
         };
 
         for entry in unit_file.lookup_by_category("Trigger") {
@@ -126,7 +160,27 @@ impl TriggerDescription {
                             },
                         }
                     }
-                    &_ => (),
+                    key => {
+                        if !interface_description.environment.parse_directive(
+                            "Trigger",
+                            key,
+                            directive.value(),
+                        )? {
+                            interface_description.restart.parse_directive(
+                                "Trigger",
+                                key,
+                                directive.value(),
+                            )?;
+                        }
+                    }
+                }
+            } else if let DirectiveEntry::Many(ref directives) = entry {
+                for directive in directives {
+                    interface_description.environment.parse_directive(
+                        "Trigger",
+                        directive.key(),
+                        directive.value(),
+                    )?;
                 }
             }
         }
@@ -187,6 +241,10 @@ impl Trigger {
         &self.description.id
     }
 
+    pub fn restart_policy(&self) -> &RestartPolicy {
+        &self.description.restart
+    }
+
     pub fn select(&self) -> Result<(), UnitSelectError> {
         Ok(())
     }
@@ -200,31 +258,42 @@ impl Trigger {
         manager: &UnitManager,
         config: &Config,
     ) -> Result<(), UnitActivateError> {
-        let mut running = Runny::new(self.description.exec_start.as_str())
-            .directory(&Some(config.working_directory(
-                &self.description.unit_directory,
-                &self.description.working_directory,
-            )))
-            .start()?;
+        let vars = self
+            .description
+            .environment
+            .resolve(&self.description.unit_directory)
+            .map_err(UnitActivateError::EnvironmentFailed)?;
+        let mut running = command_with_environment(
+            self.description.exec_start.as_str(),
+            &config.environment(self.id(), &self.description.unit_directory, &vars),
+        )
+        .directory(&Some(config.working_directory(
+            &self.description.unit_directory,
+            &self.description.working_directory,
+        )))
+        .start()?;
 
         let stdout = running.take_output();
         let stderr = running.take_error();
 
         let control_sender = manager.get_control_channel();
         let control_sender_id = self.id().clone();
+
+        // Pass control to an out-of-object thread, and shuttle communications
+        // from stdout onto the control_sender channel.
+        let thr_sender_id = control_sender_id.clone();
+        let thr_sender = control_sender.clone();
         match self.description.format {
             TriggerFormat::Text => {
-                // Pass control to an out-of-object thread, and shuttle communications
-                // from stdout onto the control_sender channel.
-                let thr_sender_id = control_sender_id.clone();
-                let thr_sender = control_sender.clone();
                 thread::spawn(move || Self::text_read(thr_sender_id, thr_sender, stdout));
-                let thr_sender_id = control_sender_id.clone();
-                let thr_sender = control_sender.clone();
-                thread::spawn(move || Self::text_read_stderr(thr_sender_id, thr_sender, stderr));
             }
-            TriggerFormat::Json => {}
+            TriggerFormat::Json => {
+                thread::spawn(move || Self::json_read(thr_sender_id, thr_sender, stdout));
+            }
         };
+        let thr_sender_id = control_sender_id.clone();
+        let thr_sender = control_sender.clone();
+        thread::spawn(move || Self::text_read_stderr(thr_sender_id, thr_sender, stderr));
 
         *self.process.borrow_mut() = Some(running);
 
@@ -253,6 +322,25 @@ impl Trigger {
         }
     }
 
+    /// Write a ManagerStatusMessage to the trigger.  Triggers only care about PINGs.
+    pub fn output_message(&self, msg: ManagerStatusMessage) -> Result<(), Error> {
+        let mut process_opt = self.process.borrow_mut();
+
+        if process_opt.is_none() {
+            return Err(Error::other("no process running"));
+        }
+
+        let process = process_opt.as_mut().unwrap();
+
+        match msg {
+            ManagerStatusMessage::Ping(id) => match self.description.format {
+                TriggerFormat::Text => writeln!(process, "PING {}", id),
+                TriggerFormat::Json => writeln!(process, "{}", msg.to_json()),
+            },
+            _ => Ok(()),
+        }
+    }
+
     fn cfti_unescape(msg: String) -> String {
         msg.replace("\\t", "\t")
             .replace("\\n", "\n")
@@ -280,8 +368,8 @@ impl Trigger {
         }
     }
 
-    fn text_read(id: UnitName, control: Sender<ManagerControlMessage>, stdout: RunningOutput) {
-        for line in BufReader::new(stdout).lines() {
+    pub fn text_read<R: Read>(id: UnitName, control: Sender<ManagerControlMessage>, input: R) {
+        for line in BufReader::new(input).lines() {
             let line = line.expect("Unable to get next line");
             let mut words: Vec<String> = line
                 .split_whitespace()
@@ -296,13 +384,15 @@ impl Trigger {
                 words.remove(0);
 
                 match verb.as_str() {
-                    "stop" => ManagerControlMessageContents::Unimplemented(
-                        "stop".to_owned(),
-                        "Unable to stop tests".to_owned(),
-                    ),
+                    "stop" => ManagerControlMessageContents::AbortTests,
+                    "log" => ManagerControlMessageContents::Log(words.join(" ")),
+                    "hello" => ManagerControlMessageContents::Log(format!(
+                        "trigger identifies itself as {}",
+                        words.join(" ")
+                    )),
                     "start" => {
                         if !words.is_empty() {
-                            match UnitName::from_str(&words[0], "test") {
+                            match UnitName::from_str(&words[0].to_lowercase(), "scenario") {
                                 Ok(name) => {
                                     ManagerControlMessageContents::StartScenario(Some(name))
                                 }
@@ -315,6 +405,7 @@ impl Trigger {
                             ManagerControlMessageContents::StartScenario(None)
                         }
                     }
+                    "pong" => ManagerControlMessageContents::Pong(words.join(" ")),
                     v => {
                         ManagerControlMessageContents::Unimplemented(v.to_owned(), words.join(" "))
                     }
@@ -333,4 +424,73 @@ impl Trigger {
             ))
             .expect("interface couldn't send exit message to controller");
     }
+
+    /// Convert a decoded JSON request into a message for the manager.
+    fn json_request(request: JsonRequest) -> ManagerControlMessageContents {
+        match request {
+            JsonRequest::Hello { version } => ManagerControlMessageContents::Log(format!(
+                "trigger identifies itself as {}",
+                version.unwrap_or_default()
+            )),
+            JsonRequest::Start { scenario, metadata } => {
+                let scenario = match scenario {
+                    None => None,
+                    Some(s) => match UnitName::from_str(&s.to_lowercase(), "scenario") {
+                        Ok(name) => Some(name),
+                        Err(e) => {
+                            return ManagerControlMessageContents::Error(format!(
+                                "Invalid scenario name: {}",
+                                e
+                            ))
+                        }
+                    },
+                };
+
+                // Metadata is kept as strings, so non-string values are stored as JSON.
+                let metadata: RunMetadata = metadata
+                    .into_iter()
+                    .map(|(key, value)| match value {
+                        Value::String(s) => (key, s),
+                        other => (key, other.to_string()),
+                    })
+                    .collect();
+                ManagerControlMessageContents::StartScenarioWithMetadata(scenario, metadata)
+            }
+            JsonRequest::Stop => ManagerControlMessageContents::AbortTests,
+            JsonRequest::Log { message } => ManagerControlMessageContents::Log(message),
+            JsonRequest::Pong { id } => ManagerControlMessageContents::Pong(id.to_string()),
+        }
+    }
+
+    pub fn json_read<R: Read>(id: UnitName, control: Sender<ManagerControlMessage>, input: R) {
+        for line in BufReader::new(input).lines() {
+            let line = match line {
+                Ok(l) => l,
+                Err(_) => break,
+            };
+
+            // Don't crash if we get a blank line.
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let msg = match serde_json::from_str::<JsonRequest>(&line) {
+                Ok(request) => Self::json_request(request),
+                Err(e) => {
+                    ManagerControlMessageContents::Error(format!("Invalid JSON request: {}", e))
+                }
+            };
+
+            // If the send fails, that means the other end has closed the pipe.
+            if control.send(ManagerControlMessage::new(&id, msg)).is_err() {
+                break;
+            }
+        }
+        control
+            .send(ManagerControlMessage::new(
+                &id,
+                ManagerControlMessageContents::ChildExited,
+            ))
+            .expect("trigger couldn't send exit message to controller");
+    }
 }